@@ -29,7 +29,7 @@ customCommands:
       - type: menuFromCommand
         title: AI Commit Messages
         key: Msg
-        command: 'rs-git-msg -n 5 {{if .Form.Instructions}}-i "{{.Form.Instructions}}"{{end}}'
+        command: 'rs-git-msg -n 5 --pipe-behavior all {{if .Form.Instructions}}-i "{{.Form.Instructions}}"{{end}}'
     command: git commit -m "{{.Form.Msg}}"
     context: 'files'
     description: 'Generate commit message using rs-git-msg'
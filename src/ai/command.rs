@@ -0,0 +1,154 @@
+use crate::error::Result;
+use anyhow::Context;
+use async_trait::async_trait;
+use std::process::{Output, Stdio};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use super::{AiProvider, ProviderCapabilities};
+use crate::error::Error;
+
+/// Provider that runs a user-supplied shell command, writing the prompt to
+/// its stdin and reading the response from its stdout
+#[derive(Debug)]
+pub struct CommandProvider {
+    command: String,
+    verbose: bool,
+}
+
+impl CommandProvider {
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        streaming: false,
+        n: false,
+        seed: false,
+        system_prompt: false,
+        temperature: false,
+        usage: false,
+    };
+
+    pub fn new(command: &str, verbose: bool) -> Self {
+        Self {
+            command: command.to_string(),
+            verbose,
+        }
+    }
+
+    async fn run(&self, prompt: &str) -> Result<String> {
+        if self.verbose {
+            eprintln!("Running provider command: {}", self.command);
+        }
+
+        let output = pipe_to_command(&self.command, prompt)
+            .await
+            .with_context(|| format!("Failed to run provider command `{}`", self.command))?;
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(Error::api(
+                "Command",
+                format!("`{}` {}: {}", self.command, output.status, stderr.trim()),
+            ));
+        }
+
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+}
+
+/// Runs a shell command with `input` on its stdin and collects its output,
+/// whatever its exit status
+pub async fn pipe_to_command(command: &str, input: &str) -> Result<Output> {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+
+    let mut child = shell
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()?;
+
+    // Write from a separate task so a command that answers before reading
+    // all of its input can't deadlock against a full pipe
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let input = input.to_string();
+    let writer = tokio::spawn(async move {
+        // The command may exit without reading everything; that's its call
+        let _ = stdin.write_all(input.as_bytes()).await;
+    });
+
+    let output = child.wait_with_output().await?;
+    let _ = writer.await;
+    Ok(output)
+}
+
+#[async_trait]
+impl AiProvider for CommandProvider {
+    async fn generate_text(&self, prompt: &str) -> Result<String> {
+        self.run(prompt).await
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_command_echoes_prompt() {
+        let provider = CommandProvider::new("cat", false);
+        let response = provider.generate_text("feat: echo me").await.unwrap();
+        assert_eq!(response, "feat: echo me");
+    }
+
+    #[tokio::test]
+    async fn test_command_script() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let script = dir.path().join("provider.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nwc -l >/dev/null\necho 'fix(core): handle empty input'\n",
+        )
+        .unwrap();
+
+        let provider = CommandProvider::new(&format!("sh {}", script.display()), false);
+        let response = provider
+            .generate_text("line one\nline two\n")
+            .await
+            .unwrap();
+        assert_eq!(response.trim(), "fix(core): handle empty input");
+    }
+
+    #[tokio::test]
+    async fn test_command_failure_is_error() {
+        let provider = CommandProvider::new("echo 'model not found' >&2; exit 3", false);
+        let err = provider.generate_text("prompt").await.unwrap_err();
+
+        assert!(matches!(
+            &err,
+            Error::ProviderApi { provider, .. } if provider == "Command"
+        ));
+        let message = err.to_string();
+        assert!(message.contains("exit status: 3"));
+        assert!(message.ends_with("model not found"));
+    }
+
+    #[tokio::test]
+    async fn test_command_ignoring_stdin() {
+        // Large prompts must not block a command that never reads them
+        let provider = CommandProvider::new("echo 'chore: ignore input'", false);
+        let prompt = "x".repeat(1 << 20);
+        let response = provider.generate_text(&prompt).await.unwrap();
+        assert_eq!(response.trim(), "chore: ignore input");
+    }
+}
@@ -0,0 +1,217 @@
+use crate::error::Result;
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::tokenizer::Tokenizer;
+use super::{AiProvider, ProviderCapabilities, RateLimit, Usage};
+use crate::error::Error;
+
+/// Tries a chain of providers in order, moving on to the next one when a
+/// provider can't be reached or rejects its credentials. Once a provider
+/// answers, later requests go straight to it.
+#[derive(Debug)]
+pub struct FallbackProvider {
+    providers: Vec<FallbackEntry>,
+    current: AtomicUsize,
+}
+
+/// One provider in the chain, with the names reported for it
+#[derive(Debug)]
+pub struct FallbackEntry {
+    pub provider: String,
+    pub model: String,
+    pub ai_provider: Box<dyn AiProvider>,
+}
+
+impl FallbackEntry {
+    fn label(&self) -> String {
+        format!("{} ({})", self.provider, self.model)
+    }
+}
+
+impl FallbackProvider {
+    /// Takes the providers in the order to try them
+    pub fn new(providers: Vec<FallbackEntry>) -> Self {
+        assert!(!providers.is_empty(), "FallbackProvider needs a provider");
+        Self {
+            providers,
+            current: AtomicUsize::new(0),
+        }
+    }
+
+    fn current(&self) -> &FallbackEntry {
+        &self.providers[self.current.load(Ordering::SeqCst)]
+    }
+
+    async fn try_each(&self, prompt: &str, temperature: Option<f32>) -> Result<String> {
+        let mut index = self.current.load(Ordering::SeqCst);
+        loop {
+            let entry = &self.providers[index];
+            let result = match temperature {
+                Some(temperature) => {
+                    entry
+                        .ai_provider
+                        .generate_text_with_temperature(prompt, temperature)
+                        .await
+                }
+                None => entry.ai_provider.generate_text(prompt).await,
+            };
+
+            let Err(e) = result else {
+                self.current.store(index, Ordering::SeqCst);
+                return result;
+            };
+            let Some(next) = self.providers.get(index + 1) else {
+                return Err(e);
+            };
+            if !is_provider_outage(&e) {
+                return Err(e);
+            }
+
+            eprintln!(
+                "Warning: {} failed ({e}), falling back to {}",
+                entry.label(),
+                next.label()
+            );
+            index += 1;
+        }
+    }
+}
+
+/// Errors another provider might not have: the service is down, or the
+/// credentials for it are missing or rejected
+fn is_provider_outage(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::ProviderUnreachable { .. } | Error::ProviderAuth { .. }
+    )
+}
+
+#[async_trait]
+impl AiProvider for FallbackProvider {
+    async fn generate_text(&self, prompt: &str) -> Result<String> {
+        self.try_each(prompt, None).await
+    }
+
+    async fn generate_text_with_temperature(
+        &self,
+        prompt: &str,
+        temperature: f32,
+    ) -> Result<String> {
+        self.try_each(prompt, Some(temperature)).await
+    }
+
+    fn tokenizer(&self) -> Box<dyn Tokenizer> {
+        self.current().ai_provider.tokenizer()
+    }
+
+    async fn preload(&self) -> Result<()> {
+        self.current().ai_provider.preload().await
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        self.current().ai_provider.usage()
+    }
+
+    fn answered_by(&self) -> Option<(String, String)> {
+        let current = self.current();
+        Some((current.provider.clone(), current.model.clone()))
+    }
+
+    fn rate_limit(&self) -> Option<RateLimit> {
+        self.current().ai_provider.rate_limit()
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        self.current().ai_provider.capabilities()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::mock::MockProvider;
+
+    fn chain(providers: Vec<MockProvider>) -> FallbackProvider {
+        FallbackProvider::new(
+            providers
+                .into_iter()
+                .enumerate()
+                .map(|(i, provider)| FallbackEntry {
+                    provider: format!("mock{}", i + 1),
+                    model: "test-model".to_string(),
+                    ai_provider: Box::new(provider),
+                })
+                .collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_falls_back_when_first_provider_fails() {
+        let first = MockProvider::new_with_auth_error("API key is required for Mock");
+        let second = MockProvider::new("feat: add login");
+        let (first_calls, second_calls) = (first.calls.clone(), second.calls.clone());
+        let provider = chain(vec![first, second]);
+
+        assert_eq!(
+            provider.answered_by(),
+            Some(("mock1".to_string(), "test-model".to_string()))
+        );
+        assert_eq!(
+            provider.generate_text("prompt").await.unwrap(),
+            "feat: add login"
+        );
+        assert_eq!(first_calls.lock().unwrap().len(), 1);
+        assert_eq!(second_calls.lock().unwrap().len(), 1);
+        assert_eq!(
+            provider.answered_by(),
+            Some(("mock2".to_string(), "test-model".to_string()))
+        );
+
+        // The provider that answered is used from then on
+        provider.generate_text("prompt").await.unwrap();
+        assert_eq!(first_calls.lock().unwrap().len(), 1);
+        assert_eq!(second_calls.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_first_provider_answering_is_kept() {
+        let first = MockProvider::new("fix: close pool");
+        let second = MockProvider::new("feat: add login");
+        let second_calls = second.calls.clone();
+        let provider = chain(vec![first, second]);
+
+        assert_eq!(
+            provider
+                .generate_text_with_temperature("prompt", 1.1)
+                .await
+                .unwrap(),
+            "fix: close pool"
+        );
+        assert!(second_calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_other_errors_do_not_fall_back() {
+        let first = MockProvider::new_with_error("model not found");
+        let second = MockProvider::new("feat: add login");
+        let second_calls = second.calls.clone();
+        let provider = chain(vec![first, second]);
+
+        let err = provider.generate_text("prompt").await.unwrap_err();
+        assert_eq!(err.to_string(), "model not found");
+        assert!(second_calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_last_error_when_every_provider_fails() {
+        let provider = chain(vec![
+            MockProvider::new_with_auth_error("first key rejected"),
+            MockProvider::new_with_auth_error("second key rejected"),
+        ]);
+
+        let err = provider.generate_text("prompt").await.unwrap_err();
+        assert_eq!(err.to_string(), "second key rejected");
+        assert!(is_provider_outage(&err));
+    }
+}
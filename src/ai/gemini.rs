@@ -1,14 +1,16 @@
-use anyhow::{Result, anyhow};
+use crate::error::Result;
 use async_trait::async_trait;
 use reqwest::Client;
 use serde::Serialize;
 use serde_json::Value;
+use std::sync::Arc;
 
-use super::AiProvider;
+use super::{AiProvider, ProviderCapabilities};
+use crate::error::Error;
 
 #[derive(Debug)]
 pub struct GeminiProvider {
-    client: Client,
+    client: Arc<Client>,
     base_url: String,
     model: String,
     api_key: String,
@@ -18,6 +20,13 @@ pub struct GeminiProvider {
 #[derive(Serialize)]
 struct GeminiRequest {
     contents: Vec<Content>,
+    #[serde(rename = "generationConfig", skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GenerationConfig>,
+}
+
+#[derive(Serialize)]
+struct GenerationConfig {
+    temperature: f32,
 }
 
 #[derive(Serialize)]
@@ -30,23 +39,60 @@ struct Part {
     text: String,
 }
 
+/// Extracts the generated text from a Gemini response body
+fn parse_response(text: &str) -> Result<String> {
+    let json: Value = serde_json::from_str(text).map_err(|e| Error::parse("Gemini", Some(e)))?;
+
+    if let Some(text) = json
+        .get("candidates")
+        .and_then(Value::as_array)
+        .and_then(|candidates| candidates.first())
+        .and_then(|candidate| candidate.get("content"))
+        .and_then(|content| content.get("parts"))
+        .and_then(Value::as_array)
+        .and_then(|parts| parts.first())
+        .and_then(|part| part.get("text"))
+        .and_then(Value::as_str)
+    {
+        return Ok(text.to_string());
+    }
+
+    if let Some(error) = json.get("error") {
+        return Err(Error::api("Gemini", error.to_string()));
+    }
+
+    Err(Error::parse("Gemini", None))
+}
+
 impl GeminiProvider {
-    pub fn new(base_url: &str, model: &str, api_key: &str, verbose: bool) -> Self {
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        streaming: false,
+        n: false,
+        seed: false,
+        system_prompt: false,
+        temperature: true,
+        usage: false,
+    };
+
+    pub fn new(
+        client: Arc<Client>,
+        base_url: &str,
+        model: &str,
+        api_key: &str,
+        verbose: bool,
+    ) -> Self {
         Self {
-            client: Client::new(),
+            client,
             base_url: base_url.to_string(),
             model: model.to_string(),
             api_key: api_key.to_string(),
             verbose,
         }
     }
-}
 
-#[async_trait]
-impl AiProvider for GeminiProvider {
-    async fn generate_text(&self, prompt: &str) -> Result<String> {
+    async fn send_request(&self, prompt: &str, temperature: Option<f32>) -> Result<String> {
         if self.verbose {
-            println!("Sending request to Gemini API...");
+            eprintln!("Sending request to Gemini API...");
         }
 
         let request = GeminiRequest {
@@ -55,6 +101,7 @@ impl AiProvider for GeminiProvider {
                     text: prompt.to_string(),
                 }],
             }],
+            generation_config: temperature.map(|temperature| GenerationConfig { temperature }),
         };
 
         let url = format!(
@@ -70,39 +117,46 @@ impl AiProvider for GeminiProvider {
             .await
             .map_err(|e| {
                 eprintln!("Failed to connect to Gemini API: {e}");
-                anyhow!("Connection to Gemini failed: {}", e)
+                Error::unreachable("Gemini", e)
             })?;
 
+        let status = response.status();
         if self.verbose {
-            println!("Gemini API response status: {}", response.status());
+            eprintln!("Gemini API response status: {status}");
         }
 
-        let text = response.text().await?;
+        let text = response
+            .text()
+            .await
+            .map_err(|e| Error::unreachable("Gemini", e))?;
         if self.verbose {
-            println!("Raw response: {text}");
+            eprintln!("Raw response: {text}");
         }
 
-        let json: Value = serde_json::from_str(&text)?;
-
-        if let Some(candidates) = json.get("candidates").and_then(Value::as_array) {
-            if let Some(candidate) = candidates.first() {
-                if let Some(content) = candidate.get("content") {
-                    if let Some(parts) = content.get("parts").and_then(Value::as_array) {
-                        if let Some(part) = parts.first() {
-                            if let Some(text) = part.get("text").and_then(Value::as_str) {
-                                return Ok(text.to_string());
-                            }
-                        }
-                    }
-                }
-            }
+        if let Some(err) = Error::from_status("Gemini", status) {
+            return Err(err);
         }
 
-        if let Some(error) = json.get("error") {
-            return Err(anyhow!("Gemini API error: {}", error.to_string()));
-        }
+        parse_response(&text)
+    }
+}
 
-        Err(anyhow!("Failed to parse Gemini response"))
+#[async_trait]
+impl AiProvider for GeminiProvider {
+    async fn generate_text(&self, prompt: &str) -> Result<String> {
+        self.send_request(prompt, None).await
+    }
+
+    async fn generate_text_with_temperature(
+        &self,
+        prompt: &str,
+        temperature: f32,
+    ) -> Result<String> {
+        self.send_request(prompt, Some(temperature)).await
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
     }
 }
 
@@ -110,31 +164,6 @@ impl AiProvider for GeminiProvider {
 mod tests {
     use super::*;
 
-    // Helper function to test the response parsing logic directly
-    fn extract_content_from_response(json_str: &str) -> Result<String> {
-        let json: Value = serde_json::from_str(json_str)?;
-
-        if let Some(candidates) = json.get("candidates").and_then(Value::as_array) {
-            if let Some(candidate) = candidates.first() {
-                if let Some(content) = candidate.get("content") {
-                    if let Some(parts) = content.get("parts").and_then(Value::as_array) {
-                        if let Some(part) = parts.first() {
-                            if let Some(text) = part.get("text").and_then(Value::as_str) {
-                                return Ok(text.to_string());
-                            }
-                        }
-                    }
-                }
-            }
-        }
-
-        if let Some(error) = json.get("error") {
-            return Err(anyhow!("Gemini API error: {}", error.to_string()));
-        }
-
-        Err(anyhow!("Failed to parse Gemini response"))
-    }
-
     #[tokio::test]
     async fn test_generate_text_success() {
         let json_str = r#"{
@@ -145,7 +174,7 @@ mod tests {
             }]
         }"#;
 
-        let result = extract_content_from_response(json_str);
+        let result = parse_response(json_str);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "feat: Implement new feature");
     }
@@ -154,11 +183,23 @@ mod tests {
     async fn test_generate_text_error() {
         let json_str = r#"{"error": "API key missing"}"#;
 
-        let result = extract_content_from_response(json_str);
+        let result = parse_response(json_str);
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().to_string(),
             "Gemini API error: \"API key missing\""
         );
     }
+
+    #[test]
+    fn test_parse_response_error_kinds() {
+        let err = parse_response(r#"{"error": {"code": 400, "message": "Bad"}}"#).unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::ProviderApi { provider, .. } if provider == "Gemini"
+        ));
+
+        let err = parse_response(r#"{"candidates": []}"#).unwrap_err();
+        assert!(matches!(&err, Error::ParseFailure { source: None, .. }));
+    }
 }
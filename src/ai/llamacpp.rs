@@ -0,0 +1,189 @@
+use crate::error::Result;
+use async_trait::async_trait;
+use reqwest::{Client, StatusCode};
+use serde::Serialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::openai::{OpenAIRequest, parse_completion};
+use super::{AiProvider, ProviderCapabilities};
+use crate::error::Error;
+
+/// Provider for a local llama.cpp `llama-server`
+#[derive(Debug)]
+pub struct LlamaCppProvider {
+    client: Arc<Client>,
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+    verbose: bool,
+}
+
+/// Request for llama-server's native `/completion` endpoint
+#[derive(Serialize)]
+struct CompletionRequest {
+    prompt: String,
+    n_predict: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+}
+
+/// Parses an OpenAI-style response, falling back to the native `{"content": ...}` shape
+fn parse_llamacpp_response(text: &str) -> Result<String> {
+    parse_completion(text).or_else(|openai_err| {
+        let json: Value =
+            serde_json::from_str(text).map_err(|e| Error::parse("llama.cpp", Some(e)))?;
+        json.get("content")
+            .and_then(Value::as_str)
+            .map(String::from)
+            .ok_or(openai_err)
+    })
+}
+
+impl LlamaCppProvider {
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        streaming: false,
+        n: false,
+        seed: false,
+        system_prompt: true,
+        temperature: true,
+        usage: false,
+    };
+
+    pub fn new(
+        client: Arc<Client>,
+        base_url: &str,
+        model: &str,
+        api_key: Option<&str>,
+        verbose: bool,
+    ) -> Self {
+        Self {
+            client,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            model: model.to_string(),
+            api_key: api_key.map(String::from),
+            verbose,
+        }
+    }
+
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let request = self.client.post(format!("{}{}", self.base_url, path));
+        match &self.api_key {
+            Some(api_key) => request.bearer_auth(api_key),
+            None => request,
+        }
+    }
+
+    async fn send_request(&self, prompt: &str, temperature: Option<f32>) -> Result<String> {
+        if self.verbose {
+            eprintln!("Sending request to llama.cpp server...");
+        }
+
+        let request = OpenAIRequest::new(&self.model, prompt, temperature.unwrap_or(0.7), false);
+
+        let mut response = self
+            .post("/v1/chat/completions")
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| {
+                eprintln!("Failed to connect to llama.cpp server: {e}");
+                eprintln!("Make sure llama-server is running on {}", self.base_url);
+                Error::unreachable("llama.cpp", e)
+            })?;
+
+        // Older or minimal builds only expose the native endpoint
+        if response.status() == StatusCode::NOT_FOUND {
+            if self.verbose {
+                eprintln!("Chat endpoint not found, falling back to /completion...");
+            }
+            let request = CompletionRequest {
+                prompt: prompt.to_string(),
+                n_predict: 1000,
+                temperature,
+            };
+            response = self
+                .post("/completion")
+                .json(&request)
+                .send()
+                .await
+                .map_err(|e| Error::unreachable("llama.cpp", e))?;
+        }
+
+        let status = response.status();
+        if self.verbose {
+            eprintln!("llama.cpp API response status: {status}");
+        }
+
+        let text = response
+            .text()
+            .await
+            .map_err(|e| Error::unreachable("llama.cpp", e))?;
+        if self.verbose {
+            eprintln!("Raw response: {text}");
+        }
+
+        if let Some(err) = Error::from_status("llama.cpp", status) {
+            return Err(err);
+        }
+
+        parse_llamacpp_response(&text)
+    }
+}
+
+#[async_trait]
+impl AiProvider for LlamaCppProvider {
+    async fn generate_text(&self, prompt: &str) -> Result<String> {
+        self.send_request(prompt, None).await
+    }
+
+    async fn generate_text_with_temperature(
+        &self,
+        prompt: &str,
+        temperature: f32,
+    ) -> Result<String> {
+        self.send_request(prompt, Some(temperature)).await
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_openai_shape() {
+        let json_str = r#"{"choices":[{"message":{"role":"assistant","content":"feat(cli): add llama.cpp"}}]}"#;
+
+        let result = parse_llamacpp_response(json_str);
+        assert_eq!(result.unwrap(), "feat(cli): add llama.cpp");
+    }
+
+    #[test]
+    fn test_parse_native_shape() {
+        let json_str = r#"{"content":"fix(server): handle native endpoint","stop":true}"#;
+
+        let result = parse_llamacpp_response(json_str);
+        assert_eq!(result.unwrap(), "fix(server): handle native endpoint");
+    }
+
+    #[test]
+    fn test_parse_error_shape() {
+        let json_str = r#"{"error":{"code":503,"message":"Loading model"}}"#;
+
+        let result = parse_llamacpp_response(json_str);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "OpenAI API error: Loading model"
+        );
+    }
+
+    #[test]
+    fn test_parse_unknown_shape() {
+        let result = parse_llamacpp_response(r#"{"unexpected":true}"#);
+        assert!(result.is_err());
+    }
+}
@@ -1,25 +1,70 @@
-use anyhow::Result;
+use crate::error::Result;
 use async_trait::async_trait;
 use std::fmt::Debug;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use super::AiProvider;
+use super::{AiProvider, ProviderCapabilities, RateLimit};
+use crate::error::Error;
 
 /// A mock AI provider for testing purposes
 #[derive(Debug)]
 pub struct MockProvider {
-    response: String,
+    responses: Vec<String>,
     pub calls: Arc<Mutex<Vec<String>>>,
+    /// Temperatures passed to `generate_text_with_temperature`
+    pub temperatures: Arc<Mutex<Vec<f32>>>,
+    /// How long each call takes, to exercise concurrent callers
+    delay: Option<Duration>,
+    in_flight: Arc<AtomicUsize>,
+    /// The highest number of calls that were running at the same time
+    pub max_in_flight: Arc<AtomicUsize>,
+    rate_limit: Option<RateLimit>,
 }
 
 impl MockProvider {
     pub fn new(response: impl Into<String>) -> Self {
         Self {
-            response: response.into(),
+            responses: vec![response.into()],
             calls: Arc::new(Mutex::new(Vec::new())),
+            temperatures: Arc::new(Mutex::new(Vec::new())),
+            delay: None,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight: Arc::new(AtomicUsize::new(0)),
+            rate_limit: None,
         }
     }
 
+    /// Create a mock that returns each response in turn, repeating the last one
+    pub fn new_with_responses<S: Into<String>>(responses: Vec<S>) -> Self {
+        assert!(
+            !responses.is_empty(),
+            "MockProvider needs at least one response"
+        );
+        Self {
+            responses: responses.into_iter().map(Into::into).collect(),
+            calls: Arc::new(Mutex::new(Vec::new())),
+            temperatures: Arc::new(Mutex::new(Vec::new())),
+            delay: None,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight: Arc::new(AtomicUsize::new(0)),
+            rate_limit: None,
+        }
+    }
+
+    /// Make every call take `delay` before responding
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = Some(delay);
+        self
+    }
+
+    /// Report `rate_limit` as if it came with the last response
+    pub fn with_rate_limit(mut self, rate_limit: RateLimit) -> Self {
+        self.rate_limit = Some(rate_limit);
+        self
+    }
+
     pub fn get_calls(&self) -> Vec<String> {
         self.calls.lock().unwrap().clone()
     }
@@ -27,25 +72,80 @@ impl MockProvider {
     // Add new method to create error-returning mock
     pub fn new_with_error(error_message: impl Into<String>) -> Self {
         Self {
-            response: format!("ERROR:{}", error_message.into()),
+            responses: vec![format!("ERROR:{}", error_message.into())],
             calls: Arc::new(Mutex::new(Vec::new())),
+            temperatures: Arc::new(Mutex::new(Vec::new())),
+            delay: None,
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight: Arc::new(AtomicUsize::new(0)),
+            rate_limit: None,
         }
     }
-}
 
-#[async_trait]
-impl AiProvider for MockProvider {
-    async fn generate_text(&self, prompt: &str) -> Result<String> {
+    /// Create a mock that fails like a provider rejecting its credentials
+    pub fn new_with_auth_error(error_message: impl Into<String>) -> Self {
+        Self::new(format!("AUTH_ERROR:{}", error_message.into()))
+    }
+
+    async fn track_in_flight(&self) {
+        let Some(delay) = self.delay else {
+            return;
+        };
+        let running = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        self.max_in_flight.fetch_max(running, Ordering::SeqCst);
+        tokio::time::sleep(delay).await;
+        self.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    fn respond(&self, prompt: &str) -> Result<String> {
         // Record the prompt that was passed
-        self.calls.lock().unwrap().push(prompt.to_string());
+        let call_index = {
+            let mut calls = self.calls.lock().unwrap();
+            calls.push(prompt.to_string());
+            calls.len() - 1
+        };
+
+        let response = &self.responses[call_index.min(self.responses.len() - 1)];
 
         // Check if this is an error mock
-        if self.response.starts_with("ERROR:") {
-            return Err(anyhow::anyhow!("{}", &self.response[6..]));
+        if let Some(message) = response.strip_prefix("AUTH_ERROR:") {
+            return Err(Error::auth("Mock", message));
+        }
+        if let Some(message) = response.strip_prefix("ERROR:") {
+            return Err(anyhow::anyhow!("{}", message).into());
         }
 
         // Return the predefined response
-        Ok(self.response.clone())
+        Ok(response.clone())
+    }
+}
+
+#[async_trait]
+impl AiProvider for MockProvider {
+    async fn generate_text(&self, prompt: &str) -> Result<String> {
+        self.track_in_flight().await;
+        self.respond(prompt)
+    }
+
+    async fn generate_text_with_temperature(
+        &self,
+        prompt: &str,
+        temperature: f32,
+    ) -> Result<String> {
+        self.temperatures.lock().unwrap().push(temperature);
+        self.track_in_flight().await;
+        self.respond(prompt)
+    }
+
+    fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities {
+            temperature: true,
+            ..Default::default()
+        }
     }
 }
 
@@ -97,6 +197,48 @@ mod tests {
         assert_eq!(calls[0], "test prompt");
     }
 
+    #[tokio::test]
+    async fn test_mock_provider_with_responses_sequence() {
+        let provider = MockProvider::new_with_responses(vec!["first", "second"]);
+
+        assert_eq!(provider.generate_text("a").await.unwrap(), "first");
+        assert_eq!(provider.generate_text("b").await.unwrap(), "second");
+        // The last response is repeated once the sequence is exhausted
+        assert_eq!(provider.generate_text("c").await.unwrap(), "second");
+        assert_eq!(provider.get_calls().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_mock_provider_records_temperature() {
+        let provider = MockProvider::new("mock response");
+
+        let _ = provider.generate_text("plain").await;
+        let result = provider
+            .generate_text_with_temperature("warm", 1.1)
+            .await
+            .unwrap();
+
+        assert_eq!(result, "mock response");
+        assert_eq!(provider.get_calls(), vec!["plain", "warm"]);
+        assert_eq!(*provider.temperatures.lock().unwrap(), vec![1.1]);
+    }
+
+    #[test]
+    fn test_mock_provider_capabilities() {
+        let provider: Box<dyn AiProvider> = Box::new(MockProvider::new("response"));
+        assert_eq!(
+            provider.capabilities(),
+            ProviderCapabilities {
+                streaming: false,
+                n: false,
+                seed: false,
+                system_prompt: false,
+                temperature: true,
+                usage: false,
+            }
+        );
+    }
+
     #[test]
     fn test_get_calls_with_concurrent_access() {
         let provider = MockProvider::new("response");
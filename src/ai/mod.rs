@@ -1,18 +1,137 @@
-use anyhow::Result;
+use crate::error::Result;
 use async_trait::async_trait;
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Display};
+use std::time::{Duration, Instant};
 
+use tokenizer::{CharEstimateTokenizer, Tokenizer};
+
+pub mod command;
+pub mod fallback;
 pub mod gemini;
+pub mod llamacpp;
 pub mod ollama;
 pub mod openai;
 pub mod provider_factory;
+pub mod throttle;
+pub mod tokenizer;
 
 #[cfg(test)]
 pub mod mock;
 
+/// Token usage reported by a provider, summed over every request it made
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    /// Estimated cost in US dollars, if the model's price is known
+    pub estimated_cost: Option<f64>,
+}
+
+impl Display for Usage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Tokens: {}+{}",
+            self.prompt_tokens, self.completion_tokens
+        )?;
+        if let Some(cost) = self.estimated_cost {
+            write!(f, ", est. ${cost:.6}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Request features a provider makes use of, as reported by --capabilities
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ProviderCapabilities {
+    /// Reads the response as it is streamed back
+    pub streaming: bool,
+    /// Can return several completions for one request (`n`)
+    pub n: bool,
+    /// Honors --seed
+    pub seed: bool,
+    /// Sends a system prompt ahead of the user prompt
+    pub system_prompt: bool,
+    /// Controls the sampling temperature
+    pub temperature: bool,
+    /// Reports token usage for --print-usage
+    pub usage: bool,
+}
+
+/// Rate-limit state a provider reported with its last response
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub remaining_requests: Option<u64>,
+    pub remaining_tokens: Option<u64>,
+    /// Time until the request quota resets, as of `observed_at`
+    pub reset_requests: Option<Duration>,
+    /// Time until the token quota resets, as of `observed_at`
+    pub reset_tokens: Option<Duration>,
+    pub observed_at: Instant,
+}
+
+impl RateLimit {
+    /// How long to hold off before the next request: what is left of the
+    /// reset window of every exhausted quota, or None if none is exhausted
+    pub fn pause(&self) -> Option<Duration> {
+        let window = [
+            (self.remaining_requests, self.reset_requests),
+            (self.remaining_tokens, self.reset_tokens),
+        ]
+        .into_iter()
+        .filter_map(|(remaining, reset)| (remaining == Some(0)).then_some(reset?))
+        .max()?;
+        window
+            .checked_sub(self.observed_at.elapsed())
+            .filter(|pause| !pause.is_zero())
+    }
+}
+
 #[async_trait]
 pub trait AiProvider: Send + Sync + Debug {
     async fn generate_text(&self, prompt: &str) -> Result<String>;
+
+    /// Generates text with an explicit sampling temperature. Providers that
+    /// can't control the temperature ignore it.
+    async fn generate_text_with_temperature(
+        &self,
+        prompt: &str,
+        _temperature: f32,
+    ) -> Result<String> {
+        self.generate_text(prompt).await
+    }
+
+    /// Tokenizer matching the provider's model, used to enforce prompt limits
+    fn tokenizer(&self) -> Box<dyn Tokenizer> {
+        Box::new(CharEstimateTokenizer)
+    }
+
+    /// Loads the model ahead of the first real request, for providers that
+    /// load models on demand
+    async fn preload(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Token usage so far, for providers that report it
+    fn usage(&self) -> Option<Usage> {
+        None
+    }
+
+    /// The provider and model that actually answered, for providers that
+    /// stand in for several
+    fn answered_by(&self) -> Option<(String, String)> {
+        None
+    }
+
+    /// Rate-limit state from the last response, for providers that report it
+    fn rate_limit(&self) -> Option<RateLimit> {
+        None
+    }
+
+    /// Request features the provider makes use of
+    fn capabilities(&self) -> ProviderCapabilities {
+        ProviderCapabilities::default()
+    }
 }
 
 #[async_trait]
@@ -20,6 +139,40 @@ impl AiProvider for Box<dyn AiProvider> {
     async fn generate_text(&self, prompt: &str) -> Result<String> {
         (**self).generate_text(prompt).await
     }
+
+    async fn generate_text_with_temperature(
+        &self,
+        prompt: &str,
+        temperature: f32,
+    ) -> Result<String> {
+        (**self)
+            .generate_text_with_temperature(prompt, temperature)
+            .await
+    }
+
+    fn tokenizer(&self) -> Box<dyn Tokenizer> {
+        (**self).tokenizer()
+    }
+
+    async fn preload(&self) -> Result<()> {
+        (**self).preload().await
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        (**self).usage()
+    }
+
+    fn answered_by(&self) -> Option<(String, String)> {
+        (**self).answered_by()
+    }
+
+    fn rate_limit(&self) -> Option<RateLimit> {
+        (**self).rate_limit()
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        (**self).capabilities()
+    }
 }
 
 #[cfg(test)]
@@ -63,4 +216,54 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(result.unwrap_err().to_string(), "Test error");
     }
+
+    #[test]
+    fn test_usage_display() {
+        let usage = Usage {
+            prompt_tokens: 1200,
+            completion_tokens: 35,
+            estimated_cost: Some(0.000201),
+        };
+        assert_eq!(usage.to_string(), "Tokens: 1200+35, est. $0.000201");
+
+        let usage = Usage {
+            estimated_cost: None,
+            ..usage
+        };
+        assert_eq!(usage.to_string(), "Tokens: 1200+35");
+    }
+
+    #[test]
+    fn test_rate_limit_pause() {
+        let rate_limit = RateLimit {
+            remaining_requests: Some(12),
+            remaining_tokens: Some(40000),
+            reset_requests: Some(Duration::from_secs(1)),
+            reset_tokens: Some(Duration::from_secs(2)),
+            observed_at: Instant::now(),
+        };
+        assert_eq!(rate_limit.pause(), None);
+
+        // Only exhausted quotas count
+        let rate_limit = RateLimit {
+            remaining_requests: Some(0),
+            ..rate_limit
+        };
+        let pause = rate_limit.pause().unwrap();
+        assert!(pause <= Duration::from_secs(1) && pause > Duration::from_millis(500));
+
+        let rate_limit = RateLimit {
+            remaining_tokens: Some(0),
+            ..rate_limit
+        };
+        assert!(rate_limit.pause().unwrap() > Duration::from_secs(1));
+
+        // A window that has already passed needs no pause
+        let rate_limit = RateLimit {
+            reset_requests: Some(Duration::ZERO),
+            reset_tokens: None,
+            ..rate_limit
+        };
+        assert_eq!(rate_limit.pause(), None);
+    }
 }
@@ -1,17 +1,21 @@
-use anyhow::{Result, anyhow};
+use crate::error::Result;
 use async_trait::async_trait;
 use reqwest::Client;
 use serde::Serialize;
 use serde_json::Value;
+use std::sync::Arc;
 
-use super::AiProvider;
+use super::{AiProvider, ProviderCapabilities};
+use crate::error::Error;
 
 #[derive(Debug)]
 pub struct OllamaProvider {
-    client: Client,
+    client: Arc<Client>,
     base_url: String,
     model: String,
     verbose: bool,
+    keep_alive: Option<String>,
+    seed: Option<u64>,
 }
 
 #[derive(Serialize)]
@@ -19,31 +23,96 @@ struct OllamaRequest {
     model: String,
     prompt: String,
     stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<String>,
+}
+
+#[derive(Serialize)]
+struct OllamaOptions {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+}
+
+/// Extracts the generated text from an Ollama response body
+fn parse_response(text: &str) -> Result<String> {
+    let json: Value = serde_json::from_str(text).map_err(|e| Error::parse("Ollama", Some(e)))?;
+
+    if let Some(response_text) = json.get("response").and_then(Value::as_str) {
+        return Ok(response_text.to_string());
+    }
+
+    if let Some(error) = json.get("error").and_then(Value::as_str) {
+        return Err(Error::api("Ollama", error));
+    }
+
+    if let Some(content) = json
+        .get("message")
+        .and_then(|m| m.get("content"))
+        .and_then(Value::as_str)
+    {
+        return Ok(content.to_string());
+    }
+
+    Ok(text.to_string())
 }
 
 impl OllamaProvider {
-    pub fn new(base_url: &str, model: &str, verbose: bool) -> Self {
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        streaming: false,
+        n: false,
+        seed: true,
+        system_prompt: false,
+        temperature: true,
+        usage: false,
+    };
+
+    pub fn new(client: Arc<Client>, base_url: &str, model: &str, verbose: bool) -> Self {
         Self {
-            client: Client::new(),
+            client,
             base_url: base_url.to_string(),
             model: model.to_string(),
             verbose,
+            keep_alive: None,
+            seed: None,
         }
     }
-}
 
-#[async_trait]
-impl AiProvider for OllamaProvider {
-    async fn generate_text(&self, prompt: &str) -> Result<String> {
-        if self.verbose {
-            println!("Sending request to Ollama API...");
-        }
+    /// How long the server keeps the model loaded after a request, in
+    /// Ollama's duration format (e.g. "10m", or "-1" for indefinitely)
+    pub fn with_keep_alive(mut self, keep_alive: Option<String>) -> Self {
+        self.keep_alive = keep_alive;
+        self
+    }
 
-        let request = OllamaRequest {
+    /// Seed for reproducible sampling
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    fn build_request(&self, prompt: &str, temperature: Option<f32>) -> OllamaRequest {
+        OllamaRequest {
             model: self.model.clone(),
             prompt: prompt.to_string(),
             stream: false,
-        };
+            options: (temperature.is_some() || self.seed.is_some()).then_some(OllamaOptions {
+                temperature,
+                seed: self.seed,
+            }),
+            keep_alive: self.keep_alive.clone(),
+        }
+    }
+
+    async fn send_request(&self, prompt: &str, temperature: Option<f32>) -> Result<String> {
+        if self.verbose {
+            eprintln!("Sending request to Ollama API...");
+        }
+
+        let request = self.build_request(prompt, temperature);
 
         let response = self
             .client
@@ -54,72 +123,75 @@ impl AiProvider for OllamaProvider {
             .map_err(|e| {
                 eprintln!("Failed to connect to Ollama server: {e}");
                 eprintln!("Make sure Ollama is running on {}", self.base_url);
-                anyhow!("Connection to Ollama failed: {}", e)
+                Error::unreachable("Ollama", e)
             })?;
 
         if self.verbose {
-            println!("Ollama API response status: {}", response.status());
+            eprintln!("Ollama API response status: {}", response.status());
         }
 
-        let text = response.text().await?;
+        let text = response
+            .text()
+            .await
+            .map_err(|e| Error::unreachable("Ollama", e))?;
         if self.verbose {
-            println!("Raw response: {text}");
+            eprintln!("Raw response: {text}");
         }
 
-        let json: Value = serde_json::from_str(&text)?;
+        parse_response(&text)
+    }
+}
 
-        if let Some(response_text) = json.get("response").and_then(Value::as_str) {
-            return Ok(response_text.to_string());
-        }
+#[async_trait]
+impl AiProvider for OllamaProvider {
+    async fn generate_text(&self, prompt: &str) -> Result<String> {
+        self.send_request(prompt, None).await
+    }
 
-        if let Some(error) = json.get("error").and_then(Value::as_str) {
-            return Err(anyhow!("Ollama API error: {}", error));
-        }
+    async fn generate_text_with_temperature(
+        &self,
+        prompt: &str,
+        temperature: f32,
+    ) -> Result<String> {
+        self.send_request(prompt, Some(temperature)).await
+    }
 
-        if let Some(content) = json
-            .get("message")
-            .and_then(|m| m.get("content"))
-            .and_then(Value::as_str)
-        {
-            return Ok(content.to_string());
+    async fn preload(&self) -> Result<()> {
+        if self.verbose {
+            eprintln!("Preloading Ollama model {}...", self.model);
         }
 
-        Ok(text)
+        // An empty prompt makes Ollama load the model without generating
+        let response = self
+            .client
+            .post(format!("{}/api/generate", self.base_url))
+            .json(&self.build_request("", None))
+            .send()
+            .await
+            .map_err(|e| Error::unreachable("Ollama", e))?;
+
+        let text = response
+            .text()
+            .await
+            .map_err(|e| Error::unreachable("Ollama", e))?;
+        parse_response(&text).map(|_| ())
+    }
+
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-
-    // Helper function to test the response parsing logic directly
-    fn extract_content_from_response(json_str: &str) -> Result<String> {
-        let json: Value = serde_json::from_str(json_str)?;
-
-        if let Some(response_text) = json.get("response").and_then(Value::as_str) {
-            return Ok(response_text.to_string());
-        }
-
-        if let Some(error) = json.get("error").and_then(Value::as_str) {
-            return Err(anyhow!("Ollama API error: {}", error));
-        }
-
-        if let Some(content) = json
-            .get("message")
-            .and_then(|m| m.get("content"))
-            .and_then(Value::as_str)
-        {
-            return Ok(content.to_string());
-        }
-
-        Ok(json_str.to_string())
-    }
+    use crate::ai::provider_factory::http_client;
 
     #[tokio::test]
     async fn test_generate_text_success() {
         let json_str = r#"{"response": "feat(api): implement user authentication"}"#;
 
-        let result = extract_content_from_response(json_str);
+        let result = parse_response(json_str);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "feat(api): implement user authentication");
     }
@@ -128,11 +200,99 @@ mod tests {
     async fn test_generate_text_error() {
         let json_str = r#"{"error": "Model not found"}"#;
 
-        let result = extract_content_from_response(json_str);
+        let result = parse_response(json_str);
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().to_string(),
             "Ollama API error: Model not found"
         );
     }
+
+    #[test]
+    fn test_parse_response_error_kinds() {
+        let err = parse_response(r#"{"error": "Model not found"}"#).unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::ProviderApi { provider, .. } if provider == "Ollama"
+        ));
+
+        let err = parse_response("not json").unwrap_err();
+        assert!(matches!(&err, Error::ParseFailure { .. }));
+    }
+
+    /// The URL of a port nothing listens on: bound to find a free one, then
+    /// released
+    fn unused_url() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}", listener.local_addr().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_server() {
+        let provider = OllamaProvider::new(http_client().unwrap(), &unused_url(), "llama3", false);
+
+        let err = provider.generate_text("prompt").await.unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::ProviderUnreachable { provider, .. } if provider == "Ollama"
+        ));
+    }
+
+    #[test]
+    fn test_keep_alive_in_request() {
+        let provider = OllamaProvider::new(
+            http_client().unwrap(),
+            "http://localhost:11434",
+            "llama3",
+            false,
+        )
+        .with_keep_alive(Some("10m".to_string()));
+        let json = serde_json::to_value(provider.build_request("prompt", None)).unwrap();
+        assert_eq!(json["keep_alive"], "10m");
+        assert_eq!(json["prompt"], "prompt");
+
+        // Left to the server's default unless configured
+        let provider = OllamaProvider::new(
+            http_client().unwrap(),
+            "http://localhost:11434",
+            "llama3",
+            false,
+        );
+        let json = serde_json::to_value(provider.build_request("prompt", None)).unwrap();
+        assert!(json.get("keep_alive").is_none());
+    }
+
+    #[test]
+    fn test_seed_in_request() {
+        let provider = OllamaProvider::new(
+            http_client().unwrap(),
+            "http://localhost:11434",
+            "llama3",
+            false,
+        )
+        .with_seed(Some(42));
+        let json = serde_json::to_value(provider.build_request("prompt", None)).unwrap();
+        assert_eq!(json["options"]["seed"], 42);
+        assert!(json["options"].get("temperature").is_none());
+
+        let json = serde_json::to_value(provider.build_request("prompt", Some(1.1))).unwrap();
+        assert_eq!(json["options"]["seed"], 42);
+        assert!(json["options"]["temperature"].as_f64().is_some());
+
+        let provider = OllamaProvider::new(
+            http_client().unwrap(),
+            "http://localhost:11434",
+            "llama3",
+            false,
+        );
+        let json = serde_json::to_value(provider.build_request("prompt", None)).unwrap();
+        assert!(json.get("options").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_preload_unreachable_server() {
+        let provider = OllamaProvider::new(http_client().unwrap(), &unused_url(), "llama3", false);
+        let err = provider.preload().await.unwrap_err();
+        assert!(matches!(&err, Error::ProviderUnreachable { .. }));
+    }
 }
@@ -1,56 +1,77 @@
-use anyhow::{Result, anyhow};
+use crate::error::Result;
 use async_trait::async_trait;
 use reqwest::Client;
+use reqwest::header::HeaderMap;
 use serde::Serialize;
 use serde_json::Value;
 use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use super::AiProvider;
+use super::tokenizer::{CharEstimateTokenizer, TiktokenTokenizer, Tokenizer};
+use super::{AiProvider, ProviderCapabilities, RateLimit, Usage};
+use crate::error::Error;
 
 #[derive(Debug)]
 pub struct OpenAIProvider {
-    client: Client,
+    client: Arc<Client>,
     base_url: String,
     model: String,
     api_key: String,
     verbose: bool,
+    json_mode: bool,
+    seed: Option<u64>,
+    stream: bool,
+    usage: Mutex<Usage>,
+    rate_limit: Mutex<Option<RateLimit>>,
 }
 
+/// Cap on the tokens a completion may use
+const MAX_TOKENS: u32 = 1000;
+
 #[derive(Serialize)]
-struct OpenAIRequest {
+pub(super) struct OpenAIRequest {
     model: String,
     messages: Vec<Message>,
     temperature: f32,
     max_tokens: u32,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<ResponseFormat>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+/// Asks for a final streamed chunk carrying the token usage
+#[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
 }
 
+#[derive(Serialize)]
+struct ResponseFormat {
+    #[serde(rename = "type")]
+    format_type: String,
+}
+
+/// Added to the system message in JSON mode. OpenAI requires the word JSON
+/// to appear in the messages when `json_object` output is requested.
+const JSON_MODE_INSTRUCTION: &str =
+    "Respond only with a JSON object of the form {\"messages\": [\"<commit message>\", ...]}.";
+
 #[derive(Serialize)]
 struct Message {
     role: String,
     content: String,
 }
 
-impl OpenAIProvider {
-    pub fn new(base_url: &str, model: &str, api_key: &str, verbose: bool) -> Self {
+impl OpenAIRequest {
+    /// Builds a chat completion request, also used by OpenAI-compatible servers
+    pub(super) fn new(model: &str, prompt: &str, temperature: f32, stream: bool) -> Self {
         Self {
-            client: Client::new(),
-            base_url: base_url.to_string(),
             model: model.to_string(),
-            api_key: api_key.to_string(),
-            verbose,
-        }
-    }
-}
-
-#[async_trait]
-impl AiProvider for OpenAIProvider {
-    async fn generate_text(&self, prompt: &str) -> Result<String> {
-        if self.verbose {
-            println!("Sending request to OpenAI API...");
-        }
-
-        let request = OpenAIRequest {
-            model: self.model.clone(),
             messages: vec![
                 Message {
                     role: "system".to_string(),
@@ -62,11 +83,347 @@ impl AiProvider for OpenAIProvider {
                     content: prompt.to_string(),
                 },
             ],
-            temperature: 0.7,
-            max_tokens: 1000,
+            temperature,
+            max_tokens: MAX_TOKENS,
+            stream,
+            seed: None,
+            response_format: None,
+            stream_options: stream.then_some(StreamOptions {
+                include_usage: true,
+            }),
+        }
+    }
+
+    /// Asks for deterministic sampling, as far as the model supports it
+    pub(super) fn seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Asks for a JSON object holding the list of messages
+    pub(super) fn json_object(mut self) -> Self {
+        if let Some(system) = self.messages.first_mut() {
+            system.content = format!("{} {JSON_MODE_INSTRUCTION}", system.content);
+        }
+        self.response_format = Some(ResponseFormat {
+            format_type: "json_object".to_string(),
+        });
+        self
+    }
+}
+
+/// Reassembles the text of a streamed (server-sent events) chat completion
+#[derive(Debug, Default)]
+struct SseAccumulator {
+    buffer: Vec<u8>,
+    content: String,
+    usage: Option<Usage>,
+    finish_reason: Option<String>,
+    done: bool,
+}
+
+impl SseAccumulator {
+    /// Feeds a chunk of the response body and returns the deltas decoded from
+    /// every complete line in it. Partial lines are kept for the next chunk.
+    fn push(&mut self, chunk: &[u8]) -> Result<Vec<String>> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut deltas = Vec::new();
+        while let Some(pos) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=pos).collect();
+            if let Some(delta) = self.handle_line(&String::from_utf8_lossy(&line))? {
+                deltas.push(delta);
+            }
+        }
+        Ok(deltas)
+    }
+
+    /// Processes any trailing line and returns the full concatenated text
+    fn finish(&mut self) -> Result<String> {
+        if !self.buffer.is_empty() {
+            let line = String::from_utf8_lossy(&std::mem::take(&mut self.buffer)).to_string();
+            self.handle_line(&line)?;
+        }
+        check_finish_reason(self.finish_reason.as_deref(), &self.content)?;
+        Ok(std::mem::take(&mut self.content))
+    }
+
+    fn handle_line(&mut self, line: &str) -> Result<Option<String>> {
+        if self.done {
+            return Ok(None);
+        }
+
+        let Some(data) = line.trim().strip_prefix("data:") else {
+            // Comments, event names and blank separators carry no content
+            return Ok(None);
+        };
+        let data = data.trim();
+
+        if data == "[DONE]" {
+            self.done = true;
+            return Ok(None);
+        }
+
+        let json: Value =
+            serde_json::from_str(data).map_err(|e| Error::parse("OpenAI", Some(e)))?;
+
+        if let Some(message) = json
+            .get("error")
+            .and_then(|err| err.get("message"))
+            .and_then(Value::as_str)
+        {
+            return Err(Error::api("OpenAI", message));
+        }
+
+        if let Some(usage) = parse_usage(&json) {
+            self.usage = Some(usage);
+        }
+
+        let choice = json
+            .get("choices")
+            .and_then(Value::as_array)
+            .and_then(|choices| choices.first());
+        if let Some(finish_reason) = choice
+            .and_then(|choice| choice.get("finish_reason"))
+            .and_then(Value::as_str)
+        {
+            self.finish_reason = Some(finish_reason.to_string());
+        }
+
+        let delta = choice
+            .and_then(|choice| choice.get("delta"))
+            .and_then(|delta| delta.get("content"))
+            .and_then(Value::as_str);
+
+        match delta {
+            Some(delta) if !delta.is_empty() => {
+                self.content.push_str(delta);
+                Ok(Some(delta.to_string()))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Prices in US dollars per million (prompt, completion) tokens. Dated model
+/// names match by prefix, so the longer names must come first.
+const MODEL_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+];
+
+/// Estimates the cost of the given usage, if the model's price is known
+fn estimate_cost(model: &str, prompt_tokens: u64, completion_tokens: u64) -> Option<f64> {
+    let (_, prompt_price, completion_price) = MODEL_PRICES
+        .iter()
+        .find(|(name, _, _)| model.starts_with(name))?;
+
+    Some(
+        (prompt_tokens as f64 * prompt_price + completion_tokens as f64 * completion_price)
+            / 1_000_000.0,
+    )
+}
+
+/// Reads the `usage` object of a completion or of the final streamed chunk
+fn parse_usage(json: &Value) -> Option<Usage> {
+    let usage = json.get("usage")?;
+    Some(Usage {
+        prompt_tokens: usage.get("prompt_tokens")?.as_u64()?,
+        completion_tokens: usage.get("completion_tokens")?.as_u64()?,
+        estimated_cost: None,
+    })
+}
+
+/// Reads OpenAI's `x-ratelimit-*` response headers, if the server sent any
+fn parse_rate_limit(headers: &HeaderMap, observed_at: Instant) -> Option<RateLimit> {
+    let header = |name: &str| headers.get(name).and_then(|value| value.to_str().ok());
+    let rate_limit = RateLimit {
+        remaining_requests: header("x-ratelimit-remaining-requests")
+            .and_then(|value| value.trim().parse().ok()),
+        remaining_tokens: header("x-ratelimit-remaining-tokens")
+            .and_then(|value| value.trim().parse().ok()),
+        reset_requests: header("x-ratelimit-reset-requests").and_then(parse_reset),
+        reset_tokens: header("x-ratelimit-reset-tokens").and_then(parse_reset),
+        observed_at,
+    };
+
+    let reported = rate_limit.remaining_requests.is_some()
+        || rate_limit.remaining_tokens.is_some()
+        || rate_limit.reset_requests.is_some()
+        || rate_limit.reset_tokens.is_some();
+    reported.then_some(rate_limit)
+}
+
+/// Parses a reset window as OpenAI writes it, e.g. `20ms`, `1.5s` or `6m0s`
+fn parse_reset(value: &str) -> Option<Duration> {
+    let mut rest = value.trim();
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut total = Duration::ZERO;
+    while !rest.is_empty() {
+        let number_len = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_len].parse().ok()?;
+        rest = &rest[number_len..];
+
+        let unit_len = rest
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_len] {
+            "h" => number * 3600.0,
+            "m" => number * 60.0,
+            "s" => number,
+            "ms" => number / 1000.0,
+            _ => return None,
         };
+        total += Duration::try_from_secs_f64(seconds).ok()?;
+        rest = &rest[unit_len..];
+    }
+    Some(total)
+}
+
+/// Explains why a completion stopped, when that is the reason it has no
+/// usable text: it was filtered, or it ran out of tokens. A truncated answer
+/// with some text is kept, with a warning.
+fn check_finish_reason(finish_reason: Option<&str>, content: &str) -> Result<()> {
+    match finish_reason {
+        Some("content_filter") => Err(Error::api(
+            "OpenAI",
+            "the response was withheld by the content filter (finish_reason: content_filter); try --redact-secrets or a smaller diff",
+        )),
+        Some("length") if content.trim().is_empty() => Err(Error::api(
+            "OpenAI",
+            format!(
+                "the response hit the {MAX_TOKENS}-token limit before any text (finish_reason: length); try a smaller diff or --max-prompt-tokens"
+            ),
+        )),
+        Some("length") => {
+            eprintln!(
+                "Warning: the OpenAI response was truncated at the {MAX_TOKENS}-token limit (finish_reason: length); the message may be incomplete"
+            );
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Extracts the text from a regular (non-streamed) chat completion response
+pub(super) fn parse_completion(text: &str) -> Result<String> {
+    let json: Value = serde_json::from_str(text).map_err(|e| Error::parse("OpenAI", Some(e)))?;
+
+    // Extract text from the standard OpenAI response format
+    if let Some(choice) = json
+        .get("choices")
+        .and_then(Value::as_array)
+        .and_then(|choices| choices.first())
+    {
+        let content = choice
+            .get("message")
+            .and_then(|message| message.get("content"))
+            .and_then(Value::as_str);
+        check_finish_reason(
+            choice.get("finish_reason").and_then(Value::as_str),
+            content.unwrap_or_default(),
+        )?;
+        if let Some(content) = content {
+            return Ok(content.to_string());
+        }
+    }
+
+    // Check for errors
+    if let Some(message) = json
+        .get("error")
+        .and_then(Value::as_object)
+        .and_then(|err| err.get("message"))
+        .and_then(Value::as_str)
+    {
+        return Err(Error::api("OpenAI", message));
+    }
+
+    Err(Error::parse("OpenAI", None))
+}
 
-        let response = self
+impl OpenAIProvider {
+    pub const CAPABILITIES: ProviderCapabilities = ProviderCapabilities {
+        streaming: true,
+        n: false,
+        seed: true,
+        system_prompt: true,
+        temperature: true,
+        usage: true,
+    };
+
+    pub fn new(
+        client: Arc<Client>,
+        base_url: &str,
+        model: &str,
+        api_key: &str,
+        verbose: bool,
+    ) -> Self {
+        Self {
+            client,
+            base_url: base_url.to_string(),
+            model: model.to_string(),
+            api_key: api_key.to_string(),
+            verbose,
+            json_mode: false,
+            seed: None,
+            stream: true,
+            usage: Mutex::new(Usage::default()),
+            rate_limit: Mutex::new(None),
+        }
+    }
+
+    /// Adds one request's usage to the running total
+    fn record_usage(&self, usage: Option<Usage>) {
+        if let Some(usage) = usage {
+            let mut total = self.usage.lock().unwrap();
+            total.prompt_tokens += usage.prompt_tokens;
+            total.completion_tokens += usage.completion_tokens;
+        }
+    }
+
+    /// Request structured JSON output instead of free-form text
+    pub fn with_json_mode(mut self, json_mode: bool) -> Self {
+        self.json_mode = json_mode;
+        self
+    }
+
+    /// Seed for reproducible sampling
+    pub fn with_seed(mut self, seed: Option<u64>) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Stream the response (the default), or ask for it in one piece for
+    /// OpenAI-compatible servers that reject `stream`
+    pub fn with_stream(mut self, stream: bool) -> Self {
+        self.stream = stream;
+        self
+    }
+
+    async fn send_request(&self, prompt: &str, temperature: Option<f32>) -> Result<String> {
+        if self.verbose {
+            eprintln!("Sending request to OpenAI API...");
+        }
+
+        let mut request =
+            OpenAIRequest::new(&self.model, prompt, temperature.unwrap_or(0.7), self.stream)
+                .seed(self.seed);
+        if self.json_mode {
+            request = request.json_object();
+        }
+
+        let mut response = self
             .client
             .post(format!("{}/chat/completions", self.base_url))
             .header("Authorization", format!("Bearer {}", self.api_key))
@@ -75,77 +432,153 @@ impl AiProvider for OpenAIProvider {
             .await
             .map_err(|e| {
                 eprintln!("Failed to connect to OpenAI API: {e}");
-                anyhow!("Connection to OpenAI failed: {}", e)
+                Error::unreachable("OpenAI", e)
             })?;
 
         if self.verbose {
-            println!("OpenAI API response status: {}", response.status());
+            eprintln!("OpenAI API response status: {}", response.status());
         }
-
-        let text = response.text().await?;
-        if self.verbose {
-            println!("Raw response: {text}");
+        if let Some(rate_limit) = parse_rate_limit(response.headers(), Instant::now()) {
+            *self.rate_limit.lock().unwrap() = Some(rate_limit);
         }
 
-        let json: Value = serde_json::from_str(&text)?;
+        let is_event_stream = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
 
-        // Extract text from the standard OpenAI response format
-        if let Some(content) = json
-            .get("choices")
-            .and_then(Value::as_array)
-            .and_then(|choices| choices.first())
-            .and_then(|choice| choice.get("message"))
-            .and_then(|message| message.get("content"))
-            .and_then(Value::as_str)
-        {
-            return Ok(content.to_string());
+        // Errors and servers that ignore `stream` reply with a plain JSON body
+        let status = response.status();
+        if !status.is_success() || !is_event_stream {
+            let text = response
+                .text()
+                .await
+                .map_err(|e| Error::unreachable("OpenAI", e))?;
+            if self.verbose {
+                eprintln!("Raw response: {text}");
+            }
+            if let Some(err) = Error::from_status("OpenAI", status) {
+                return Err(err);
+            }
+            let content = parse_completion(&text)?;
+            self.record_usage(
+                serde_json::from_str(&text)
+                    .ok()
+                    .and_then(|json| parse_usage(&json)),
+            );
+            return Ok(content);
         }
 
-        // Check for errors
-        if let Some(message) = json
-            .get("error")
-            .and_then(Value::as_object)
-            .and_then(|err| err.get("message"))
-            .and_then(Value::as_str)
+        let mut accumulator = SseAccumulator::default();
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| Error::unreachable("OpenAI", e))?
         {
-            return Err(anyhow!("OpenAI API error: {}", message));
+            for delta in accumulator.push(&chunk)? {
+                if self.verbose {
+                    eprint!("{delta}");
+                }
+            }
+        }
+
+        let content = accumulator.finish()?;
+        if self.verbose {
+            eprintln!();
+        }
+        self.record_usage(accumulator.usage);
+
+        Ok(content)
+    }
+}
+
+#[async_trait]
+impl AiProvider for OpenAIProvider {
+    async fn generate_text(&self, prompt: &str) -> Result<String> {
+        self.send_request(prompt, None).await
+    }
+
+    async fn generate_text_with_temperature(
+        &self,
+        prompt: &str,
+        temperature: f32,
+    ) -> Result<String> {
+        self.send_request(prompt, Some(temperature)).await
+    }
+
+    fn tokenizer(&self) -> Box<dyn Tokenizer> {
+        // Custom models behind an OpenAI-compatible URL may not be known to tiktoken
+        match TiktokenTokenizer::for_model(&self.model) {
+            Some(tokenizer) => Box::new(tokenizer),
+            None => Box::new(CharEstimateTokenizer),
         }
+    }
+
+    fn usage(&self) -> Option<Usage> {
+        let total = *self.usage.lock().unwrap();
+        Some(Usage {
+            estimated_cost: estimate_cost(
+                &self.model,
+                total.prompt_tokens,
+                total.completion_tokens,
+            ),
+            ..total
+        })
+    }
+
+    fn rate_limit(&self) -> Option<RateLimit> {
+        *self.rate_limit.lock().unwrap()
+    }
 
-        Err(anyhow!("Failed to parse OpenAI response"))
+    fn capabilities(&self) -> ProviderCapabilities {
+        Self::CAPABILITIES
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::ai::provider_factory::http_client;
 
-    // Helper function to test the response parsing logic directly
-    fn extract_content_from_response(json_str: &str) -> Result<String> {
-        let json: Value = serde_json::from_str(json_str)?;
-
-        // Extract text from the standard OpenAI response format
-        if let Some(content) = json
-            .get("choices")
-            .and_then(Value::as_array)
-            .and_then(|choices| choices.first())
-            .and_then(|choice| choice.get("message"))
-            .and_then(|message| message.get("content"))
-            .and_then(Value::as_str)
-        {
-            return Ok(content.to_string());
-        }
+    #[test]
+    fn test_tokenizer_for_known_and_custom_models() {
+        let provider = OpenAIProvider::new(
+            http_client().unwrap(),
+            "https://api.openai.com",
+            "gpt-4",
+            "key",
+            false,
+        );
+        assert_eq!(provider.tokenizer().count_tokens("hello world"), 2);
 
-        // Check for errors
-        if let Some(message) = json
-            .get("error")
-            .and_then(Value::as_object)
-            .and_then(|err| err.get("message"))
-            .and_then(Value::as_str)
-        {
-            return Err(anyhow!("OpenAI API error: {}", message));
-        }
+        // Unknown models fall back to the character estimate
+        let provider = OpenAIProvider::new(
+            http_client().unwrap(),
+            "http://localhost:1234",
+            "my-local-model",
+            "key",
+            false,
+        );
+        assert_eq!(provider.tokenizer().count_tokens("hello world"), 3);
+    }
 
-        Err(anyhow!("Failed to parse OpenAI response"))
+    #[test]
+    fn test_capabilities() {
+        let provider = OpenAIProvider::new(
+            http_client().unwrap(),
+            "https://api.openai.com",
+            "gpt-4o-mini",
+            "key",
+            false,
+        );
+        let capabilities = provider.capabilities();
+        assert!(capabilities.streaming);
+        assert!(capabilities.seed);
+        assert!(capabilities.system_prompt);
+        assert!(capabilities.temperature);
+        assert!(capabilities.usage);
+        assert!(!capabilities.n);
     }
 
     #[tokio::test]
@@ -153,7 +586,7 @@ mod tests {
         let json_str =
             r#"{"choices":[{"message":{"content":"fix(core): resolve null pointer exception"}}]}"#;
 
-        let result = extract_content_from_response(json_str);
+        let result = parse_completion(json_str);
         assert!(result.is_ok());
         assert_eq!(result.unwrap(), "fix(core): resolve null pointer exception");
     }
@@ -162,11 +595,343 @@ mod tests {
     async fn test_generate_text_error() {
         let json_str = r#"{"error":{"message":"Invalid API key"}}"#;
 
-        let result = extract_content_from_response(json_str);
+        let result = parse_completion(json_str);
         assert!(result.is_err());
         assert_eq!(
             result.unwrap_err().to_string(),
             "OpenAI API error: Invalid API key"
         );
     }
+
+    // Recorded from a streamed chat completion, trimmed to the relevant fields
+    const SSE_TRANSCRIPT: &str = concat!(
+        ": keep-alive\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"feat\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"(api): \"},\"finish_reason\":null}]}\r\n\r\n",
+        "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"add streaming ✨\"},\"finish_reason\":null}]}\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{},\"finish_reason\":\"stop\"}]}\n\n",
+        "data: [DONE]\n\n",
+        "data: {\"id\":\"chatcmpl-1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"ignored\"}}]}\n\n",
+    );
+
+    #[test]
+    fn test_sse_reassembles_transcript() {
+        let mut accumulator = SseAccumulator::default();
+        let deltas = accumulator.push(SSE_TRANSCRIPT.as_bytes()).unwrap();
+
+        assert_eq!(deltas, vec!["feat", "(api): ", "add streaming ✨"]);
+        assert_eq!(accumulator.finish().unwrap(), "feat(api): add streaming ✨");
+    }
+
+    #[test]
+    fn test_sse_handles_lines_split_across_chunks() {
+        // Small chunks split both lines and multi-byte characters
+        let mut accumulator = SseAccumulator::default();
+        for chunk in SSE_TRANSCRIPT.as_bytes().chunks(7) {
+            accumulator.push(chunk).unwrap();
+        }
+
+        assert_eq!(accumulator.finish().unwrap(), "feat(api): add streaming ✨");
+    }
+
+    #[test]
+    fn test_sse_trailing_line_without_newline() {
+        let mut accumulator = SseAccumulator::default();
+        accumulator
+            .push(br#"data: {"choices":[{"delta":{"content":"fix: tail"}}]}"#)
+            .unwrap();
+
+        assert_eq!(accumulator.finish().unwrap(), "fix: tail");
+    }
+
+    #[test]
+    fn test_parse_completion_finish_reasons() {
+        let response = |content: &str, finish_reason: &str| {
+            format!(
+                r#"{{"choices":[{{"message":{{"role":"assistant","content":{content}}},"finish_reason":"{finish_reason}"}}]}}"#
+            )
+        };
+
+        assert_eq!(
+            parse_completion(&response(r#""feat: add login""#, "stop")).unwrap(),
+            "feat: add login"
+        );
+
+        // Truncated text is still used; no text at all is an error
+        assert_eq!(
+            parse_completion(&response(r#""feat: add log""#, "length")).unwrap(),
+            "feat: add log"
+        );
+        let err = parse_completion(&response("\"\"", "length")).unwrap_err();
+        assert!(matches!(&err, Error::ProviderApi { .. }));
+        assert!(err.to_string().contains("finish_reason: length"));
+
+        // Filtered responses come back with null content
+        let err = parse_completion(&response("null", "content_filter")).unwrap_err();
+        assert!(matches!(&err, Error::ProviderApi { .. }));
+        assert!(err.to_string().contains("content filter"));
+    }
+
+    #[test]
+    fn test_sse_finish_reasons() {
+        let stream = |content: &str, finish_reason: &str| {
+            format!(
+                "data: {{\"choices\":[{{\"delta\":{{\"content\":\"{content}\"}},\"finish_reason\":null}}]}}\n\ndata: {{\"choices\":[{{\"delta\":{{}},\"finish_reason\":\"{finish_reason}\"}}]}}\n\ndata: [DONE]\n\n"
+            )
+        };
+        let finish = |transcript: String| {
+            let mut accumulator = SseAccumulator::default();
+            accumulator.push(transcript.as_bytes()).unwrap();
+            accumulator.finish()
+        };
+
+        assert_eq!(finish(stream("fix: tail", "stop")).unwrap(), "fix: tail");
+        assert_eq!(finish(stream("fix: ta", "length")).unwrap(), "fix: ta");
+        assert!(
+            finish(stream("", "length"))
+                .unwrap_err()
+                .to_string()
+                .contains("finish_reason: length")
+        );
+        assert!(
+            finish(stream("", "content_filter"))
+                .unwrap_err()
+                .to_string()
+                .contains("finish_reason: content_filter")
+        );
+    }
+
+    #[test]
+    fn test_parse_completion_error_kinds() {
+        let err = parse_completion(r#"{"error":{"message":"Invalid API key"}}"#).unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::ProviderApi { provider, message }
+                if provider == "OpenAI" && message == "Invalid API key"
+        ));
+
+        let err = parse_completion("<html>Bad Gateway</html>").unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::ParseFailure {
+                source: Some(_),
+                ..
+            }
+        ));
+
+        let err = parse_completion(r#"{"choices":[]}"#).unwrap_err();
+        assert!(matches!(&err, Error::ParseFailure { source: None, .. }));
+    }
+
+    #[test]
+    fn test_sse_error_event() {
+        let mut accumulator = SseAccumulator::default();
+        let result =
+            accumulator.push(b"data: {\"error\":{\"message\":\"Rate limit reached\"}}\n\n");
+
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "OpenAI API error: Rate limit reached"
+        );
+    }
+
+    #[test]
+    fn test_json_mode_request() {
+        let request = OpenAIRequest::new("gpt-4o", "prompt", 0.7, true);
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("response_format").is_none());
+
+        let request = OpenAIRequest::new("gpt-4o", "prompt", 0.7, true).json_object();
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["response_format"]["type"], "json_object");
+        assert!(
+            json["messages"][0]["content"]
+                .as_str()
+                .unwrap()
+                .contains("JSON")
+        );
+        assert_eq!(json["messages"][1]["content"], "prompt");
+    }
+
+    #[test]
+    fn test_request_without_streaming() {
+        let json = serde_json::to_value(OpenAIRequest::new("gpt-4o", "prompt", 0.7, true)).unwrap();
+        assert_eq!(json["stream"], true);
+        assert_eq!(json["stream_options"]["include_usage"], true);
+
+        let json =
+            serde_json::to_value(OpenAIRequest::new("gpt-4o", "prompt", 0.7, false)).unwrap();
+        assert_eq!(json["stream"], false);
+        assert!(json.get("stream_options").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_generate_text_without_streaming() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // Read up to the end of the JSON body
+            while !request.ends_with(b"}") {
+                let n = socket.read(&mut buf).await.unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let body =
+                r#"{"choices":[{"message":{"content":"fix: whole"},"finish_reason":"stop"}]}"#;
+            let response = format!(
+                "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+                body.len()
+            );
+            socket.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let provider =
+            OpenAIProvider::new(http_client().unwrap(), &base_url, "gpt-4o", "key", false)
+                .with_stream(false);
+        assert_eq!(
+            provider.generate_text("prompt").await.unwrap(),
+            "fix: whole"
+        );
+
+        let request = server.await.unwrap();
+        assert!(request.contains(r#""stream":false"#));
+        assert!(!request.contains("stream_options"));
+    }
+
+    #[test]
+    fn test_seed_request() {
+        let request = OpenAIRequest::new("gpt-4o", "prompt", 0.7, true);
+        let json = serde_json::to_value(&request).unwrap();
+        assert!(json.get("seed").is_none());
+
+        let request = OpenAIRequest::new("gpt-4o", "prompt", 0.7, true).seed(Some(42));
+        let json = serde_json::to_value(&request).unwrap();
+        assert_eq!(json["seed"], 42);
+    }
+
+    #[test]
+    fn test_parse_usage() {
+        let json: Value = serde_json::from_str(
+            r#"{"choices":[{"message":{"content":"fix: x"}}],"usage":{"prompt_tokens":812,"completion_tokens":24,"total_tokens":836}}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            parse_usage(&json),
+            Some(Usage {
+                prompt_tokens: 812,
+                completion_tokens: 24,
+                estimated_cost: None,
+            })
+        );
+
+        let json: Value = serde_json::from_str(r#"{"usage":null}"#).unwrap();
+        assert_eq!(parse_usage(&json), None);
+    }
+
+    #[test]
+    fn test_sse_captures_usage_chunk() {
+        let mut accumulator = SseAccumulator::default();
+        accumulator
+            .push(b"data: {\"choices\":[{\"delta\":{\"content\":\"fix: x\"}}],\"usage\":null}\n\n")
+            .unwrap();
+        accumulator
+            .push(b"data: {\"choices\":[],\"usage\":{\"prompt_tokens\":10,\"completion_tokens\":3}}\n\ndata: [DONE]\n\n")
+            .unwrap();
+
+        assert_eq!(accumulator.finish().unwrap(), "fix: x");
+        assert_eq!(
+            accumulator
+                .usage
+                .map(|u| (u.prompt_tokens, u.completion_tokens)),
+            Some((10, 3))
+        );
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        // gpt-4o-mini: $0.15 per million prompt tokens, $0.60 per million completion tokens
+        let cost = estimate_cost("gpt-4o-mini", 1000, 500).unwrap();
+        assert!((cost - 0.00045).abs() < 1e-12);
+
+        // Dated snapshots use their family's price
+        let cost = estimate_cost("gpt-4o-2024-08-06", 1_000_000, 0).unwrap();
+        assert!((cost - 2.5).abs() < 1e-9);
+
+        assert_eq!(estimate_cost("my-local-model", 1000, 500), None);
+    }
+
+    #[test]
+    fn test_usage_totals_across_requests() {
+        let provider = OpenAIProvider::new(
+            http_client().unwrap(),
+            "https://api.openai.com",
+            "gpt-4o-mini",
+            "key",
+            false,
+        );
+        provider.record_usage(Some(Usage {
+            prompt_tokens: 600,
+            completion_tokens: 200,
+            estimated_cost: None,
+        }));
+        provider.record_usage(Some(Usage {
+            prompt_tokens: 400,
+            completion_tokens: 300,
+            estimated_cost: None,
+        }));
+
+        let usage = provider.usage().unwrap();
+        assert_eq!((usage.prompt_tokens, usage.completion_tokens), (1000, 500));
+        assert!((usage.estimated_cost.unwrap() - 0.00045).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_parse_rate_limit_headers() {
+        use reqwest::header::HeaderValue;
+
+        // As sent with a chat completion once the request quota is used up
+        let mut headers = HeaderMap::new();
+        for (name, value) in [
+            ("x-ratelimit-limit-requests", "500"),
+            ("x-ratelimit-limit-tokens", "200000"),
+            ("x-ratelimit-remaining-requests", "0"),
+            ("x-ratelimit-remaining-tokens", "199211"),
+            ("x-ratelimit-reset-requests", "6m0s"),
+            ("x-ratelimit-reset-tokens", "236ms"),
+        ] {
+            headers.insert(name, HeaderValue::from_static(value));
+        }
+
+        let observed_at = Instant::now();
+        assert_eq!(
+            parse_rate_limit(&headers, observed_at),
+            Some(RateLimit {
+                remaining_requests: Some(0),
+                remaining_tokens: Some(199211),
+                reset_requests: Some(Duration::from_secs(360)),
+                reset_tokens: Some(Duration::from_millis(236)),
+                observed_at,
+            })
+        );
+
+        // Servers behind an OpenAI-compatible URL often send none
+        assert_eq!(parse_rate_limit(&HeaderMap::new(), observed_at), None);
+    }
+
+    #[test]
+    fn test_parse_reset() {
+        assert_eq!(parse_reset("20ms"), Some(Duration::from_millis(20)));
+        assert_eq!(parse_reset("1.5s"), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_reset("1h2m3s"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_reset("soon"), None);
+        assert_eq!(parse_reset("12"), None);
+        assert_eq!(parse_reset(""), None);
+    }
 }
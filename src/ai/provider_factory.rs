@@ -1,6 +1,72 @@
-use super::{AiProvider, gemini::GeminiProvider, ollama::OllamaProvider, openai::OpenAIProvider};
+use super::{
+    AiProvider, command::CommandProvider, gemini::GeminiProvider, llamacpp::LlamaCppProvider,
+    ollama::OllamaProvider, openai::OpenAIProvider,
+};
 use crate::Provider;
-use anyhow::{Result, anyhow};
+use crate::error::Error;
+use crate::error::Result;
+use anyhow::anyhow;
+use reqwest::Client;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+/// How long to wait for a provider's server to accept the connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where Ollama listens unless --api-url says otherwise
+pub const OLLAMA_URL: &str = "http://localhost:11434";
+
+/// How long provider auto-detection waits for Ollama to answer
+const PROBE_TIMEOUT: Duration = Duration::from_millis(500);
+
+static HTTP_CLIENT: LazyLock<reqwest::Result<Arc<Client>>> = LazyLock::new(|| {
+    // Proxies are taken from HTTP_PROXY/HTTPS_PROXY/NO_PROXY by default
+    Client::builder()
+        .connect_timeout(CONNECT_TIMEOUT)
+        .build()
+        .map(Arc::new)
+});
+
+/// The HTTP client shared by every provider, so concurrent requests reuse
+/// one connection pool. Fails if it can't be set up, e.g. for a malformed
+/// proxy URL or a TLS backend that won't initialize.
+pub fn http_client() -> Result<Arc<Client>> {
+    match &*HTTP_CLIENT {
+        Ok(client) => Ok(client.clone()),
+        Err(e) => Err(anyhow!("Failed to set up the HTTP client: {e}").into()),
+    }
+}
+
+/// Whether an Ollama server answers at `base_url`, giving up quickly so
+/// auto-detection doesn't hold up the run
+pub async fn ollama_is_running(base_url: &str) -> bool {
+    let url = format!("{}/api/tags", base_url.trim_end_matches('/'));
+    // create_provider reports a client that can't be set up
+    let Ok(client) = http_client() else {
+        return false;
+    };
+    client
+        .get(url)
+        .timeout(PROBE_TIMEOUT)
+        .send()
+        .await
+        .is_ok_and(|response| response.status().is_success())
+}
+
+/// Provider-specific settings that most providers ignore
+#[derive(Debug, Default, Clone)]
+pub struct ProviderOptions {
+    /// Ask OpenAI for structured JSON output
+    pub json_mode: bool,
+    /// How long Ollama keeps the model loaded after a request (e.g. "10m")
+    pub ollama_keep_alive: Option<String>,
+    /// Seed for reproducible sampling (OpenAI and Ollama)
+    pub seed: Option<u64>,
+    /// Ask OpenAI for the whole response at once instead of streaming it
+    pub no_stream: bool,
+    /// Shell command run by the command provider
+    pub command: Option<String>,
+}
 
 /// Creates an AI provider based on the specified provider type
 pub fn create_provider(
@@ -9,26 +75,53 @@ pub fn create_provider(
     api_key: Option<&str>,
     api_url: Option<&str>,
     verbose: bool,
+    options: &ProviderOptions,
 ) -> Result<Box<dyn AiProvider>> {
+    let client = http_client()?;
     match provider_type {
         Provider::Ollama => {
-            let base_url = api_url.unwrap_or("http://localhost:11434");
-            Ok(Box::new(OllamaProvider::new(base_url, model, verbose)))
+            let base_url = api_url.unwrap_or(OLLAMA_URL);
+            Ok(Box::new(
+                OllamaProvider::new(client, base_url, model, verbose)
+                    .with_keep_alive(options.ollama_keep_alive.clone())
+                    .with_seed(options.seed),
+            ))
         }
         Provider::OpenAI => {
-            let api_key = api_key.ok_or_else(|| anyhow!("API key is required for OpenAI"))?;
+            let api_key =
+                api_key.ok_or_else(|| Error::auth("OpenAI", "API key is required for OpenAI"))?;
             let base_url = api_url.unwrap_or("https://api.openai.com/v1");
-            Ok(Box::new(OpenAIProvider::new(
-                base_url, model, api_key, verbose,
-            )))
+            Ok(Box::new(
+                OpenAIProvider::new(client, base_url, model, api_key, verbose)
+                    .with_json_mode(options.json_mode)
+                    .with_seed(options.seed)
+                    .with_stream(!options.no_stream),
+            ))
         }
         Provider::Gemini => {
-            let api_key = api_key.ok_or_else(|| anyhow!("API key is required for Gemini"))?;
+            let api_key =
+                api_key.ok_or_else(|| Error::auth("Gemini", "API key is required for Gemini"))?;
             let base_url = api_url.unwrap_or("https://generativelanguage.googleapis.com");
             Ok(Box::new(GeminiProvider::new(
-                base_url, model, api_key, verbose,
+                client, base_url, model, api_key, verbose,
             )))
         }
+        Provider::LlamaCpp => {
+            let base_url = api_url.unwrap_or("http://localhost:8080");
+            Ok(Box::new(LlamaCppProvider::new(
+                client, base_url, model, api_key, verbose,
+            )))
+        }
+        Provider::Command => {
+            let command = options
+                .command
+                .as_deref()
+                .filter(|command| !command.trim().is_empty())
+                .ok_or_else(|| {
+                    anyhow!("--provider-command is required for the command provider")
+                })?;
+            Ok(Box::new(CommandProvider::new(command, verbose)))
+        }
     }
 }
 
@@ -45,6 +138,7 @@ mod tests {
             None,
             Some("http://test-url:11434"),
             false,
+            &ProviderOptions::default(),
         );
 
         assert!(provider.is_ok());
@@ -60,6 +154,7 @@ mod tests {
             None,
             None, // No URL provided - should use default
             true, // With verbose turned on
+            &ProviderOptions::default(),
         );
 
         assert!(provider.is_ok());
@@ -73,6 +168,7 @@ mod tests {
             Some("test-api-key"),
             Some("https://test-openai-url"),
             false,
+            &ProviderOptions::default(),
         );
 
         assert!(provider.is_ok());
@@ -86,6 +182,7 @@ mod tests {
             Some("test-api-key"),
             None, // No URL provided - should use default
             true, // With verbose turned on
+            &ProviderOptions::default(),
         );
 
         assert!(provider.is_ok());
@@ -99,13 +196,50 @@ mod tests {
             None,
             Some("https://test-openai-url"),
             false,
+            &ProviderOptions::default(),
         );
 
         assert!(provider.is_err());
-        assert_eq!(
-            provider.unwrap_err().to_string(),
-            "API key is required for OpenAI"
+        let err = provider.unwrap_err();
+        assert_eq!(err.to_string(), "API key is required for OpenAI");
+        assert!(matches!(
+            &err,
+            Error::ProviderAuth { provider, .. } if provider == "OpenAI"
+        ));
+    }
+
+    #[test]
+    fn test_create_openai_provider_json_mode() {
+        let provider = create_provider(
+            Provider::OpenAI,
+            "gpt-4o",
+            Some("test-api-key"),
+            None,
+            false,
+            &ProviderOptions {
+                json_mode: true,
+                ..Default::default()
+            },
         );
+
+        assert!(provider.is_ok());
+    }
+
+    #[test]
+    fn test_create_ollama_provider_keep_alive() {
+        let provider = create_provider(
+            Provider::Ollama,
+            "llama3",
+            None,
+            None,
+            false,
+            &ProviderOptions {
+                ollama_keep_alive: Some("10m".to_string()),
+                ..Default::default()
+            },
+        );
+
+        assert!(provider.is_ok());
     }
 
     #[test]
@@ -116,6 +250,7 @@ mod tests {
             Some("test-api-key"),
             Some("https://test-gemini-url"),
             false,
+            &ProviderOptions::default(),
         );
 
         assert!(provider.is_ok());
@@ -129,6 +264,7 @@ mod tests {
             Some("test-api-key"),
             None, // No URL provided - should use default
             true, // With verbose turned on
+            &ProviderOptions::default(),
         );
 
         assert!(provider.is_ok());
@@ -142,6 +278,7 @@ mod tests {
             None,
             Some("https://test-gemini-url"),
             false,
+            &ProviderOptions::default(),
         );
 
         assert!(provider.is_err());
@@ -150,4 +287,113 @@ mod tests {
             "API key is required for Gemini"
         );
     }
+
+    #[test]
+    fn test_create_llamacpp_provider() {
+        // No API key needed, default URL
+        let provider = create_provider(
+            Provider::LlamaCpp,
+            "default",
+            None,
+            None,
+            false,
+            &ProviderOptions::default(),
+        );
+        assert!(provider.is_ok());
+
+        let provider = create_provider(
+            Provider::LlamaCpp,
+            "default",
+            Some("server-key"),
+            Some("http://gpu-box:8080"),
+            true,
+            &ProviderOptions::default(),
+        );
+        assert!(provider.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_providers_share_connections() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}", listener.local_addr().unwrap());
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                accepted.fetch_add(1, Ordering::SeqCst);
+                // Answers every request on the connection, keeping it open
+                tokio::spawn(async move {
+                    let mut buf = [0; 4096];
+                    loop {
+                        let mut request = Vec::new();
+                        // Read up to the end of the JSON body
+                        while !request.ends_with(b"}") {
+                            match socket.read(&mut buf).await {
+                                Ok(0) | Err(_) => return,
+                                Ok(n) => request.extend_from_slice(&buf[..n]),
+                            }
+                        }
+                        let body = r#"{"response":"feat: add login","done":true}"#;
+                        let response = format!(
+                            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{body}",
+                            body.len()
+                        );
+                        socket.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        let options = ProviderOptions::default();
+        for _ in 0..2 {
+            let provider = create_provider(
+                Provider::Ollama,
+                "llama3",
+                None,
+                Some(&base_url),
+                false,
+                &options,
+            )
+            .unwrap();
+            assert_eq!(
+                provider.generate_text("prompt").await.unwrap(),
+                "feat: add login"
+            );
+        }
+        // The second provider got the first one's connection from the pool
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_create_command_provider() {
+        let provider = create_provider(
+            Provider::Command,
+            "default",
+            None,
+            None,
+            false,
+            &ProviderOptions {
+                command: Some("cat".to_string()),
+                ..Default::default()
+            },
+        );
+        assert!(provider.is_ok());
+
+        let provider = create_provider(
+            Provider::Command,
+            "default",
+            None,
+            None,
+            false,
+            &ProviderOptions::default(),
+        );
+        assert_eq!(
+            provider.unwrap_err().to_string(),
+            "--provider-command is required for the command provider"
+        );
+    }
 }
@@ -0,0 +1,101 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Limits requests to a steady rate with a token bucket: each request takes
+/// a token, tokens come back at `per_minute` a minute, and up to `capacity`
+/// of them can be saved up for a burst. Share one bucket (in an `Arc`)
+/// between everything that should count against the same limit; the state
+/// lives in memory, so it only covers one process.
+#[derive(Debug)]
+pub struct TokenBucket {
+    capacity: f64,
+    per_second: f64,
+    /// Tokens available as of the instant, negative when requests are
+    /// already queued for tokens that haven't come back yet
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// A full bucket of `capacity` tokens refilling at `per_minute` a minute
+    pub fn new(capacity: u32, per_minute: u32) -> Self {
+        let capacity = f64::from(capacity.max(1));
+        Self {
+            capacity,
+            per_second: f64::from(per_minute.max(1)) / 60.0,
+            state: Mutex::new((capacity, Instant::now())),
+        }
+    }
+
+    /// Takes a token at `now`, returning how long to wait before using it.
+    /// Requests that have to wait are queued in the order they asked.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let (tokens, updated) = *state;
+        let refilled = now.saturating_duration_since(updated).as_secs_f64() * self.per_second;
+        let tokens = (tokens + refilled).min(self.capacity) - 1.0;
+        *state = (tokens, now.max(updated));
+
+        if tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-tokens / self.per_second)
+        }
+    }
+
+    /// Waits until a request may be sent
+    pub async fn acquire(&self) {
+        let wait = self.reserve(Instant::now());
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_steady_rate() {
+        // 60 a minute is one a second, with a burst of two
+        let bucket = TokenBucket::new(2, 60);
+        let start = bucket.state.lock().unwrap().1;
+
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        // Queued requests are spaced a second apart
+        assert_eq!(bucket.reserve(start), Duration::from_secs(1));
+        assert_eq!(bucket.reserve(start), Duration::from_secs(2));
+
+        // By then the queue has drained and one token came back
+        let later = start + Duration::from_secs(3);
+        assert_eq!(bucket.reserve(later), Duration::ZERO);
+        assert_eq!(bucket.reserve(later), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_refill_is_capped() {
+        let bucket = TokenBucket::new(1, 30);
+        let start = bucket.state.lock().unwrap().1;
+
+        assert_eq!(bucket.reserve(start), Duration::ZERO);
+        assert_eq!(bucket.reserve(start), Duration::from_secs(2));
+
+        // A long idle spell saves up no more than the capacity
+        let idle = start + Duration::from_secs(600);
+        assert_eq!(bucket.reserve(idle), Duration::ZERO);
+        assert_eq!(bucket.reserve(idle), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_acquire_waits_for_a_token() {
+        // One every 50ms
+        let bucket = TokenBucket::new(1, 1200);
+        let start = Instant::now();
+
+        bucket.acquire().await;
+        bucket.acquire().await;
+        bucket.acquire().await;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,132 @@
+use std::fmt::{self, Debug};
+use tiktoken_rs::CoreBPE;
+
+/// Rough number of characters per token used when no real tokenizer is known
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Counts prompt tokens the way a provider's model would
+pub trait Tokenizer: Send + Sync + Debug {
+    fn count_tokens(&self, text: &str) -> usize;
+
+    /// Keeps as many whole leading lines of `text` as fit in `max_tokens`
+    fn truncate(&self, text: &str, max_tokens: usize) -> String {
+        if self.count_tokens(text) <= max_tokens {
+            return text.to_string();
+        }
+
+        let lines: Vec<&str> = text.lines().collect();
+        // Binary search for the longest prefix of lines that still fits
+        let (mut low, mut high) = (0, lines.len());
+        while low < high {
+            let mid = (low + high).div_ceil(2);
+            if self.count_tokens(&lines[..mid].join("\n")) <= max_tokens {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+
+        lines[..low].join("\n")
+    }
+}
+
+/// Estimates tokens from the character count, for models without a known tokenizer
+#[derive(Debug, Default)]
+pub struct CharEstimateTokenizer;
+
+impl Tokenizer for CharEstimateTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        text.chars().count().div_ceil(CHARS_PER_TOKEN)
+    }
+}
+
+/// Exact token counts for OpenAI models using their BPE encoding
+pub struct TiktokenTokenizer {
+    bpe: CoreBPE,
+}
+
+impl TiktokenTokenizer {
+    /// Returns None if the model's encoding isn't known to tiktoken
+    pub fn for_model(model: &str) -> Option<Self> {
+        tiktoken_rs::get_bpe_from_model(model)
+            .ok()
+            .map(|bpe| Self { bpe })
+    }
+}
+
+impl Debug for TiktokenTokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TiktokenTokenizer").finish_non_exhaustive()
+    }
+}
+
+impl Tokenizer for TiktokenTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE_PROMPT: &str = "Generate 1 alternative commit message(s) for the following changes.\n\nDiff:\n```\n--- a/src/main.rs\n+++ b/src/main.rs\n@@ -1,3 +1,3 @@\n-fn main() {}\n+fn main() { println!(\"hello\"); }\n```\n";
+
+    #[test]
+    fn test_char_estimate_counts() {
+        let tokenizer = CharEstimateTokenizer;
+        assert_eq!(tokenizer.count_tokens(""), 0);
+        assert_eq!(tokenizer.count_tokens("abcd"), 1);
+        assert_eq!(tokenizer.count_tokens("abcde"), 2);
+        // Counts characters, not bytes
+        assert_eq!(tokenizer.count_tokens("éééé"), 1);
+    }
+
+    #[test]
+    fn test_tiktoken_counts() {
+        let tokenizer = TiktokenTokenizer::for_model("gpt-4").unwrap();
+        assert_eq!(tokenizer.count_tokens(""), 0);
+        assert_eq!(tokenizer.count_tokens("hello world"), 2);
+    }
+
+    #[test]
+    fn test_tiktoken_unknown_model() {
+        assert!(TiktokenTokenizer::for_model("qwen2.5-coder").is_none());
+    }
+
+    #[test]
+    fn test_sample_prompt_counts_are_comparable() {
+        let estimate = CharEstimateTokenizer.count_tokens(SAMPLE_PROMPT);
+        let exact = TiktokenTokenizer::for_model("gpt-4o")
+            .unwrap()
+            .count_tokens(SAMPLE_PROMPT);
+
+        assert!(exact > 0);
+        // The estimate is a heuristic, but should be in the same ballpark
+        assert!(estimate * 2 >= exact && exact * 2 >= estimate);
+    }
+
+    #[test]
+    fn test_truncate_keeps_text_that_fits() {
+        let tokenizer = CharEstimateTokenizer;
+        assert_eq!(tokenizer.truncate(SAMPLE_PROMPT, 1000), SAMPLE_PROMPT);
+    }
+
+    #[test]
+    fn test_truncate_keeps_whole_lines() {
+        let text = "aaaa\nbbbb\ncccc\ndddd";
+        // Two lines plus the newline between them is 9 chars, i.e. 3 tokens
+        assert_eq!(CharEstimateTokenizer.truncate(text, 3), "aaaa\nbbbb");
+        assert_eq!(CharEstimateTokenizer.truncate(text, 0), "");
+    }
+
+    #[test]
+    fn test_tiktoken_truncate_fits_budget() {
+        let tokenizer = TiktokenTokenizer::for_model("gpt-4").unwrap();
+        let truncated = tokenizer.truncate(SAMPLE_PROMPT, 20);
+
+        assert!(tokenizer.count_tokens(&truncated) <= 20);
+        assert!(SAMPLE_PROMPT.starts_with(&truncated));
+        assert!(!truncated.is_empty());
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::Provider;
+
+/// Aliases available without a config file, per provider
+const BUILTIN_ALIASES: &[(Provider, &str, &str)] = &[
+    (Provider::OpenAI, "fast", "gpt-4o-mini"),
+    (Provider::OpenAI, "smart", "gpt-4o"),
+    (Provider::Gemini, "fast", "gemini-2.0-flash-lite"),
+    (Provider::Gemini, "smart", "gemini-2.5-pro"),
+    (Provider::Ollama, "fast", "qwen2.5-coder:3b"),
+    (Provider::Ollama, "smart", "qwen2.5-coder:32b"),
+];
+
+/// An `[aliases]` entry: either a model for every provider, or a table of
+/// aliases that only apply to the provider it's named after
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(untagged)]
+enum AliasEntry {
+    Model(String),
+    Provider(HashMap<String, String>),
+}
+
+/// Settings read from the config file
+///
+/// ```toml
+/// types = ["feat", "fix", "chore"]
+///
+/// [aliases]
+/// fast = "gpt-4o-mini"
+///
+/// [aliases.ollama]
+/// fast = "llama3.2:1b"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    aliases: HashMap<String, AliasEntry>,
+    types: Vec<String>,
+}
+
+impl Config {
+    /// Loads the config file, or an empty config if there is none
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let Some(path) = path.map(Path::to_path_buf).or_else(default_path) else {
+            return Ok(Self::default());
+        };
+
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents)
+                .with_context(|| format!("Invalid config file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("Failed to read config file {}", path.display()))
+            }
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        Ok(toml::from_str(contents)?)
+    }
+
+    /// The commit types to allow, if the config restricts them
+    pub fn types(&self) -> &[String] {
+        &self.types
+    }
+
+    /// Expands a model alias for the provider, passing unknown names through
+    ///
+    /// Provider-specific aliases win over ones for every provider, and
+    /// configured aliases win over the built-in ones.
+    pub fn resolve_model(&self, provider: Provider, model: &str) -> String {
+        let provider_name = provider
+            .to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_default();
+
+        let for_provider = match self.aliases.get(&provider_name) {
+            Some(AliasEntry::Provider(aliases)) => aliases.get(model),
+            _ => None,
+        };
+        let for_all = match self.aliases.get(model) {
+            Some(AliasEntry::Model(resolved)) => Some(resolved),
+            _ => None,
+        };
+        let builtin = BUILTIN_ALIASES
+            .iter()
+            .find(|(builtin_provider, alias, _)| *builtin_provider == provider && *alias == model)
+            .map(|(_, _, resolved)| resolved.to_string());
+
+        for_provider
+            .or(for_all)
+            .cloned()
+            .or(builtin)
+            .unwrap_or_else(|| model.to_string())
+    }
+}
+
+/// `$XDG_CONFIG_HOME/rs-git-msg/config.toml`, or under `~/.config`
+fn default_path() -> Option<PathBuf> {
+    let config_dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(config_dir.join("rs-git-msg").join("config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_aliases() {
+        let config = Config::default();
+        assert_eq!(
+            config.resolve_model(Provider::OpenAI, "fast"),
+            "gpt-4o-mini"
+        );
+        assert_eq!(config.resolve_model(Provider::OpenAI, "smart"), "gpt-4o");
+        assert_eq!(
+            config.resolve_model(Provider::Gemini, "fast"),
+            "gemini-2.0-flash-lite"
+        );
+    }
+
+    #[test]
+    fn test_unknown_model_passes_through() {
+        let config = Config::default();
+        assert_eq!(config.resolve_model(Provider::OpenAI, "gpt-4.1"), "gpt-4.1");
+        assert_eq!(config.resolve_model(Provider::Ollama, "llama3"), "llama3");
+        // Built-in aliases only exist for providers with known model names
+        assert_eq!(config.resolve_model(Provider::LlamaCpp, "fast"), "fast");
+    }
+
+    #[test]
+    fn test_configured_aliases() {
+        let config = Config::parse(
+            r#"
+            [aliases]
+            fast = "gpt-4.1-nano"
+            review = "o3"
+
+            [aliases.ollama]
+            fast = "llama3.2:1b"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.resolve_model(Provider::OpenAI, "review"), "o3");
+        // Configured aliases override the built-in ones
+        assert_eq!(
+            config.resolve_model(Provider::OpenAI, "fast"),
+            "gpt-4.1-nano"
+        );
+        // Provider-specific aliases override the ones for every provider
+        assert_eq!(
+            config.resolve_model(Provider::Ollama, "fast"),
+            "llama3.2:1b"
+        );
+        assert_eq!(
+            config.resolve_model(Provider::Ollama, "smart"),
+            "qwen2.5-coder:32b"
+        );
+        // A provider table is not itself a model name
+        assert_eq!(config.resolve_model(Provider::OpenAI, "ollama"), "ollama");
+    }
+
+    #[test]
+    fn test_configured_types() {
+        let config = Config::parse(
+            r#"
+            types = ["feat", "fix", "chore"]
+
+            [aliases]
+            fast = "gpt-4.1-nano"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.types(), ["feat", "fix", "chore"]);
+
+        assert!(Config::default().types().is_empty());
+        assert!(Config::parse("types = \"feat\"").is_err());
+    }
+
+    #[test]
+    fn test_load_config_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("config.toml");
+
+        // A missing file is an empty config
+        let config = Config::load(Some(&path)).unwrap();
+        assert_eq!(
+            config.resolve_model(Provider::OpenAI, "fast"),
+            "gpt-4o-mini"
+        );
+
+        std::fs::write(&path, "[aliases]\nfast = \"gpt-4.1-mini\"\n").unwrap();
+        let config = Config::load(Some(&path)).unwrap();
+        assert_eq!(
+            config.resolve_model(Provider::OpenAI, "fast"),
+            "gpt-4.1-mini"
+        );
+
+        std::fs::write(&path, "[aliases\n").unwrap();
+        let err = Config::load(Some(&path)).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid config file"));
+    }
+}
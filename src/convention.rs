@@ -0,0 +1,166 @@
+use anyhow::{Context, Result};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// commitlint config files looked for at the repository root, in order.
+/// Only JSON configs are understood.
+pub const COMMITLINT_FILES: &[&str] = &[".commitlintrc", ".commitlintrc.json"];
+
+/// Rules from the repository's commit convention that the prompt should
+/// follow, read from a commitlint config:
+///
+/// ```json
+/// {
+///   "rules": {
+///     "type-enum": [2, "always", ["feat", "fix", "chore"]],
+///     "header-max-length": [2, "always", 50]
+///   }
+/// }
+/// ```
+///
+/// Rules that are disabled (level 0) or set to "never" are ignored.
+#[derive(Debug, Default, PartialEq)]
+pub struct Convention {
+    /// Allowed commit types, from `type-enum`
+    pub types: Vec<String>,
+    /// Longest allowed first line, from `header-max-length`
+    pub header_max_length: Option<usize>,
+}
+
+impl Convention {
+    /// Loads `path` if given, otherwise the first commitlint config at the
+    /// repository root, if there is one
+    pub fn load(repo_root: Option<&Path>, path: Option<&Path>) -> Result<Option<Self>> {
+        let Some(path) = path
+            .map(Path::to_path_buf)
+            .or_else(|| find_config(repo_root?))
+        else {
+            return Ok(None);
+        };
+
+        let contents = std::fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Self::parse(&contents)
+            .map(Some)
+            .with_context(|| format!("Invalid commit convention file {}", path.display()))
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let config: Value = serde_json::from_str(contents)?;
+        let rules = &config["rules"];
+
+        let types = enabled_rule_value(&rules["type-enum"])
+            .and_then(Value::as_array)
+            .map(|types| {
+                types
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let header_max_length = enabled_rule_value(&rules["header-max-length"])
+            .and_then(Value::as_u64)
+            .and_then(|length| usize::try_from(length).ok());
+
+        Ok(Self {
+            types,
+            header_max_length,
+        })
+    }
+}
+
+fn find_config(repo_root: &Path) -> Option<PathBuf> {
+    COMMITLINT_FILES
+        .iter()
+        .map(|name| repo_root.join(name))
+        .find(|path| path.is_file())
+}
+
+/// The value of a commitlint rule written as `[level, "always", value]`
+fn enabled_rule_value(rule: &Value) -> Option<&Value> {
+    let [level, applicable, value] = rule.as_array()?.as_slice() else {
+        return None;
+    };
+    (level.as_u64()? > 0 && applicable.as_str()? == "always").then_some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_type_enum() {
+        let convention = Convention::parse(
+            r#"{
+                "extends": ["@commitlint/config-conventional"],
+                "rules": {
+                    "type-enum": [2, "always", ["feat", "fix", "chore", "release"]],
+                    "header-max-length": [2, "always", 50]
+                }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            convention,
+            Convention {
+                types: vec![
+                    "feat".to_string(),
+                    "fix".to_string(),
+                    "chore".to_string(),
+                    "release".to_string()
+                ],
+                header_max_length: Some(50),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_ignores_disabled_rules() {
+        let convention = Convention::parse(
+            r#"{"rules": {
+                "type-enum": [0, "always", ["feat"]],
+                "header-max-length": [2, "never", 50]
+            }}"#,
+        )
+        .unwrap();
+        assert_eq!(convention, Convention::default());
+
+        // Only extending a shared config sets no rules of its own
+        let convention =
+            Convention::parse(r#"{"extends": ["@commitlint/config-conventional"]}"#).unwrap();
+        assert_eq!(convention, Convention::default());
+
+        assert!(Convention::parse("extends: ['@commitlint/config-conventional']").is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(Convention::load(Some(dir.path()), None).unwrap(), None);
+        assert_eq!(Convention::load(None, None).unwrap(), None);
+
+        std::fs::write(
+            dir.path().join(".commitlintrc.json"),
+            r#"{"rules": {"type-enum": [2, "always", ["feat", "fix"]]}}"#,
+        )
+        .unwrap();
+        let convention = Convention::load(Some(dir.path()), None).unwrap().unwrap();
+        assert_eq!(convention.types, vec!["feat", "fix"]);
+
+        // A configured file wins over the repository's
+        let other = dir.path().join("convention.json");
+        std::fs::write(
+            &other,
+            r#"{"rules": {"type-enum": [2, "always", ["docs"]]}}"#,
+        )
+        .unwrap();
+        let convention = Convention::load(Some(dir.path()), Some(&other))
+            .unwrap()
+            .unwrap();
+        assert_eq!(convention.types, vec!["docs"]);
+
+        let err = Convention::load(None, Some(&dir.path().join("missing.json"))).unwrap_err();
+        assert!(err.to_string().starts_with("Failed to read "));
+    }
+}
@@ -0,0 +1,189 @@
+use anyhow::{Context, Result, bail};
+use std::ffi::OsStr;
+use std::io::Write;
+use tokio::process::Command;
+
+/// Editor used when none is configured, if it can be found on the `PATH`
+const DEFAULT_EDITOR: &str = if cfg!(windows) { "notepad" } else { "vi" };
+
+/// Picks the first configured editor (`$EDITOR`, `$VISUAL`, then git's
+/// `core.editor`), falling back to `vi` (`notepad` on Windows) when it is on
+/// the `PATH`
+pub fn resolve_editor(
+    configured: &[Option<String>],
+    search_path: Option<&OsStr>,
+) -> Result<String> {
+    if let Some(editor) = configured
+        .iter()
+        .flatten()
+        .map(|editor| editor.trim())
+        .find(|editor| !editor.is_empty())
+    {
+        return Ok(editor.to_string());
+    }
+
+    let executable = if cfg!(windows) {
+        format!("{DEFAULT_EDITOR}.exe")
+    } else {
+        DEFAULT_EDITOR.to_string()
+    };
+    let found = search_path
+        .map(|paths| std::env::split_paths(paths).any(|dir| dir.join(&executable).is_file()))
+        .unwrap_or(false);
+    if !found {
+        bail!(
+            "No editor found for --edit. Set $EDITOR (or git's core.editor), or install {DEFAULT_EDITOR}"
+        );
+    }
+    Ok(DEFAULT_EDITOR.to_string())
+}
+
+/// Opens `message` in `editor` through a temporary file and returns the
+/// edited text. The other candidates are listed below it as `#` comments,
+/// which are dropped along with the rest of git's comment lines.
+pub async fn edit_message(editor: &str, message: &str, others: &[String]) -> Result<String> {
+    let mut file = tempfile::Builder::new()
+        .prefix("rs-git-msg-")
+        .suffix(".txt")
+        .tempfile()
+        .context("Failed to create a temporary file for --edit")?;
+    file.write_all(edit_template(message, others).as_bytes())
+        .and_then(|_| file.flush())
+        .context("Failed to write the message to edit")?;
+
+    // Run the editor through the shell like git does, so `$EDITOR` may carry
+    // its own arguments (e.g. `code --wait`)
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell
+            .arg("/C")
+            .arg(format!("{editor} \"{}\"", file.path().display()));
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell
+            .arg("-c")
+            .arg(format!("{editor} \"$@\""))
+            .arg(editor)
+            .arg(file.path());
+        shell
+    };
+    let status = shell
+        .status()
+        .await
+        .with_context(|| format!("Failed to run editor `{editor}`"))?;
+    if !status.success() {
+        bail!("Editor `{editor}` exited with {status}");
+    }
+
+    let edited =
+        std::fs::read_to_string(file.path()).context("Failed to read the edited message")?;
+    let edited = strip_comments(&edited);
+    if edited.is_empty() {
+        bail!("Aborting: the edited message is empty");
+    }
+    Ok(edited)
+}
+
+fn edit_template(message: &str, others: &[String]) -> String {
+    let mut text = format!(
+        "{message}\n\n# Edit the message above. Lines starting with '#' are ignored,\n# and an empty message aborts.\n"
+    );
+    if !others.is_empty() {
+        text.push_str("#\n# Other candidates:\n");
+        for other in others {
+            for line in other.lines() {
+                text.push_str(&format!("#   {line}\n"));
+            }
+        }
+    }
+    text
+}
+
+/// Drops `#` comment lines and surrounding blank lines
+pub fn strip_comments(text: &str) -> String {
+    text.lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim()
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_editor_order() {
+        let configured = [None, Some("nano".to_string()), Some("vim".to_string())];
+        assert_eq!(resolve_editor(&configured, None).unwrap(), "nano");
+
+        // Blank values count as unset
+        let configured = [
+            Some("  ".to_string()),
+            None,
+            Some("code --wait".to_string()),
+        ];
+        assert_eq!(resolve_editor(&configured, None).unwrap(), "code --wait");
+    }
+
+    #[test]
+    fn test_resolve_editor_default() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let err = resolve_editor(&[None, None], Some(dir.path().as_os_str())).unwrap_err();
+        assert!(err.to_string().starts_with("No editor found for --edit"));
+        assert!(resolve_editor(&[], None).is_err());
+
+        let executable = if cfg!(windows) { "notepad.exe" } else { "vi" };
+        std::fs::write(dir.path().join(executable), "").unwrap();
+        assert_eq!(
+            resolve_editor(&[None], Some(dir.path().as_os_str())).unwrap(),
+            DEFAULT_EDITOR
+        );
+    }
+
+    #[test]
+    fn test_strip_comments() {
+        let template = edit_template("feat: add login", &["fix: a\n\nbody".to_string()]);
+        assert_eq!(strip_comments(&template), "feat: add login");
+        assert_eq!(
+            strip_comments("\nfeat: add login\n# note\n\nDetails\n"),
+            "feat: add login\n\nDetails"
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_edit_message_uses_edited_content() {
+        let dir = tempfile::TempDir::new().unwrap();
+        // A stub editor that rewrites the subject in place
+        let script = dir.path().join("editor.sh");
+        std::fs::write(
+            &script,
+            "#!/bin/sh\nsed 's/^feat: add login$/feat(auth): add login form/' \"$1\" > \"$1.tmp\" && mv \"$1.tmp\" \"$1\"\n",
+        )
+        .unwrap();
+        let editor = format!("sh {}", script.display());
+
+        let edited = edit_message(&editor, "feat: add login", &["fix: other".to_string()])
+            .await
+            .unwrap();
+        assert_eq!(edited, "feat(auth): add login form");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_edit_message_errors() {
+        let err = edit_message("false", "feat: add login", &[])
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("Editor `false` exited with"));
+
+        // Emptying the file aborts, like `git commit`
+        let err = edit_message("truncate -s 0", "feat: add login", &[])
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Aborting: the edited message is empty");
+    }
+}
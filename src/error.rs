@@ -0,0 +1,204 @@
+use serde_json::{Value, json};
+use thiserror::Error;
+
+/// Failure kinds callers may want to handle differently. `Repository` and
+/// the AI providers return it (see `Result`), so match on it directly; once
+/// it is in an `anyhow::Error`, find it with `err.downcast_ref::<Error>()`.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("No staged changes found")]
+    NoStagedChanges,
+
+    /// Missing or rejected credentials
+    #[error("{message}")]
+    ProviderAuth { provider: String, message: String },
+
+    #[error("Connection to {provider} failed: {source}")]
+    ProviderUnreachable {
+        provider: String,
+        #[source]
+        source: reqwest::Error,
+    },
+
+    /// The provider answered with an error message of its own
+    #[error("{provider} API error: {message}")]
+    ProviderApi { provider: String, message: String },
+
+    #[error("Failed to parse {provider} response")]
+    ParseFailure {
+        provider: String,
+        #[source]
+        source: Option<serde_json::Error>,
+    },
+
+    #[error("Git error: {0}")]
+    Git(#[from] git2::Error),
+
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    /// Anything without a kind of its own
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+/// Result of `Repository` and the AI providers
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    pub fn auth(provider: &str, message: impl Into<String>) -> Self {
+        Self::ProviderAuth {
+            provider: provider.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn unreachable(provider: &str, source: reqwest::Error) -> Self {
+        Self::ProviderUnreachable {
+            provider: provider.to_string(),
+            source,
+        }
+    }
+
+    pub fn api(provider: &str, message: impl Into<String>) -> Self {
+        Self::ProviderApi {
+            provider: provider.to_string(),
+            message: message.into(),
+        }
+    }
+
+    pub fn parse(provider: &str, source: Option<serde_json::Error>) -> Self {
+        Self::ParseFailure {
+            provider: provider.to_string(),
+            source,
+        }
+    }
+
+    /// Stable name of the failure kind, for machine-readable output
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::NoStagedChanges => "no_staged_changes",
+            Self::ProviderAuth { .. } => "provider_auth",
+            Self::ProviderUnreachable { .. } => "provider_unreachable",
+            Self::ProviderApi { .. } => "provider_api",
+            Self::ParseFailure { .. } => "parse_failure",
+            Self::Git(_) => "git",
+            Self::Io(_) => "io",
+            // Context may have been added to a typed error
+            Self::Other(err) => err.downcast_ref::<Error>().map_or("other", Error::kind),
+        }
+    }
+
+    fn provider(&self) -> Option<&str> {
+        match self {
+            Self::ProviderAuth { provider, .. }
+            | Self::ProviderUnreachable { provider, .. }
+            | Self::ProviderApi { provider, .. }
+            | Self::ParseFailure { provider, .. } => Some(provider),
+            Self::Other(err) => err.downcast_ref::<Error>().and_then(Error::provider),
+            Self::NoStagedChanges | Self::Git(_) | Self::Io(_) => None,
+        }
+    }
+
+    /// Returns an auth error for HTTP 401/403 responses
+    pub fn from_status(provider: &str, status: reqwest::StatusCode) -> Option<Self> {
+        match status {
+            reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => Some(Self::auth(
+                provider,
+                format!("{provider} rejected the API key ({status})"),
+            )),
+            _ => None,
+        }
+    }
+}
+
+/// Describes an error as `{"error": ..., "kind": ...}`, plus the provider
+/// for provider errors. Errors without a known kind are `"other"`.
+pub fn to_json(err: &anyhow::Error) -> Value {
+    let typed = err.downcast_ref::<Error>();
+    let mut json = json!({
+        "error": format!("{err:#}"),
+        "kind": typed.map_or("other", Error::kind),
+    });
+    if let Some(provider) = typed.and_then(Error::provider) {
+        json["provider"] = json!(provider);
+    }
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_messages() {
+        assert_eq!(
+            Error::NoStagedChanges.to_string(),
+            "No staged changes found"
+        );
+        assert_eq!(
+            Error::api("OpenAI", "Invalid API key").to_string(),
+            "OpenAI API error: Invalid API key"
+        );
+        assert_eq!(
+            Error::parse("Gemini", None).to_string(),
+            "Failed to parse Gemini response"
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let err = anyhow::Error::from(Error::auth("OpenAI", "API key is required for OpenAI"));
+        assert_eq!(
+            to_json(&err),
+            json!({
+                "error": "API key is required for OpenAI",
+                "kind": "provider_auth",
+                "provider": "OpenAI",
+            })
+        );
+
+        // Context is kept in the message without hiding the kind
+        let err = anyhow::Error::from(Error::NoStagedChanges)
+            .context("Failed to generate commit message");
+        assert_eq!(
+            to_json(&err),
+            json!({
+                "error": "Failed to generate commit message: No staged changes found",
+                "kind": "no_staged_changes",
+            })
+        );
+
+        let err = anyhow::anyhow!("something else");
+        assert_eq!(
+            to_json(&err),
+            json!({"error": "something else", "kind": "other"})
+        );
+
+        // A typed error passed back through `Error::Other` keeps its kind
+        let err = anyhow::Error::from(Error::from(
+            anyhow::Error::from(Error::api("OpenAI", "overloaded")).context("Ollama failed too"),
+        ));
+        assert_eq!(
+            to_json(&err),
+            json!({
+                "error": "Ollama failed too: OpenAI API error: overloaded",
+                "kind": "provider_api",
+                "provider": "OpenAI",
+            })
+        );
+    }
+
+    #[test]
+    fn test_from_status() {
+        let err = Error::from_status("OpenAI", reqwest::StatusCode::UNAUTHORIZED).unwrap();
+        assert!(matches!(err, Error::ProviderAuth { ref provider, .. } if provider == "OpenAI"));
+        assert_eq!(
+            err.to_string(),
+            "OpenAI rejected the API key (401 Unauthorized)"
+        );
+
+        assert!(Error::from_status("OpenAI", reqwest::StatusCode::OK).is_none());
+        assert!(Error::from_status("OpenAI", reqwest::StatusCode::BAD_REQUEST).is_none());
+    }
+}
@@ -1,53 +1,720 @@
-use anyhow::{Result, bail};
-use git2::{DiffOptions, Repository as Git2Repo, Status, StatusOptions};
+use anyhow::anyhow;
+use git2::{
+    BranchType, Delta, DiffFindOptions, DiffOptions, IndexEntryExtendedFlag, Oid,
+    Repository as Git2Repo, Status, StatusOptions,
+};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::ffi::OsString;
+use std::io::{Read, Write};
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Output, Stdio};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::error::{Error, Result};
 
 pub enum DiffAlg {
     Default,
     Patience,
     Minimal,
+    /// Not implemented by libgit2, so this delegates to the git CLI
+    Histogram,
+    /// Word-level diff via `git diff --word-diff=plain`. Falls back to the
+    /// default line diff when the git CLI is not installed.
+    Word,
     Difftastic,
     // Add more as needed
 }
 
+/// Returns true when the difftastic binary (`difft`) is on the PATH
+pub fn is_difftastic_available() -> bool {
+    command_exists("difft")
+}
+
+fn command_exists(name: &str) -> bool {
+    Command::new("which")
+        .arg(name)
+        .output()
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+/// Replaces the `Subproject commit` hunks of submodule pointer updates with a
+/// one-line summary the model can describe correctly
+fn summarize_submodule_changes(diff_text: &str) -> String {
+    let mut sections: Vec<&str> = Vec::new();
+    let mut start = 0;
+    for (offset, _) in diff_text.match_indices("diff --git ") {
+        if offset == 0 || diff_text.as_bytes()[offset - 1] == b'\n' {
+            if offset > start {
+                sections.push(&diff_text[start..offset]);
+            }
+            start = offset;
+        }
+    }
+    sections.push(&diff_text[start..]);
+
+    sections
+        .into_iter()
+        .map(|section| summarize_submodule_section(section).unwrap_or_else(|| section.to_string()))
+        .collect()
+}
+
+fn summarize_submodule_section(section: &str) -> Option<String> {
+    let header = section.lines().next()?.strip_prefix("diff --git ")?;
+    let path = header.rsplit_once(" b/")?.1;
+
+    let mut old_sha = None;
+    let mut new_sha = None;
+    for line in section.lines().skip(1) {
+        if line.starts_with("---") || line.starts_with("+++") {
+            continue;
+        }
+        if let Some(sha) = line.strip_prefix("-Subproject commit ") {
+            old_sha = Some(sha.trim());
+        } else if let Some(sha) = line.strip_prefix("+Subproject commit ") {
+            new_sha = Some(sha.trim());
+        } else if line.starts_with('-') || line.starts_with('+') {
+            // Regular content changes, not a submodule pointer
+            return None;
+        }
+    }
+
+    let short = |sha: &str| sha.chars().take(7).collect::<String>();
+    let summary = match (old_sha, new_sha) {
+        (Some(old), Some(new)) => {
+            format!("submodule {path} updated {}..{}", short(old), short(new))
+        }
+        (None, Some(new)) => format!("submodule {path} added at {}", short(new)),
+        (Some(old), None) => format!("submodule {path} removed (was {})", short(old)),
+        (None, None) => return None,
+    };
+    Some(format!("{summary}\n"))
+}
+
+/// Everything about the staged changes, read from a single diff
+#[derive(Debug)]
+pub struct StagedDiff {
+    /// The diff as sent to the AI, in the chosen algorithm's format
+    pub text: String,
+    /// Staged files, relative to the repository root
+    pub paths: Vec<String>,
+    /// (old path, new path) of each renamed file
+    pub renames: Vec<(String, String)>,
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+}
+
+/// The staged diff read one file at a time; see
+/// `Repository::staged_file_diffs`
+pub struct StagedFileDiffs<'a> {
+    repo: &'a Repository,
+    diff: git2::Diff<'a>,
+    intent_to_add: BTreeSet<String>,
+    next: usize,
+}
+
+impl Iterator for StagedFileDiffs<'_> {
+    type Item = Result<(String, String)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.diff.deltas().len() {
+            let idx = self.next;
+            self.next += 1;
+            match self
+                .repo
+                .staged_file_patch(&self.diff, idx, &self.intent_to_add)
+            {
+                Ok(Some((path, patch))) => {
+                    return Some(Ok((path, summarize_submodule_changes(&patch))));
+                }
+                Ok(None) => continue,
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+/// A merge waiting to be committed
+#[derive(Debug, Clone, PartialEq)]
+pub struct MergeInfo {
+    /// Branches (or short commit ids) being merged into the current branch
+    pub merged: Vec<String>,
+    /// Files that conflicted in the merge, i.e. whose result was settled by hand
+    pub resolved_files: Vec<String>,
+}
+
+/// Which kinds of change to keep, written as the letters of git's
+/// `--diff-filter`: upper case selects a kind, lower case excludes it
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiffFilter {
+    spec: String,
+    include: Vec<Delta>,
+    exclude: Vec<Delta>,
+}
+
+impl DiffFilter {
+    /// Parses letters from A (added), C (copied), D (deleted), M (modified),
+    /// R (renamed) and T (type changed), e.g. "AM" or "d"
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        if spec.is_empty() {
+            return Err("expected letters from ACDMRT".to_string());
+        }
+        let mut filter = Self {
+            spec: spec.to_string(),
+            include: Vec::new(),
+            exclude: Vec::new(),
+        };
+        for letter in spec.chars() {
+            let status = match letter.to_ascii_uppercase() {
+                'A' => Delta::Added,
+                'C' => Delta::Copied,
+                'D' => Delta::Deleted,
+                'M' => Delta::Modified,
+                'R' => Delta::Renamed,
+                'T' => Delta::Typechange,
+                _ => {
+                    return Err(format!(
+                        "unknown change type '{letter}', expected letters from ACDMRT"
+                    ));
+                }
+            };
+            if letter.is_ascii_uppercase() {
+                filter.include.push(status);
+            } else {
+                filter.exclude.push(status);
+            }
+        }
+        Ok(filter)
+    }
+
+    fn keeps(&self, status: Delta) -> bool {
+        !self.exclude.contains(&status)
+            && (self.include.is_empty() || self.include.contains(&status))
+    }
+}
+
+/// How long difftastic may take on one file before the built-in diff is
+/// used for it instead
+pub const DEFAULT_DIFFT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An opened repository. Every read goes through this one handle, so
+/// nothing reopens the repository. It is `Send` but, like libgit2's handle,
+/// not `Sync`: share it between concurrent tasks as `Arc<Mutex<Repository>>`.
 pub struct Repository {
     repo: Git2Repo,
     verbose: bool,
+    difft_timeout: Duration,
+    diff_filter: Option<DiffFilter>,
+    include_intent_to_add: bool,
 }
 
 impl Repository {
+    /// Opens the repository git would use here: the one `GIT_DIR` names,
+    /// with `GIT_WORK_TREE` as its working tree, as in hooks; otherwise the
+    /// one containing the current directory
     pub fn open_current_dir(verbose: bool) -> Result<Self> {
         let path = std::env::current_dir()?;
-        Self::open(&path, verbose)
+        Self::open_with_git_env(
+            &path,
+            std::env::var_os("GIT_DIR"),
+            std::env::var_os("GIT_WORK_TREE"),
+            verbose,
+        )
+    }
+
+    /// Opens the repository for `cwd` given the values of `GIT_DIR` and
+    /// `GIT_WORK_TREE`, which are relative to `cwd` when not absolute
+    fn open_with_git_env(
+        cwd: &Path,
+        git_dir: Option<OsString>,
+        work_tree: Option<OsString>,
+        verbose: bool,
+    ) -> Result<Self> {
+        let repo = match &git_dir {
+            Some(git_dir) => Git2Repo::open(cwd.join(git_dir)),
+            None => Git2Repo::discover(cwd),
+        }
+        .map_err(Error::from)?;
+        if let Some(work_tree) = work_tree {
+            repo.set_workdir(&cwd.join(work_tree), false)
+                .map_err(Error::from)?;
+        }
+        if verbose && git_dir.is_some() {
+            eprintln!("Debug: Using GIT_DIR {}", repo.path().display());
+        }
+
+        Ok(Self {
+            repo,
+            verbose,
+            difft_timeout: DEFAULT_DIFFT_TIMEOUT,
+            diff_filter: None,
+            include_intent_to_add: false,
+        })
     }
 
     pub fn open(path: &Path, verbose: bool) -> Result<Self> {
-        let repo = Git2Repo::open(path)?;
-        Ok(Self { repo, verbose })
+        let repo = Git2Repo::open(path).map_err(Error::from)?;
+        Ok(Self {
+            repo,
+            verbose,
+            difft_timeout: DEFAULT_DIFFT_TIMEOUT,
+            diff_filter: None,
+            include_intent_to_add: false,
+        })
+    }
+
+    /// How long difftastic may take on one file
+    pub fn with_difft_timeout(mut self, timeout: Duration) -> Self {
+        self.difft_timeout = timeout;
+        self
+    }
+
+    /// Leave the kinds of change the filter doesn't keep out of every diff
+    pub fn with_diff_filter(mut self, filter: Option<DiffFilter>) -> Self {
+        self.diff_filter = filter;
+        self
+    }
+
+    /// Diff files added with `git add -N` by their working-tree contents
+    /// instead of noting that their contents aren't staged
+    pub fn with_include_intent_to_add(mut self, include: bool) -> Self {
+        self.include_intent_to_add = include;
+        self
+    }
+
+    /// Whether the diff filter, if any, keeps changes of this kind
+    fn keeps(&self, status: Delta) -> bool {
+        self.diff_filter
+            .as_ref()
+            .is_none_or(|filter| filter.keeps(status))
+    }
+
+    /// The root of the working tree, or None for a bare repository
+    pub fn workdir(&self) -> Option<&Path> {
+        self.repo.workdir()
+    }
+
+    /// The editor set in git's `core.editor`, if any
+    pub fn core_editor(&self) -> Option<String> {
+        let config = self.repo.config().ok()?;
+        config.get_string("core.editor").ok()
     }
 
     pub fn get_branch_name(&self) -> Result<String> {
-        let head = self.repo.head()?;
+        if self.repo.head_detached().map_err(Error::from)? {
+            return Ok("detached-head".to_string());
+        }
+
+        // Read HEAD itself rather than what it resolves to. In a linked
+        // worktree this is the worktree's own HEAD, and it also names the
+        // branch before its first commit.
+        let head_ref = self.repo.find_reference("HEAD").map_err(Error::from)?;
+        if let Some(branch) = head_ref
+            .symbolic_target()
+            .and_then(|target| target.strip_prefix("refs/heads/"))
+        {
+            return Ok(branch.to_string());
+        }
+
+        let head = self.repo.head().map_err(Error::from)?;
         if head.is_branch() {
             head.shorthand()
                 .map(String::from)
-                .ok_or_else(|| anyhow::anyhow!("Failed to get branch name"))
+                .ok_or_else(|| anyhow!("Failed to get branch name").into())
         } else {
             Ok("detached-head".to_string())
         }
     }
 
-    pub fn get_staged_diff(&self, alg: DiffAlg) -> Result<String> {
+    /// The current branch's description (`git branch --edit-description`,
+    /// stored as `branch.<name>.description`), if it has one
+    pub fn get_branch_description(&self) -> Result<Option<String>> {
+        let branch = self.get_branch_name()?;
+        if branch == "detached-head" {
+            return Ok(None);
+        }
+
+        let config = self.repo.config().map_err(Error::from)?;
+        Ok(config
+            .get_string(&format!("branch.{branch}.description"))
+            .ok()
+            .map(|description| description.trim().to_string())
+            .filter(|description| !description.is_empty()))
+    }
+
+    /// Returns the branch changes are likely headed for: the branch a remote's
+    /// HEAD points at (preferring `origin`), or `init.defaultBranch` from the
+    /// config. Returns None when the repository has no remotes.
+    pub fn get_default_branch(&self) -> Result<Option<String>> {
+        let remotes = self.repo.remotes().map_err(Error::from)?;
+        let mut names: Vec<&str> = remotes.iter().flatten().collect();
+        if names.is_empty() {
+            return Ok(None);
+        }
+        // Check origin first, then the others in order
+        names.sort_by_key(|name| *name != "origin");
+
+        for name in names {
+            let Ok(head) = self
+                .repo
+                .find_reference(&format!("refs/remotes/{name}/HEAD"))
+            else {
+                continue;
+            };
+            let prefix = format!("refs/remotes/{name}/");
+            if let Some(branch) = head
+                .symbolic_target()
+                .and_then(|target| target.strip_prefix(&prefix))
+            {
+                return Ok(Some(branch.to_string()));
+            }
+        }
+
+        let config = self.repo.config().map_err(Error::from)?;
+        Ok(config.get_string("init.defaultBranch").ok())
+    }
+
+    /// Whether HEAD is a commit of the current branch's own, one the base
+    /// branch doesn't have yet. The base branch is the default branch (see
+    /// `get_default_branch`), or else main or master, local or on a remote.
+    /// False when none of them exist, since then there is no telling.
+    pub fn head_is_ahead_of_base(&self) -> Result<bool> {
+        let Ok(head) = self.repo.head().and_then(|head| head.peel_to_commit()) else {
+            return Ok(false);
+        };
+        let bases = match self.get_default_branch()? {
+            Some(branch) => vec![branch],
+            None => vec!["main".to_string(), "master".to_string()],
+        };
+        let remotes = self.repo.remotes()?;
+
+        let mut found = false;
+        for base in &bases {
+            let names = std::iter::once(format!("refs/heads/{base}")).chain(
+                remotes
+                    .iter()
+                    .flatten()
+                    .map(|remote| format!("refs/remotes/{remote}/{base}")),
+            );
+            for name in names {
+                let Some(tip) = self
+                    .repo
+                    .find_reference(&name)
+                    .ok()
+                    .and_then(|reference| reference.target())
+                else {
+                    continue;
+                };
+                found = true;
+                if tip == head.id() || self.repo.graph_descendant_of(tip, head.id())? {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Stages every change to tracked files, deletions included, like
+    /// `git add -u`; with `include_untracked`, new files too, like
+    /// `git add -A`. Ignored files are left out. Returns the staged paths.
+    pub fn stage_all(&self, include_untracked: bool) -> Result<Vec<String>> {
+        let mut status_opts = StatusOptions::new();
+        status_opts
+            .include_untracked(include_untracked)
+            .recurse_untracked_dirs(include_untracked)
+            .include_ignored(false);
+        let statuses = self
+            .repo
+            .statuses(Some(&mut status_opts))
+            .map_err(Error::from)?;
+
+        let mut index = self.repo.index().map_err(Error::from)?;
+        let mut staged = Vec::new();
+        for entry in statuses.iter() {
+            let Some(path) = entry.path() else {
+                continue;
+            };
+            let status = entry.status();
+            if status.contains(Status::WT_DELETED) {
+                index.remove_path(Path::new(path)).map_err(Error::from)?;
+            } else if status.intersects(Status::WT_MODIFIED | Status::WT_TYPECHANGE)
+                || (include_untracked && status.contains(Status::WT_NEW))
+            {
+                index.add_path(Path::new(path)).map_err(Error::from)?;
+            } else {
+                continue;
+            }
+            staged.push(path.to_string());
+        }
+        index.write().map_err(Error::from)?;
+
+        if self.verbose {
+            match staged.len() {
+                0 => eprintln!("Nothing to stage"),
+                _ => eprintln!("Staged {}", staged.join(", ")),
+            }
+        }
+        Ok(staged)
+    }
+
+    /// Whether a merge is in progress, i.e. `MERGE_HEAD` exists
+    pub fn is_merging(&self) -> bool {
+        self.repo.path().join("MERGE_HEAD").is_file()
+    }
+
+    /// Describes the merge in progress, if any: the branches being merged in,
+    /// and the files that conflicted, which is where the result was settled
+    /// by hand. Once resolved, a conflict leaves no trace in the index, so
+    /// the merge of each branch with HEAD is redone in memory to find them.
+    pub fn merge_info(&self) -> Result<Option<MergeInfo>> {
+        if !self.is_merging() {
+            return Ok(None);
+        }
+
+        // One commit id per line, for each branch being merged
+        let merge_head = std::fs::read_to_string(self.repo.path().join("MERGE_HEAD"))?;
+        let heads = merge_head
+            .split_whitespace()
+            .map(Oid::from_str)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(Error::from)?;
+
+        let merged = heads.iter().map(|oid| self.describe_commit(*oid)).collect();
+
+        let mut conflicted = std::collections::BTreeSet::new();
+        let mut add_conflicts = |index: &git2::Index| -> Result<()> {
+            for conflict in index.conflicts().map_err(Error::from)? {
+                let conflict = conflict.map_err(Error::from)?;
+                if let Some(entry) = conflict.our.or(conflict.their).or(conflict.ancestor) {
+                    conflicted.insert(String::from_utf8_lossy(&entry.path).into_owned());
+                }
+            }
+            Ok(())
+        };
+
+        // Conflicts still unresolved are staged as such
+        add_conflicts(&self.repo.index().map_err(Error::from)?)?;
+
+        let head = self
+            .repo
+            .head()
+            .and_then(|head| head.peel_to_commit())
+            .map_err(Error::from)?;
+        let head_tree = head.tree().map_err(Error::from)?;
+        for oid in &heads {
+            let theirs = self.repo.find_commit(*oid).map_err(Error::from)?;
+            // Unrelated histories merge against an empty tree
+            let base_tree = match self.repo.merge_base(head.id(), *oid) {
+                Ok(base) => self.repo.find_commit(base).and_then(|base| base.tree()),
+                Err(_) => self
+                    .repo
+                    .treebuilder(None)
+                    .and_then(|builder| builder.write())
+                    .and_then(|tree| self.repo.find_tree(tree)),
+            }
+            .map_err(Error::from)?;
+            let index = self
+                .repo
+                .merge_trees(
+                    &base_tree,
+                    &head_tree,
+                    &theirs.tree().map_err(Error::from)?,
+                    None,
+                )
+                .map_err(Error::from)?;
+            add_conflicts(&index)?;
+        }
+
+        Ok(Some(MergeInfo {
+            merged,
+            resolved_files: conflicted.into_iter().collect(),
+        }))
+    }
+
+    /// A branch pointing at the commit (local ones first), or its short id
+    fn describe_commit(&self, oid: Oid) -> String {
+        let mut branches: Vec<(BranchType, String)> = self
+            .repo
+            .branches(None)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter(|(branch, _)| branch.get().target() == Some(oid))
+            .filter_map(|(branch, kind)| Some((kind, branch.name().ok()??.to_string())))
+            .collect();
+        branches.sort_by_key(|(kind, _)| *kind == BranchType::Remote);
+
+        branches
+            .into_iter()
+            .next()
+            .map(|(_, name)| name)
+            .unwrap_or_else(|| oid.to_string()[..7].to_string())
+    }
+
+    /// Reads the staged changes once, deriving the diff text, file list,
+    /// renames and line counts from the same index/tree comparison
+    pub fn get_staged_diff(&self, alg: DiffAlg) -> Result<StagedDiff> {
+        let diff = self.staged_git2_diff(&alg)?;
+        let text = match alg {
+            DiffAlg::Default | DiffAlg::Patience | DiffAlg::Minimal => self.patch_text(&diff)?,
+            _ => self.get_external_staged_diff(alg)?,
+        };
+        self.describe_diff(&diff, &text)
+    }
+
+    /// Reads the changes HEAD made to its first parent (or its whole tree,
+    /// for a root commit) the way `get_staged_diff` reads the index.
+    /// Algorithms libgit2 doesn't implement fall back to the default.
+    pub fn get_head_diff(&self, alg: DiffAlg) -> Result<StagedDiff> {
+        let head = self.repo.head()?.peel_to_commit()?;
+        let parent_tree = match head.parents().next() {
+            Some(parent) => Some(parent.tree()?),
+            None => None,
+        };
+
+        let mut diff = self
+            .repo
+            .diff_tree_to_tree(
+                parent_tree.as_ref(),
+                Some(&head.tree()?),
+                Some(&mut diff_options(&alg)),
+            )
+            .map_err(Error::from)?;
+        diff.find_similar(Some(DiffFindOptions::new().renames(true)))
+            .map_err(Error::from)?;
+
+        let mut text = String::new();
+        self.for_each_file_patch(&diff, |_, patch| {
+            text.push_str(patch);
+            Ok(())
+        })?;
+        self.describe_diff(&diff, &text)
+    }
+
+    /// Replaces HEAD's message, keeping its tree, parents and author, like
+    /// `git commit --amend --only`. When `commit.gpgsign` is set, it runs
+    /// that instead, since libgit2 would drop the signature. Returns the
+    /// rewritten commit.
+    pub fn amend_head_message(&self, message: &str) -> Result<Oid> {
+        if self.signs_commits() {
+            // --only leaves whatever is staged out of the rewritten commit
+            return self.git_commit(&["--amend", "--only"], message);
+        }
+
+        let head = self.repo.head()?.peel_to_commit()?;
+        // git records whoever amends as the committer
+        let committer = self.repo.signature().ok();
+        let message = format!("{}\n", message.trim_end());
+        let oid = head.amend(
+            Some("HEAD"),
+            None,
+            committer.as_ref(),
+            None,
+            Some(&message),
+            None,
+        )?;
+        Ok(oid)
+    }
+
+    /// Commits the staged changes by running `git commit`, which libgit2
+    /// can't stand in for: it concludes a merge in progress, runs the
+    /// pre-commit and commit-msg hooks, and signs the commit when
+    /// `commit.gpgsign` is set. Returns the new commit.
+    pub fn commit(&self, message: &str) -> Result<Oid> {
+        self.git_commit(&[], message)
+    }
+
+    /// Whether `commit.gpgsign` asks for signed commits
+    fn signs_commits(&self) -> bool {
+        self.repo
+            .config()
+            .and_then(|config| config.get_bool("commit.gpgsign"))
+            .unwrap_or(false)
+    }
+
+    /// Runs `git commit` with extra arguments, reading the message from stdin
+    fn git_commit(&self, extra_args: &[&str], message: &str) -> Result<Oid> {
+        let workdir = self
+            .repo
+            .workdir()
+            .ok_or_else(|| anyhow!("Repository has no working directory"))?;
+
+        let mut child = Command::new("git")
+            .arg("--git-dir")
+            .arg(self.repo.path())
+            .arg("--work-tree")
+            .arg(workdir)
+            // Keep the message as it is, apart from surrounding whitespace
+            .args(["commit", "--quiet", "--cleanup=whitespace", "--file=-"])
+            .args(extra_args)
+            .env_remove("GIT_INDEX_FILE")
+            .current_dir(workdir)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                std::io::ErrorKind::NotFound => {
+                    anyhow!("git not found in PATH. Committing requires the git command line tool.")
+                }
+                _ => anyhow!("Failed to run git: {}", e),
+            })?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(format!("{}\n", message.trim_end()).as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "git commit failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        Ok(self.repo.head()?.peel_to_commit()?.id())
+    }
+
+    /// Remote-tracking branches that already contain HEAD, where rewriting
+    /// it would take a force push
+    pub fn remote_branches_containing_head(&self) -> Result<Vec<String>> {
+        let head = self.repo.head()?.peel_to_commit()?.id();
+        let mut names = Vec::new();
+        for branch in self.repo.branches(Some(BranchType::Remote))? {
+            let (branch, _) = branch?;
+            // Symbolic refs such as origin/HEAD have no target of their own
+            let Some(target) = branch.get().target() else {
+                continue;
+            };
+            if (target == head || self.repo.graph_descendant_of(target, head)?)
+                && let Some(name) = branch.name()?
+            {
+                names.push(name.to_string());
+            }
+        }
+        Ok(names)
+    }
+
+    /// Diff text for the algorithms libgit2 doesn't implement
+    fn get_external_staged_diff(&self, alg: DiffAlg) -> Result<String> {
         match alg {
+            DiffAlg::Histogram => self.get_git_cli_staged_diff(&["--histogram"]),
+            DiffAlg::Word => {
+                if command_exists("git") {
+                    self.get_git_cli_staged_diff(&["--word-diff=plain"])
+                } else {
+                    if self.verbose {
+                        eprintln!("Debug: git CLI not found, falling back to a line diff");
+                    }
+                    self.patch_text(&self.staged_git2_diff(&DiffAlg::Default)?)
+                }
+            }
             DiffAlg::Difftastic => {
                 // Check if difftastic is available
-                if Command::new("which")
-                    .arg("difft")
-                    .output()
-                    .map(|o| o.status.success())
-                    .unwrap_or(false)
-                {
+                if is_difftastic_available() {
                     // Collect staged files
                     let mut status_opts = StatusOptions::new();
                     status_opts.include_untracked(false).include_ignored(false);
@@ -55,252 +722,1659 @@ impl Repository {
                     let mut diffs = Vec::new();
                     for entry in statuses.iter() {
                         let status = entry.status();
-                        if status.intersects(
-                            Status::INDEX_NEW | Status::INDEX_MODIFIED | Status::INDEX_DELETED,
-                        ) {
+                        let delta = if status.contains(Status::INDEX_NEW) {
+                            Delta::Added
+                        } else if status.contains(Status::INDEX_MODIFIED) {
+                            Delta::Modified
+                        } else if status.contains(Status::INDEX_DELETED) {
+                            Delta::Deleted
+                        } else {
+                            continue;
+                        };
+                        if self.keeps(delta) {
                             let path = entry.path().unwrap();
-                            // Get blob for HEAD (if exists)
-                            let head = self.repo.head().ok();
-                            let tree = head.as_ref().and_then(|h| h.peel_to_tree().ok());
-                            let head_blob = tree
-                                .as_ref()
-                                .and_then(|t| t.get_path(Path::new(path)).ok())
-                                .and_then(|e| e.to_object(&self.repo).ok())
-                                .and_then(|o| o.as_blob().map(|b| b.content().to_vec()));
-                            // Get blob for index (staged)
-                            let index = self.repo.index()?;
-                            let staged_entry = index.get_path(Path::new(path), 0);
-                            let staged_blob = if let Some(e) = staged_entry {
-                                self.repo.find_blob(e.id).ok().map(|b| b.content().to_vec())
-                            } else {
-                                None
-                            };
-                            // Write both to temp files
-                            let a_file = tempfile::NamedTempFile::new()?;
-                            let b_file = tempfile::NamedTempFile::new()?;
-                            if let Some(ref content) = head_blob {
-                                std::fs::write(a_file.path(), content)?;
-                            }
-                            if let Some(ref content) = staged_blob {
-                                std::fs::write(b_file.path(), content)?;
-                            }
-                            // Call difft
-                            let output = Command::new("difft")
-                                .arg(a_file.path())
-                                .arg(b_file.path())
-                                .current_dir(self.repo.path().parent().unwrap())
-                                .output();
-                            match output {
-                                Ok(output) => {
-                                    if output.status.success() {
-                                        let diff =
-                                            String::from_utf8_lossy(&output.stdout).to_string();
-                                        if !diff.trim().is_empty() {
-                                            diffs.push(format!(
-                                                "diff --difftastic a/{path} b/{path}\n{diff}"
-                                            ));
-                                        }
-                                    } else {
-                                        bail!(
-                                            "difftastic failed: {}",
-                                            String::from_utf8_lossy(&output.stderr)
-                                        );
-                                    }
-                                }
-                                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
-                                    bail!(
-                                        "difftastic (difft) not found in PATH when running difft. Please install difftastic (https://difftastic.wilfred.me.uk/) and ensure 'difft' is available in your PATH."
-                                    );
-                                }
-                                Err(e) => {
-                                    bail!("Failed to run difft: {}", e);
-                                }
-                            }
+                            diffs.extend(self.difftastic_file_diff("difft", path)?);
                         }
                     }
                     Ok(diffs.join("\n"))
                 } else {
-                    bail!(
+                    Err(anyhow!(
                         "difftastic (difft) not found in PATH. Please install difftastic (https://difftastic.wilfred.me.uk/) and ensure 'difft' is available in your PATH."
-                    );
+                    ).into())
                 }
             }
             _ => {
-                // Built-in algorithms
-                let head = self.repo.head().ok();
-                let tree = head.as_ref().and_then(|h| h.peel_to_tree().ok());
+                let diff = self.staged_git2_diff(&alg)?;
+                self.patch_text(&diff)
+            }
+        }
+    }
+
+    /// Collects the file list, renames and line counts of a diff alongside
+    /// its text, leaving out the changes the diff filter drops
+    fn describe_diff(&self, diff: &git2::Diff<'_>, text: &str) -> Result<StagedDiff> {
+        let mut paths = Vec::new();
+        let mut renames = Vec::new();
+        let (mut files_changed, mut insertions, mut deletions) = (0, 0, 0);
+        for (idx, delta) in diff.deltas().enumerate() {
+            if !self.keeps(delta.status()) {
+                continue;
+            }
+            let old_path = delta
+                .old_file()
+                .path()
+                .map(|path| path.display().to_string());
+            let new_path = delta
+                .new_file()
+                .path()
+                .map(|path| path.display().to_string());
+            if delta.status() == Delta::Renamed
+                && let (Some(old_path), Some(new_path)) = (&old_path, &new_path)
+            {
+                renames.push((old_path.clone(), new_path.clone()));
+            }
+            paths.extend(new_path.or(old_path));
+
+            // Only count file by file when some are left out
+            if self.diff_filter.is_some() {
+                files_changed += 1;
+                if let Some(patch) = git2::Patch::from_diff(diff, idx)? {
+                    let (_, added, removed) = patch.line_stats()?;
+                    insertions += added;
+                    deletions += removed;
+                }
+            }
+        }
+
+        if self.diff_filter.is_none() {
+            let stats = diff.stats().map_err(Error::from)?;
+            (files_changed, insertions, deletions) =
+                (stats.files_changed(), stats.insertions(), stats.deletions());
+        }
+        Ok(StagedDiff {
+            text: summarize_submodule_changes(text),
+            paths,
+            renames,
+            files_changed,
+            insertions,
+            deletions,
+        })
+    }
+
+    /// Runs difftastic on one staged file, falling back to the built-in diff
+    /// with a warning when it takes longer than the timeout. Returns None
+    /// when difftastic shows no change.
+    fn difftastic_file_diff(&self, difft: &str, path: &str) -> Result<Option<String>> {
+        // Get blob for HEAD (if exists)
+        let head = self.repo.head().ok();
+        let tree = head.as_ref().and_then(|h| h.peel_to_tree().ok());
+        let head_blob = tree
+            .as_ref()
+            .and_then(|t| t.get_path(Path::new(path)).ok())
+            .and_then(|e| e.to_object(&self.repo).ok())
+            .and_then(|o| o.as_blob().map(|b| b.content().to_vec()));
+        // Get blob for index (staged)
+        let index = self.repo.index()?;
+        let staged_entry = index.get_path(Path::new(path), 0);
+        let staged_blob = if let Some(e) = staged_entry {
+            self.repo.find_blob(e.id).ok().map(|b| b.content().to_vec())
+        } else {
+            None
+        };
+        // Write both to temp files
+        let a_file = tempfile::NamedTempFile::new()?;
+        let b_file = tempfile::NamedTempFile::new()?;
+        if let Some(ref content) = head_blob {
+            std::fs::write(a_file.path(), content)?;
+        }
+        if let Some(ref content) = staged_blob {
+            std::fs::write(b_file.path(), content)?;
+        }
+        // Call difft
+        let output = output_with_timeout(
+            Command::new(difft)
+                .arg(a_file.path())
+                .arg(b_file.path())
+                .current_dir(self.repo.path().parent().unwrap()),
+            self.difft_timeout,
+        );
+        match output {
+            Ok(Some(output)) => {
+                if output.status.success() {
+                    let diff = String::from_utf8_lossy(&output.stdout).to_string();
+                    Ok((!diff.trim().is_empty())
+                        .then(|| format!("diff --difftastic a/{path} b/{path}\n{diff}")))
+                } else {
+                    Err(anyhow!(
+                        "difftastic failed: {}",
+                        String::from_utf8_lossy(&output.stderr)
+                    )
+                    .into())
+                }
+            }
+            Ok(None) => {
+                eprintln!(
+                    "Warning: difftastic took longer than {}s on {path}, using the built-in diff for it",
+                    self.difft_timeout.as_secs_f64()
+                );
+                let diff = self.builtin_file_diff(path)?;
+                Ok((!diff.is_empty()).then_some(diff))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                Err(anyhow!(
+                    "difftastic (difft) not found in PATH when running difft. Please install difftastic (https://difftastic.wilfred.me.uk/) and ensure 'difft' is available in your PATH."
+                ).into())
+            }
+            Err(e) => {
+                Err(anyhow!("Failed to run difft: {}", e).into())
+            }
+        }
+    }
+
+    /// The built-in diff of one staged file
+    fn builtin_file_diff(&self, path: &str) -> Result<String> {
+        let head = self.repo.head().ok();
+        let tree = head.as_ref().and_then(|h| h.peel_to_tree().ok());
+        let mut options = DiffOptions::new();
+        options.pathspec(path).disable_pathspec_match(true);
+        let diff = self
+            .repo
+            .diff_tree_to_index(tree.as_ref(), None, Some(&mut options))
+            .map_err(Error::from)?;
+
+        let mut text = String::new();
+        self.for_each_staged_patch(&diff, |_, patch| {
+            text.push_str(patch);
+            Ok(())
+        })?;
+        Ok(text)
+    }
+
+    /// Compares HEAD (or nothing, before the first commit) with the index,
+    /// detecting renames
+    fn staged_git2_diff(&self, alg: &DiffAlg) -> Result<git2::Diff<'_>> {
+        let head = self.repo.head().ok();
+        let tree = head.as_ref().and_then(|h| h.peel_to_tree().ok());
+
+        if self.verbose && head.is_none() {
+            eprintln!("Debug: Repository has no HEAD commit yet");
+        }
+
+        let mut diff = self
+            .repo
+            .diff_tree_to_index(tree.as_ref(), None, Some(&mut diff_options(alg)))
+            .map_err(Error::from)?;
+        diff.find_similar(Some(DiffFindOptions::new().renames(true)))
+            .map_err(Error::from)?;
+        Ok(diff)
+    }
+
+    /// The staged diff one file at a time, as (path, patch). Each file is
+    /// only rendered when the iterator gets to it, so a huge change is never
+    /// held as one string. Algorithms libgit2 doesn't implement fall back to
+    /// the default.
+    pub fn staged_file_diffs(&self, alg: DiffAlg) -> Result<StagedFileDiffs<'_>> {
+        Ok(StagedFileDiffs {
+            repo: self,
+            diff: self.staged_git2_diff(&alg)?,
+            intent_to_add: self.intent_to_add_paths()?,
+            next: 0,
+        })
+    }
+
+    /// Reads what `get_staged_diff` does except the diff itself, for when
+    /// it is read file by file with `staged_file_diffs`; `text` is empty
+    pub fn get_staged_stats(&self, alg: DiffAlg) -> Result<StagedDiff> {
+        let diff = self.staged_git2_diff(&alg)?;
+        self.describe_diff(&diff, "")
+    }
+
+    /// Renders a libgit2 diff as a patch
+    fn patch_text(&self, diff: &git2::Diff<'_>) -> Result<String> {
+        let mut diff_text = String::new();
+        self.for_each_staged_patch(diff, |_, patch| {
+            diff_text.push_str(patch);
+            Ok(())
+        })?;
+
+        if diff_text.is_empty() && self.verbose {
+            self.debug_staging_status()?;
+        }
+
+        Ok(diff_text)
+    }
+
+    /// `for_each_file_patch` for a diff of the index, where files added with
+    /// `git add -N` are staged empty
+    fn for_each_staged_patch(
+        &self,
+        diff: &git2::Diff<'_>,
+        mut f: impl FnMut(&str, &str) -> Result<()>,
+    ) -> Result<()> {
+        let intent_to_add = self.intent_to_add_paths()?;
+        for idx in 0..diff.deltas().len() {
+            if let Some((path, patch)) = self.staged_file_patch(diff, idx, &intent_to_add)? {
+                f(&path, &patch)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `file_patch` for a diff of the index, given the paths added with
+    /// `git add -N`
+    fn staged_file_patch(
+        &self,
+        diff: &git2::Diff<'_>,
+        idx: usize,
+        intent_to_add: &BTreeSet<String>,
+    ) -> Result<Option<(String, String)>> {
+        Ok(self.file_patch(diff, idx)?.map(|(path, patch)| {
+            let patch = if intent_to_add.contains(&path) {
+                self.intent_to_add_patch(&path, &patch)
+            } else {
+                patch
+            };
+            (path, patch)
+        }))
+    }
+
+    /// Paths added with `git add -N`
+    fn intent_to_add_paths(&self) -> Result<BTreeSet<String>> {
+        let index = self.repo.index()?;
+        Ok(index
+            .iter()
+            .filter(|entry| {
+                IndexEntryExtendedFlag::from_bits_truncate(entry.flags_extended).is_intent_to_add()
+            })
+            .map(|entry| String::from_utf8_lossy(&entry.path).into_owned())
+            .collect())
+    }
+
+    /// The patch of a file added with `git add -N`, which libgit2 shows as an
+    /// empty new file: its working-tree contents with
+    /// `with_include_intent_to_add`, otherwise a note that they aren't staged
+    fn intent_to_add_patch(&self, path: &str, patch: &str) -> String {
+        let note = format!("{patch}(added with git add -N; contents not staged)\n");
+        if !self.include_intent_to_add {
+            return note;
+        }
+        let Some(contents) = self
+            .repo
+            .workdir()
+            .and_then(|workdir| std::fs::read(workdir.join(path)).ok())
+            .filter(|contents| !contents.is_empty())
+        else {
+            return note;
+        };
+
+        let hunks = git2::Patch::from_buffers(&[], None, &contents, Some(Path::new(path)), None)
+            .and_then(|mut patch| patch.to_buf())
+            .map(|buf| String::from_utf8_lossy(&buf).into_owned());
+        match hunks {
+            Ok(hunks) => match hunks.split_once("\n@@") {
+                Some((_, hunks)) => format!("{patch}@@{hunks}"),
+                None => format!("{patch}Binary files /dev/null and b/{path} differ\n"),
+            },
+            Err(_) => note,
+        }
+    }
+
+    /// Renders a libgit2 diff as a patch, handing `f` each file's part as
+    /// soon as it is complete
+    fn for_each_file_patch(
+        &self,
+        diff: &git2::Diff<'_>,
+        mut f: impl FnMut(&str, &str) -> Result<()>,
+    ) -> Result<()> {
+        for idx in 0..diff.deltas().len() {
+            if let Some((path, patch)) = self.file_patch(diff, idx)? {
+                f(&path, &patch)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Renders the patch of the diff's `idx`th file, or None for a file the
+    /// diff filter leaves out
+    fn file_patch(&self, diff: &git2::Diff<'_>, idx: usize) -> Result<Option<(String, String)>> {
+        let Some(delta) = diff
+            .get_delta(idx)
+            .filter(|delta| self.keeps(delta.status()))
+        else {
+            return Ok(None);
+        };
+        let path = delta
+            .new_file()
+            .path()
+            .or(delta.old_file().path())
+            .map(|path| path.display().to_string())
+            .unwrap_or_default();
+        let Some(mut file_patch) = git2::Patch::from_diff(diff, idx).map_err(Error::from)? else {
+            return Ok(None);
+        };
+
+        let mut patch = String::new();
+        let mut non_utf8 = false;
+        file_patch
+            .print(&mut |_delta, _hunk, line| {
+                // Content lines carry their +/-/space marker separately
+                if matches!(line.origin(), '+' | '-' | ' ') {
+                    patch.push(line.origin());
+                }
+                // Keep lines from e.g. latin-1 files rather than dropping them
+                let content = String::from_utf8_lossy(line.content());
+                if let Cow::Owned(_) = content {
+                    non_utf8 = true;
+                }
+                patch.push_str(&content);
+                true
+            })
+            .map_err(Error::from)?;
+
+        if non_utf8 && self.verbose {
+            eprintln!("Note: {path} is not valid UTF-8; invalid bytes are shown as \u{FFFD}");
+        }
+        Ok(Some((path, patch)))
+    }
+
+    /// Returns the full messages (subject and body) of the last `n` commits
+    pub fn get_last_commit_messages(&self, n: usize) -> Result<Vec<String>> {
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push_head()?;
+        let mut messages = Vec::new();
+
+        for oid_result in revwalk.take(n) {
+            let oid = oid_result?;
+            let commit = self.repo.find_commit(oid)?;
+            let message = String::from_utf8_lossy(commit.message_bytes())
+                .trim()
+                .to_string();
+            if !message.is_empty() {
+                messages.push(message);
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Runs `git diff --cached` with extra arguments for options libgit2 lacks
+    fn get_git_cli_staged_diff(&self, extra_args: &[&str]) -> Result<String> {
+        let workdir = self
+            .repo
+            .workdir()
+            .ok_or_else(|| anyhow!("Repository has no working directory"))?;
+
+        let output = Command::new("git")
+            .arg("--git-dir")
+            .arg(self.repo.path())
+            .arg("--work-tree")
+            .arg(workdir)
+            .args(["diff", "--cached", "--no-color", "--no-ext-diff"])
+            .args(extra_args)
+            .args(
+                self.diff_filter
+                    .as_ref()
+                    .map(|filter| format!("--diff-filter={}", filter.spec)),
+            )
+            // Read the repository's own index, as libgit2 does
+            .env_remove("GIT_INDEX_FILE")
+            .current_dir(workdir)
+            .output();
+
+        match output {
+            Ok(output) if output.status.success() => {
+                let diff_text = String::from_utf8_lossy(&output.stdout).to_string();
+                if diff_text.is_empty() && self.verbose {
+                    self.debug_staging_status()?;
+                }
+                Ok(diff_text)
+            }
+            Ok(output) => Err(anyhow!(
+                "git diff failed: {}",
+                String::from_utf8_lossy(&output.stderr)
+            )
+            .into()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Err(anyhow!(
+                "git not found in PATH. The selected diff algorithm requires the git command line tool."
+            )
+            .into()),
+            Err(e) => Err(anyhow!("Failed to run git: {}", e).into()),
+        }
+    }
+
+    pub fn get_last_commit_titles(&self, n: usize) -> Result<Vec<String>> {
+        let mut revwalk = self.repo.revwalk()?;
+        revwalk.push_head()?;
+        let mut titles = Vec::new();
+
+        for oid_result in revwalk.take(n) {
+            let oid = oid_result?;
+            let commit = self.repo.find_commit(oid)?;
+            let message = commit.summary().unwrap_or("").trim().to_string();
+            if !message.is_empty() {
+                titles.push(message);
+            }
+        }
+        Ok(titles)
+    }
+
+    fn debug_staging_status(&self) -> Result<()> {
+        eprintln!("Debug: No changes detected in staging area. Checking repository status:");
+
+        let mut status_opts = StatusOptions::new();
+        status_opts.include_untracked(true);
+
+        let statuses = self.repo.statuses(Some(&mut status_opts))?;
+
+        if statuses.is_empty() {
+            eprintln!("Debug: No changes in the repository");
+            return Ok(());
+        }
+
+        eprintln!("Debug: Found {} changed files:", statuses.len());
+        for entry in statuses.iter() {
+            let status = entry.status();
+            let is_staged = status
+                .intersects(Status::INDEX_NEW | Status::INDEX_MODIFIED | Status::INDEX_DELETED);
+            let path = String::from_utf8_lossy(entry.path_bytes());
+
+            eprintln!("Debug: {path} - staged: {is_staged}, status: {status:?}");
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs `command` like `Command::output`, but kills it once `timeout` has
+/// passed. Returns None if it had to be killed.
+fn output_with_timeout(
+    command: &mut Command,
+    timeout: Duration,
+) -> std::io::Result<Option<Output>> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Drain the pipes while waiting, so a chatty child can't block on a full one
+    fn drain(pipe: Option<impl Read + Send + 'static>) -> Option<JoinHandle<Vec<u8>>> {
+        pipe.map(|mut pipe| {
+            std::thread::spawn(move || {
+                let mut buffer = Vec::new();
+                let _ = pipe.read_to_end(&mut buffer);
+                buffer
+            })
+        })
+    }
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            // It may have exited in the meantime; either way it's done
+            let _ = child.kill();
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    let collect = |reader: Option<JoinHandle<Vec<u8>>>| {
+        reader
+            .and_then(|reader| reader.join().ok())
+            .unwrap_or_default()
+    };
+    Ok(Some(Output {
+        status,
+        stdout: collect(stdout),
+        stderr: collect(stderr),
+    }))
+}
+
+/// libgit2 options for the algorithms it implements
+fn diff_options(alg: &DiffAlg) -> DiffOptions {
+    let mut options = DiffOptions::new();
+    match alg {
+        DiffAlg::Patience => {
+            options.patience(true);
+        }
+        DiffAlg::Minimal => {
+            options.minimal(true);
+        }
+        _ => {}
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::{self};
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    fn setup_test_repo() -> (TempDir, PathBuf) {
+        let temp_dir = TempDir::new().unwrap();
+        let repo_path = temp_dir.path().to_path_buf();
+
+        // Initialize a git repository
+        let repo = git2::Repository::init(&repo_path).unwrap();
+
+        // Create an initial commit on master branch
+        let file_path = repo_path.join("test.txt");
+        fs::write(&file_path, "initial content").unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
+        let tree_id = index.write_tree().unwrap();
+        let tree = repo.find_tree(tree_id).unwrap();
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+
+        // Create master branch with initial commit
+        repo.commit(
+            Some("refs/heads/master"),
+            &sig,
+            &sig,
+            "Initial commit",
+            &tree,
+            &[],
+        )
+        .unwrap();
+
+        // Set HEAD to point to master branch
+        repo.set_head("refs/heads/master").unwrap();
+
+        (temp_dir, repo_path)
+    }
+
+    const NOTES: &str = "one\ntwo\nthree\nfour\nfive\n";
+
+    /// Commits files on top of `parent` to `refs/heads/<branch>`
+    fn commit_files(
+        repo: &git2::Repository,
+        branch: &str,
+        parent: &git2::Commit,
+        files: &[(&str, &str)],
+    ) -> git2::Oid {
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+        let mut index = git2::Index::new().unwrap();
+        index.read_tree(&parent.tree().unwrap()).unwrap();
+        for (path, content) in files {
+            let mut entry = index.get_path(Path::new("test.txt"), 0).unwrap();
+            entry.id = repo.blob(content.as_bytes()).unwrap();
+            entry.path = path.as_bytes().to_vec();
+            entry.file_size = content.len() as u32;
+            index.add(&entry).unwrap();
+        }
+        let tree = repo.find_tree(index.write_tree_to(repo).unwrap()).unwrap();
+        let reference = format!("refs/heads/{branch}");
+        repo.commit(Some(&reference), &sig, &sig, branch, &tree, &[parent])
+            .unwrap()
+    }
+
+    /// Starts merging a `feature` branch into master the way `git merge`
+    /// leaves things before the commit: `MERGE_HEAD` written and the result
+    /// staged. Both sides changed test.txt, which conflicted and was resolved
+    /// by hand, and notes.txt, which merged cleanly.
+    fn start_merge(repo_path: &Path) -> git2::Oid {
+        let repo = git2::Repository::open(repo_path).unwrap();
+        let initial = repo.head().unwrap().peel_to_commit().unwrap();
+        let base = commit_files(&repo, "master", &initial, &[("notes.txt", NOTES)]);
+        let base = repo.find_commit(base).unwrap();
+
+        let feature = commit_files(
+            &repo,
+            "feature",
+            &base,
+            &[
+                ("feature.txt", "feature"),
+                ("test.txt", "feature side"),
+                ("notes.txt", &NOTES.replace("one", "ONE")),
+            ],
+        );
+        let master = commit_files(
+            &repo,
+            "master",
+            &base,
+            &[
+                ("test.txt", "master side"),
+                ("notes.txt", &NOTES.replace("five", "FIVE")),
+            ],
+        );
+        repo.checkout_tree(
+            repo.find_commit(master).unwrap().as_object(),
+            Some(git2::build::CheckoutBuilder::new().force()),
+        )
+        .unwrap();
+
+        fs::write(repo_path.join("feature.txt"), "feature").unwrap();
+        fs::write(repo_path.join("test.txt"), "both sides").unwrap();
+        let notes = NOTES.replace("one", "ONE").replace("five", "FIVE");
+        fs::write(repo_path.join("notes.txt"), notes).unwrap();
+        let mut index = repo.index().unwrap();
+        for path in ["feature.txt", "test.txt", "notes.txt"] {
+            index.add_path(Path::new(path)).unwrap();
+        }
+        index.write().unwrap();
+        fs::write(repo.path().join("MERGE_HEAD"), format!("{feature}\n")).unwrap();
+
+        feature
+    }
+
+    #[test]
+    fn test_stage_all_tracked_only() {
+        let (_temp_dir, repo_path) = setup_test_repo();
+        fs::write(repo_path.join("test.txt"), "modified content").unwrap();
+        fs::write(repo_path.join("new.txt"), "untracked").unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert_eq!(repo.stage_all(false).unwrap(), vec!["test.txt"]);
+
+        let staged = repo.get_staged_diff(DiffAlg::Default).unwrap();
+        assert_eq!(staged.paths, vec!["test.txt"]);
+        assert!(staged.text.contains("modified content"));
+
+        // Nothing left to stage
+        assert!(repo.stage_all(false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_stage_all_with_untracked_and_deletions() {
+        let (_temp_dir, repo_path) = setup_test_repo();
+        fs::remove_file(repo_path.join("test.txt")).unwrap();
+        fs::create_dir(repo_path.join("src")).unwrap();
+        fs::write(repo_path.join("src/new.rs"), "fn main() {}\n").unwrap();
+        fs::write(repo_path.join(".gitignore"), "*.log\n").unwrap();
+        fs::write(repo_path.join("debug.log"), "ignored").unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let mut staged = repo.stage_all(true).unwrap();
+        staged.sort();
+        assert_eq!(staged, vec![".gitignore", "src/new.rs", "test.txt"]);
+
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let index = git_repo.index().unwrap();
+        assert!(index.get_path(Path::new("test.txt"), 0).is_none());
+        assert!(index.get_path(Path::new("src/new.rs"), 0).is_some());
+        assert!(index.get_path(Path::new("debug.log"), 0).is_none());
+    }
+
+    #[test]
+    fn test_is_merging() {
+        let (_temp_dir, repo_path) = setup_test_repo();
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert!(!repo.is_merging());
+        assert!(repo.merge_info().unwrap().is_none());
+
+        start_merge(&repo_path);
+        assert!(repo.is_merging());
+    }
+
+    #[test]
+    fn test_merge_info() {
+        let (_temp_dir, repo_path) = setup_test_repo();
+        start_merge(&repo_path);
+        let repo = Repository::open(&repo_path, false).unwrap();
+
+        let merge = repo.merge_info().unwrap().unwrap();
+        assert_eq!(merge.merged, vec!["feature"]);
+        // feature.txt came from the branch unchanged and notes.txt merged
+        // cleanly; only test.txt conflicted
+        assert_eq!(merge.resolved_files, vec!["test.txt"]);
+    }
+
+    #[test]
+    fn test_merge_info_unresolved_conflict() {
+        let (_temp_dir, repo_path) = setup_test_repo();
+        let feature = start_merge(&repo_path);
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let master = git_repo.head().unwrap().peel_to_commit().unwrap();
+        let feature = git_repo.find_commit(feature).unwrap();
+        let mut merged = git_repo.merge_commits(&master, &feature, None).unwrap();
+        assert!(merged.has_conflicts());
+        git_repo.set_index(&mut merged).unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let merge = repo.merge_info().unwrap().unwrap();
+        assert_eq!(merge.resolved_files, vec!["test.txt"]);
+    }
+
+    #[test]
+    fn test_merge_info_unnamed_commit() {
+        let (_temp_dir, repo_path) = setup_test_repo();
+        let feature = start_merge(&repo_path);
+        git2::Repository::open(&repo_path)
+            .unwrap()
+            .find_branch("feature", git2::BranchType::Local)
+            .unwrap()
+            .delete()
+            .unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let merge = repo.merge_info().unwrap().unwrap();
+        assert_eq!(merge.merged, vec![feature.to_string()[..7].to_string()]);
+    }
+
+    #[test]
+    fn test_get_branch_description() {
+        let (_temp_dir, repo_path) = setup_test_repo();
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert_eq!(repo.get_branch_description().unwrap(), None);
+
+        // Set the way `git branch --edit-description` does
+        let mut config = git2::Repository::open(&repo_path)
+            .unwrap()
+            .config()
+            .unwrap();
+        config
+            .set_str(
+                "branch.master.description",
+                "Rework session handling\n\nSessions move to signed cookies.\n",
+            )
+            .unwrap();
+        // Other branches' descriptions don't count
+        config
+            .set_str("branch.feature.description", "Something else")
+            .unwrap();
+
+        assert_eq!(
+            repo.get_branch_description().unwrap().as_deref(),
+            Some("Rework session handling\n\nSessions move to signed cookies.")
+        );
+    }
+
+    #[test]
+    fn test_get_branch_description_blank() {
+        let (_temp_dir, repo_path) = setup_test_repo();
+        let mut config = git2::Repository::open(&repo_path)
+            .unwrap()
+            .config()
+            .unwrap();
+        config.set_str("branch.master.description", "  \n").unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert_eq!(repo.get_branch_description().unwrap(), None);
+    }
+
+    #[test]
+    fn test_open_repository() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let repo = Repository::open(&repo_path, false);
+
+        assert!(repo.is_ok());
+
+        // Keep temp_dir alive until the end of the test
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_open_non_repository_is_git_error() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let err = Repository::open(temp_dir.path(), false).err().unwrap();
+        assert!(matches!(err, Error::Git(_)));
+    }
+
+    #[test]
+    fn test_get_branch_name() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let repo = Repository::open(&repo_path, false).unwrap();
+
+        let branch_name = repo.get_branch_name();
+        assert!(branch_name.is_ok());
+        assert_eq!(branch_name.unwrap(), "master");
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_get_branch_name_in_worktree() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+
+        let head_commit = git_repo.head().unwrap().peel_to_commit().unwrap();
+        let branch = git_repo
+            .branch("feature/worktree", &head_commit, false)
+            .unwrap();
+
+        let worktree_dir = TempDir::new().unwrap();
+        let worktree_path = worktree_dir.path().join("wt");
+        let mut options = git2::WorktreeAddOptions::new();
+        options.reference(Some(branch.get()));
+        git_repo
+            .worktree("wt", &worktree_path, Some(&options))
+            .unwrap();
+
+        let repo = Repository::open(&worktree_path, false).unwrap();
+        assert_eq!(repo.get_branch_name().unwrap(), "feature/worktree");
+
+        // The main checkout still reports its own branch
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert_eq!(repo.get_branch_name().unwrap(), "master");
+
+        drop(worktree_dir);
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_get_branch_name_before_first_commit() {
+        let temp_dir = TempDir::new().unwrap();
+        let git_repo = git2::Repository::init(temp_dir.path()).unwrap();
+        git_repo.set_head("refs/heads/main").unwrap();
+
+        let repo = Repository::open(temp_dir.path(), false).unwrap();
+        assert_eq!(repo.get_branch_name().unwrap(), "main");
+    }
+
+    /// Adds a remote whose HEAD points at `branch`, like a fresh clone has
+    fn add_remote_with_head(git_repo: &git2::Repository, remote: &str, branch: &str) {
+        git_repo
+            .remote(remote, &format!("https://example.com/{remote}.git"))
+            .unwrap();
+        let head_id = git_repo.head().unwrap().target().unwrap();
+        let branch_ref = format!("refs/remotes/{remote}/{branch}");
+        git_repo
+            .reference(&branch_ref, head_id, true, "test")
+            .unwrap();
+        git_repo
+            .reference_symbolic(
+                &format!("refs/remotes/{remote}/HEAD"),
+                &branch_ref,
+                true,
+                "test",
+            )
+            .unwrap();
+    }
+
+    #[test]
+    fn test_head_is_ahead_of_base() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let repo = Repository::open(&repo_path, false).unwrap();
+        // On master itself
+        assert!(!repo.head_is_ahead_of_base().unwrap());
+
+        // A new branch with no commits of its own yet
+        let initial = git_repo.head().unwrap().peel_to_commit().unwrap();
+        git_repo.branch("feature", &initial, false).unwrap();
+        git_repo.set_head("refs/heads/feature").unwrap();
+        assert!(!repo.head_is_ahead_of_base().unwrap());
+
+        commit_files(&git_repo, "feature", &initial, &[("login.rs", "login")]);
+        assert!(repo.head_is_ahead_of_base().unwrap());
+
+        // Once master has it too, it is no longer the branch's own
+        let feature = git_repo.head().unwrap().target().unwrap();
+        git_repo
+            .reference("refs/heads/master", feature, true, "merge")
+            .unwrap();
+        assert!(!repo.head_is_ahead_of_base().unwrap());
+
+        // A base branch that doesn't exist tells nothing
+        git_repo
+            .config()
+            .unwrap()
+            .set_str("init.defaultBranch", "trunk")
+            .unwrap();
+        git_repo
+            .remote("origin", "https://example.com/origin.git")
+            .unwrap();
+        assert!(!repo.head_is_ahead_of_base().unwrap());
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_get_default_branch_from_remote_head() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        add_remote_with_head(&git_repo, "fork", "trunk");
+        add_remote_with_head(&git_repo, "origin", "develop");
+
+        // origin wins over other remotes
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert_eq!(
+            repo.get_default_branch().unwrap().as_deref(),
+            Some("develop")
+        );
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_get_default_branch_other_remote() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        add_remote_with_head(&git_repo, "upstream", "main");
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert_eq!(repo.get_default_branch().unwrap().as_deref(), Some("main"));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_get_default_branch_falls_back_to_config() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        git_repo
+            .remote("origin", "https://example.com/origin.git")
+            .unwrap();
+        git_repo
+            .config()
+            .unwrap()
+            .set_str("init.defaultBranch", "main")
+            .unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert_eq!(repo.get_default_branch().unwrap().as_deref(), Some("main"));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_get_default_branch_without_remote() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert_eq!(repo.get_default_branch().unwrap(), None);
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_get_staged_diff() {
+        let (temp_dir, repo_path) = setup_test_repo();
+
+        // Modify file and stage changes
+        let file_path = repo_path.join("test.txt");
+        fs::write(&file_path, "modified content").unwrap();
+
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let diff = repo.get_staged_diff(DiffAlg::Default);
+
+        assert!(diff.is_ok());
+        let diff_text = diff.unwrap().text;
+        assert!(diff_text.contains("modified content"));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_intent_to_add_files() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        fs::write(repo_path.join("new.rs"), "fn main() {}\n").unwrap();
+        let added = Command::new("git")
+            .args(["add", "--intent-to-add", "new.rs"])
+            .current_dir(&repo_path)
+            .status()
+            .unwrap();
+        assert!(added.success());
+
+        // Without its contents, the new file is still mentioned
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let staged = repo.get_staged_diff(DiffAlg::Default).unwrap();
+        assert_eq!(staged.paths, vec!["new.rs"]);
+        assert!(
+            staged
+                .text
+                .starts_with("diff --git a/new.rs b/new.rs\nnew file mode")
+        );
+        assert!(
+            staged
+                .text
+                .ends_with("+++ b/new.rs\n(added with git add -N; contents not staged)\n")
+        );
+
+        let repo = repo.with_include_intent_to_add(true);
+        let staged = repo.get_staged_diff(DiffAlg::Default).unwrap();
+        assert!(
+            staged
+                .text
+                .starts_with("diff --git a/new.rs b/new.rs\nnew file mode")
+        );
+        assert!(
+            staged
+                .text
+                .ends_with("+++ b/new.rs\n@@ -0,0 +1 @@\n+fn main() {}\n")
+        );
+
+        let files: Vec<_> = repo
+            .staged_file_diffs(DiffAlg::Default)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(files, vec![("new.rs".to_string(), staged.text)]);
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_staged_file_diffs() {
+        let (temp_dir, repo_path) = setup_test_repo();
+
+        fs::write(repo_path.join("test.txt"), "modified content").unwrap();
+        fs::write(repo_path.join("other.txt"), "other content").unwrap();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.add_path(Path::new("other.txt")).unwrap();
+        index.write().unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let files: Vec<_> = repo
+            .staged_file_diffs(DiffAlg::Default)
+            .unwrap()
+            .map(Result::unwrap)
+            .collect();
+
+        // Each file gets only its own part of the diff, in diff order
+        assert_eq!(files.len(), 2);
+        let (path, patch) = &files[0];
+        assert_eq!(path, "other.txt");
+        assert!(patch.starts_with("diff --git a/other.txt b/other.txt"));
+        assert!(patch.contains("+other content") && !patch.contains("test.txt"));
+        let (path, patch) = &files[1];
+        assert_eq!(path, "test.txt");
+        assert!(patch.contains("+modified content") && !patch.contains("other.txt"));
+
+        // Together they make up the whole diff
+        let whole = repo.get_staged_diff(DiffAlg::Default).unwrap().text;
+        assert_eq!(whole, format!("{}{}", files[0].1, files[1].1));
+
+        // The stats come without the diff
+        let stats = repo.get_staged_stats(DiffAlg::Default).unwrap();
+        assert!(stats.text.is_empty());
+        assert_eq!(stats.paths, vec!["other.txt", "test.txt"]);
+        assert_eq!(stats.files_changed, 2);
+
+        drop(temp_dir);
+    }
+
+    /// Commits `content` as `test.txt` on top of HEAD
+    fn commit_change(repo_path: &Path, content: &str, message: &str) -> git2::Oid {
+        let git_repo = git2::Repository::open(repo_path).unwrap();
+        fs::write(repo_path.join("test.txt"), content).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+        let tree = git_repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let head = git_repo.head().unwrap().peel_to_commit().unwrap();
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+        git_repo
+            .commit(Some("HEAD"), &sig, &sig, message, &tree, &[&head])
+            .unwrap()
+    }
+
+    #[test]
+    fn test_get_head_diff() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let repo = Repository::open(&repo_path, false).unwrap();
+
+        // The root commit is compared with an empty tree
+        let root = repo.get_head_diff(DiffAlg::Default).unwrap();
+        assert_eq!(root.paths, vec!["test.txt"]);
+        assert!(root.text.contains("+initial content"));
+
+        commit_change(&repo_path, "changed content", "wip");
+        // Staged changes don't leak into the last commit's diff
+        fs::write(repo_path.join("staged.txt"), "staged").unwrap();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("staged.txt")).unwrap();
+        index.write().unwrap();
+
+        let head = repo.get_head_diff(DiffAlg::Default).unwrap();
+        assert_eq!(head.paths, vec!["test.txt"]);
+        assert!(head.text.contains("-initial content"));
+        assert!(head.text.contains("+changed content"));
+        assert!(!head.text.contains("staged"));
+        assert_eq!((head.insertions, head.deletions), (1, 1));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_amend_head_message() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let old = commit_change(&repo_path, "changed content", "wip");
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let new = repo.amend_head_message("fix: change the content").unwrap();
+        assert_ne!(new, old);
+
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let head = git_repo.head().unwrap().peel_to_commit().unwrap();
+        let old = git_repo.find_commit(old).unwrap();
+        assert_eq!(head.id(), new);
+        assert_eq!(head.message(), Some("fix: change the content\n"));
+        assert_eq!(head.tree_id(), old.tree_id());
+        assert_eq!(
+            head.parent_ids().collect::<Vec<_>>(),
+            old.parent_ids().collect::<Vec<_>>()
+        );
+        assert_eq!(head.author().name(), Some("Test"));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_commit() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut config = git_repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        let initial = git_repo.head().unwrap().target().unwrap();
+
+        fs::write(repo_path.join("test.txt"), "changed content").unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let oid = repo.commit("feat: change the content\n\n").unwrap();
+
+        let head = git_repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id(), oid);
+        assert_eq!(head.message(), Some("feat: change the content\n"));
+        assert_eq!(head.parent_ids().collect::<Vec<_>>(), vec![initial]);
+        assert!(git_repo.extract_signature(&oid, None).is_err());
+        // The staged change is what was committed
+        assert!(
+            repo.get_staged_diff(DiffAlg::Default)
+                .unwrap()
+                .text
+                .is_empty()
+        );
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_commit_concludes_merge() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let feature = start_merge(&repo_path);
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut config = git_repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        let master = git_repo.head().unwrap().target().unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert!(repo.is_merging());
+        let oid = repo.commit("Merge branch 'feature'").unwrap();
+
+        let head = git_repo.find_commit(oid).unwrap();
+        assert_eq!(head.parent_ids().collect::<Vec<_>>(), vec![master, feature]);
+        assert!(!repo.is_merging());
+
+        drop(temp_dir);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_commit_runs_hooks() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (temp_dir, repo_path) = setup_test_repo();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut config = git_repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        fs::write(repo_path.join("test.txt"), "changed content").unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
+        // A commit-msg hook that rejects everything stops the commit
+        let hook = git_repo.path().join("hooks").join("commit-msg");
+        fs::create_dir_all(hook.parent().unwrap()).unwrap();
+        fs::write(&hook, "#!/bin/sh\necho rejected by hook >&2\nexit 1\n").unwrap();
+        fs::set_permissions(&hook, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let err = repo.commit("feat: change the content").unwrap_err();
+        assert!(err.to_string().contains("rejected by hook"));
+        assert_eq!(
+            git_repo.head().unwrap().peel_to_commit().unwrap().message(),
+            Some("Initial commit")
+        );
+
+        drop(temp_dir);
+    }
+
+    /// Turns on `commit.gpgsign` with a script that stands in for gpg,
+    /// answering the way git expects it to. The script lives in the
+    /// returned directory.
+    #[cfg(unix)]
+    fn configure_fake_signing(git_repo: &git2::Repository) -> TempDir {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script_dir = TempDir::new().unwrap();
+        let fake_gpg = script_dir.path().join("fake-gpg");
+        fs::write(
+            &fake_gpg,
+            "#!/bin/sh\ncat >/dev/null\nprintf '\\n[GNUPG:] SIG_CREATED D 1 8 00 0 FAKE\\n' >&2\n\
+             printf -- '-----BEGIN PGP SIGNATURE-----\\n\\nfake\\n-----END PGP SIGNATURE-----\\n'\n",
+        )
+        .unwrap();
+        fs::set_permissions(&fake_gpg, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut config = git_repo.config().unwrap();
+        config.set_str("user.name", "Test").unwrap();
+        config.set_str("user.email", "test@example.com").unwrap();
+        config.set_bool("commit.gpgsign", true).unwrap();
+        config.set_str("gpg.format", "openpgp").unwrap();
+        config
+            .set_str("gpg.program", fake_gpg.to_str().unwrap())
+            .unwrap();
+        script_dir
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_commit_signs_when_configured() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let _script_dir = configure_fake_signing(&git_repo);
+
+        fs::write(repo_path.join("test.txt"), "changed content").unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let oid = repo.commit("feat: change the content").unwrap();
+
+        let head = git_repo.head().unwrap().peel_to_commit().unwrap();
+        assert_eq!(head.id(), oid);
+        assert_eq!(head.message(), Some("feat: change the content\n"));
+        let (signature, _) = git_repo.extract_signature(&oid, None).unwrap();
+        assert!(signature.as_str().unwrap().contains("fake"));
+
+        drop(temp_dir);
+    }
 
-                if self.verbose && head.is_none() {
-                    println!("Debug: Repository has no HEAD commit yet");
-                }
+    #[cfg(unix)]
+    #[test]
+    fn test_amend_head_message_signs_when_configured() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let old = commit_change(&repo_path, "changed content", "wip");
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let _script_dir = configure_fake_signing(&git_repo);
 
-                let mut options = DiffOptions::new();
-                match alg {
-                    DiffAlg::Patience => {
-                        options.patience(true);
-                    }
-                    DiffAlg::Minimal => {
-                        options.minimal(true);
-                    }
-                    _ => {}
-                }
+        // Something staged since stays out of the rewritten commit
+        fs::write(repo_path.join("staged.txt"), "staged").unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("staged.txt")).unwrap();
+        index.write().unwrap();
 
-                let diff = self
-                    .repo
-                    .diff_tree_to_index(tree.as_ref(), None, Some(&mut options))?;
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let new = repo.amend_head_message("fix: change the content").unwrap();
+
+        let head = git_repo.find_commit(new).unwrap();
+        let old = git_repo.find_commit(old).unwrap();
+        assert_eq!(head.message(), Some("fix: change the content\n"));
+        assert_eq!(head.tree_id(), old.tree_id());
+        assert_eq!(
+            head.parent_ids().collect::<Vec<_>>(),
+            old.parent_ids().collect::<Vec<_>>()
+        );
+        let (signature, _) = git_repo.extract_signature(&new, None).unwrap();
+        assert!(signature.as_str().unwrap().contains("fake"));
+        assert_eq!(
+            repo.get_staged_diff(DiffAlg::Default).unwrap().paths,
+            vec!["staged.txt"]
+        );
+
+        drop(temp_dir);
+    }
 
-                let mut diff_text = String::new();
+    #[test]
+    fn test_remote_branches_containing_head() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert!(repo.remote_branches_containing_head().unwrap().is_empty());
 
-                diff.print(git2::DiffFormat::Patch, |_delta, _hunk, line| {
-                    if let Ok(content) = std::str::from_utf8(line.content()) {
-                        diff_text.push_str(content);
-                    }
-                    true
-                })?;
+        // Pushed: the remote-tracking branch points at HEAD
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let pushed = git_repo.head().unwrap().target().unwrap();
+        git_repo
+            .reference("refs/remotes/origin/master", pushed, true, "push")
+            .unwrap();
+        assert_eq!(
+            repo.remote_branches_containing_head().unwrap(),
+            vec!["origin/master"]
+        );
 
-                if diff_text.is_empty() && self.verbose {
-                    self.debug_staging_status()?;
-                }
+        // A new local commit isn't on the remote yet
+        commit_change(&repo_path, "changed content", "wip");
+        assert!(repo.remote_branches_containing_head().unwrap().is_empty());
 
-                Ok(diff_text)
-            }
-        }
+        drop(temp_dir);
     }
 
-    pub fn get_last_commit_titles(&self, n: usize) -> Result<Vec<String>> {
-        let mut revwalk = self.repo.revwalk()?;
-        revwalk.push_head()?;
-        let mut titles = Vec::new();
+    #[cfg(unix)]
+    #[test]
+    fn test_difftastic_timeout_falls_back() {
+        use std::os::unix::fs::PermissionsExt;
 
-        for oid_result in revwalk.take(n) {
-            let oid = oid_result?;
-            let commit = self.repo.find_commit(oid)?;
-            let message = commit.summary().unwrap_or("").trim().to_string();
-            if !message.is_empty() {
-                titles.push(message);
-            }
-        }
-        Ok(titles)
+        let (temp_dir, repo_path) = setup_test_repo();
+        fs::write(repo_path.join("test.txt"), "modified content").unwrap();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
+        // Stands in for difft hanging on a pathological file
+        let script_dir = TempDir::new().unwrap();
+        let slow_difft = script_dir.path().join("slow-difft");
+        fs::write(&slow_difft, "#!/bin/sh\nsleep 5\necho never\n").unwrap();
+        fs::set_permissions(&slow_difft, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let repo = Repository::open(&repo_path, false)
+            .unwrap()
+            .with_difft_timeout(Duration::from_millis(200));
+        let started = Instant::now();
+        let diff = repo
+            .difftastic_file_diff(slow_difft.to_str().unwrap(), "test.txt")
+            .unwrap()
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(4));
+        assert!(diff.starts_with("diff --git a/test.txt b/test.txt"));
+        assert!(diff.contains("+modified content"));
+        assert!(!diff.contains("never"));
+
+        // A quick difft is used as is
+        let quick_difft = script_dir.path().join("quick-difft");
+        fs::write(&quick_difft, "#!/bin/sh\necho structural change\n").unwrap();
+        fs::set_permissions(&quick_difft, fs::Permissions::from_mode(0o755)).unwrap();
+        let diff = repo
+            .difftastic_file_diff(quick_difft.to_str().unwrap(), "test.txt")
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            diff,
+            "diff --difftastic a/test.txt b/test.txt\nstructural change\n"
+        );
+
+        drop(temp_dir);
     }
 
-    fn debug_staging_status(&self) -> Result<()> {
-        println!("Debug: No changes detected in staging area. Checking repository status:");
+    #[test]
+    fn test_diff_filter_parse() {
+        let filter = DiffFilter::parse("AM").unwrap();
+        assert!(filter.keeps(Delta::Added) && filter.keeps(Delta::Modified));
+        assert!(!filter.keeps(Delta::Deleted) && !filter.keeps(Delta::Renamed));
+
+        // Lower case excludes, keeping everything else
+        let filter = DiffFilter::parse("d").unwrap();
+        assert!(!filter.keeps(Delta::Deleted));
+        assert!(filter.keeps(Delta::Added) && filter.keeps(Delta::Renamed));
+
+        assert!(DiffFilter::parse("AX").is_err());
+        assert!(DiffFilter::parse("").is_err());
+    }
 
-        let mut status_opts = StatusOptions::new();
-        status_opts.include_untracked(true);
+    #[test]
+    fn test_staged_diff_with_diff_filter() {
+        let (temp_dir, repo_path) = setup_test_repo();
 
-        let statuses = self.repo.statuses(Some(&mut status_opts))?;
+        // Stage an addition and a deletion
+        fs::write(repo_path.join("added.txt"), "new file\nsecond line\n").unwrap();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("added.txt")).unwrap();
+        index.remove_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
 
-        if statuses.is_empty() {
-            println!("Debug: No changes in the repository");
-            return Ok(());
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let staged = repo.get_staged_diff(DiffAlg::Default).unwrap();
+        assert_eq!(staged.paths, vec!["added.txt", "test.txt"]);
+
+        for spec in ["A", "d"] {
+            let repo = Repository::open(&repo_path, false)
+                .unwrap()
+                .with_diff_filter(Some(DiffFilter::parse(spec).unwrap()));
+            let staged = repo.get_staged_diff(DiffAlg::Default).unwrap();
+            assert_eq!(staged.paths, vec!["added.txt"]);
+            assert!(staged.text.contains("+new file"));
+            assert!(!staged.text.contains("initial content"));
+            assert_eq!(
+                (staged.files_changed, staged.insertions, staged.deletions),
+                (1, 2, 0)
+            );
+
+            // The git CLI is given the same filter
+            if command_exists("git") {
+                let staged = repo.get_staged_diff(DiffAlg::Histogram).unwrap();
+                assert!(staged.text.contains("+new file"));
+                assert!(!staged.text.contains("initial content"));
+            }
         }
 
-        println!("Debug: Found {} changed files:", statuses.len());
-        for entry in statuses.iter() {
-            let status = entry.status();
-            let is_staged = status
-                .intersects(Status::INDEX_NEW | Status::INDEX_MODIFIED | Status::INDEX_DELETED);
-            let path = String::from_utf8_lossy(entry.path_bytes());
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_staged_deletion_shows_removed_content() {
+        let (temp_dir, repo_path) = setup_test_repo();
 
-            println!("Debug: {path} - staged: {is_staged}, status: {status:?}");
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.remove_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let mut algorithms = vec![DiffAlg::Default, DiffAlg::Patience, DiffAlg::Minimal];
+        if command_exists("git") {
+            algorithms.push(DiffAlg::Histogram);
+        }
+        for alg in algorithms {
+            let staged = repo.get_staged_diff(alg).unwrap();
+            assert!(staged.text.contains("deleted file mode"));
+            // Every removed line is shown, not just the deletion itself
+            assert!(staged.text.contains("-initial content"));
+            assert_eq!((staged.insertions, staged.deletions), (0, 1));
         }
 
-        Ok(())
+        drop(temp_dir);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::env;
-    use std::fs;
-    use std::io::{self};
-    use std::path::PathBuf;
-    use tempfile::TempDir;
+    #[test]
+    fn test_staged_diff_fields_are_consistent() {
+        let (temp_dir, repo_path) = setup_test_repo();
 
-    fn setup_test_repo() -> (TempDir, PathBuf) {
-        let temp_dir = TempDir::new().unwrap();
-        let repo_path = temp_dir.path().to_path_buf();
+        // One modified file and one renamed file, from a single staged change
+        fs::write(repo_path.join("notes.md"), "one\ntwo\nthree\nfour\n").unwrap();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("notes.md")).unwrap();
+        index.write().unwrap();
+        let tree = git_repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let head = git_repo.head().unwrap().peel_to_commit().unwrap();
+        let signature = git2::Signature::now("Test User", "test@example.com").unwrap();
+        git_repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "Add notes",
+                &tree,
+                &[&head],
+            )
+            .unwrap();
 
-        // Initialize a git repository
-        let repo = git2::Repository::init(&repo_path).unwrap();
+        fs::rename(repo_path.join("notes.md"), repo_path.join("NOTES.md")).unwrap();
+        fs::write(repo_path.join("test.txt"), "modified content\nsecond line").unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.remove_path(Path::new("notes.md")).unwrap();
+        index.add_path(Path::new("NOTES.md")).unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let staged = repo.get_staged_diff(DiffAlg::Default).unwrap();
+
+        let mut paths = staged.paths.clone();
+        paths.sort();
+        assert_eq!(paths, vec!["NOTES.md", "test.txt"]);
+        assert_eq!(
+            staged.renames,
+            vec![("notes.md".to_string(), "NOTES.md".to_string())]
+        );
+        assert_eq!(staged.files_changed, staged.paths.len());
+        assert_eq!(staged.insertions, 2);
+        assert_eq!(staged.deletions, 1);
+
+        // The text describes the same change
+        assert!(
+            staged
+                .text
+                .contains("rename from notes.md\nrename to NOTES.md")
+        );
+        assert!(staged.text.contains("\n-initial content"));
+        assert!(staged.text.contains("\n+modified content"));
+        assert!(staged.text.contains("\n+second line"));
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_get_staged_diff_histogram() {
+        let (temp_dir, repo_path) = setup_test_repo();
 
-        // Create an initial commit on master branch
         let file_path = repo_path.join("test.txt");
-        fs::write(&file_path, "initial content").unwrap();
+        fs::write(&file_path, "histogram content").unwrap();
 
-        let mut index = repo.index().unwrap();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
         index.add_path(Path::new("test.txt")).unwrap();
         index.write().unwrap();
 
-        let tree_id = index.write_tree().unwrap();
-        let tree = repo.find_tree(tree_id).unwrap();
-        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
-
-        // Create master branch with initial commit
-        repo.commit(
-            Some("refs/heads/master"),
-            &sig,
-            &sig,
-            "Initial commit",
-            &tree,
-            &[],
-        )
-        .unwrap();
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let diff_text = repo.get_staged_diff(DiffAlg::Histogram).unwrap().text;
 
-        // Set HEAD to point to master branch
-        repo.set_head("refs/heads/master").unwrap();
+        assert!(diff_text.contains("diff --git a/test.txt b/test.txt"));
+        assert!(diff_text.contains("-initial content"));
+        assert!(diff_text.contains("+histogram content"));
 
-        (temp_dir, repo_path)
+        drop(temp_dir);
     }
 
     #[test]
-    fn test_open_repository() {
+    fn test_get_staged_diff_word() {
         let (temp_dir, repo_path) = setup_test_repo();
-        let repo = Repository::open(&repo_path, false);
 
-        assert!(repo.is_ok());
+        let file_path = repo_path.join("test.txt");
+        fs::write(&file_path, "initial wording").unwrap();
+
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
+        let repo = Repository::open(&repo_path, false).unwrap();
+        let diff_text = repo.get_staged_diff(DiffAlg::Word).unwrap().text;
+
+        // Only the changed word is marked, the rest of the line is context
+        assert!(diff_text.contains("initial [-content-]{+wording+}"));
 
-        // Keep temp_dir alive until the end of the test
         drop(temp_dir);
     }
 
     #[test]
-    fn test_get_branch_name() {
+    fn test_get_staged_diff_has_line_markers() {
         let (temp_dir, repo_path) = setup_test_repo();
+
+        fs::write(repo_path.join("test.txt"), "modified content").unwrap();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
         let repo = Repository::open(&repo_path, false).unwrap();
+        let diff_text = repo.get_staged_diff(DiffAlg::Default).unwrap().text;
 
-        let branch_name = repo.get_branch_name();
-        assert!(branch_name.is_ok());
-        assert_eq!(branch_name.unwrap(), "master");
+        assert!(diff_text.contains("\n-initial content"));
+        assert!(diff_text.contains("\n+modified content"));
 
         drop(temp_dir);
     }
 
     #[test]
-    fn test_get_staged_diff() {
+    fn test_get_staged_diff_non_utf8() {
         let (temp_dir, repo_path) = setup_test_repo();
 
-        // Modify file and stage changes
-        let file_path = repo_path.join("test.txt");
-        fs::write(&file_path, "modified content").unwrap();
-
+        // "café au lait" in latin-1
+        fs::write(
+            repo_path.join("test.txt"),
+            b"caf\xe9 au lait\nsecond line\n",
+        )
+        .unwrap();
         let git_repo = git2::Repository::open(&repo_path).unwrap();
         let mut index = git_repo.index().unwrap();
         index.add_path(Path::new("test.txt")).unwrap();
         index.write().unwrap();
 
         let repo = Repository::open(&repo_path, false).unwrap();
-        let diff = repo.get_staged_diff(DiffAlg::Default);
+        let diff_text = repo.get_staged_diff(DiffAlg::Default).unwrap().text;
 
-        assert!(diff.is_ok());
-        let diff_text = diff.unwrap();
-        assert!(diff_text.contains("modified content"));
+        assert!(diff_text.contains("\n+caf\u{FFFD} au lait\n"));
+        assert!(diff_text.contains("\n+second line"));
 
         drop(temp_dir);
     }
 
+    #[test]
+    fn test_summarize_submodule_changes() {
+        let diff = "\
+diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1 +1 @@
+-old line
++new line
+diff --git a/vendor/lib b/vendor/lib
+index 3a4b5c6d7e8f..9f8e7d6c5b4a 160000
+--- a/vendor/lib
++++ b/vendor/lib
+@@ -1 +1 @@
+-Subproject commit 3a4b5c6d7e8f9a0b1c2d3e4f5a6b7c8d9e0f1a2b
++Subproject commit 9f8e7d6c5b4a3f2e1d0c9b8a7f6e5d4c3b2a1f0e
+diff --git a/deps/new b/deps/new
+new file mode 160000
+index 0000000..abcdef1
+--- /dev/null
++++ b/deps/new
+@@ -0,0 +1 @@
++Subproject commit abcdef1234567890abcdef1234567890abcdef12
+";
+
+        let summarized = summarize_submodule_changes(diff);
+
+        // Regular file changes are untouched
+        assert!(summarized.starts_with("diff --git a/src/lib.rs b/src/lib.rs\n"));
+        assert!(summarized.contains("-old line\n+new line\n"));
+
+        assert!(summarized.contains("submodule vendor/lib updated 3a4b5c6..9f8e7d6\n"));
+        assert!(summarized.contains("submodule deps/new added at abcdef1\n"));
+        assert!(!summarized.contains("Subproject commit"));
+    }
+
     #[test]
     fn test_open_current_dir() {
         let (temp_dir, repo_path) = setup_test_repo();
@@ -321,6 +2395,47 @@ mod tests {
         drop(temp_dir);
     }
 
+    #[test]
+    fn test_open_with_git_env() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let elsewhere = TempDir::new().unwrap();
+        let same_dir = |a: &Path, b: &Path| a.canonicalize().unwrap() == b.canonicalize().unwrap();
+
+        // GIT_DIR points at the repository from an unrelated directory
+        let git_dir = repo_path.join(".git").into_os_string();
+        let repo =
+            Repository::open_with_git_env(elsewhere.path(), Some(git_dir.clone()), None, false)
+                .unwrap();
+        assert!(same_dir(repo.workdir().unwrap(), &repo_path));
+        assert_eq!(repo.get_branch_name().unwrap(), "master");
+
+        // GIT_WORK_TREE overrides the working tree
+        let repo = Repository::open_with_git_env(
+            &repo_path,
+            Some(git_dir),
+            Some(elsewhere.path().as_os_str().to_owned()),
+            false,
+        )
+        .unwrap();
+        assert!(same_dir(repo.workdir().unwrap(), elsewhere.path()));
+
+        // Relative to the current directory
+        let repo =
+            Repository::open_with_git_env(&repo_path, Some(OsString::from(".git")), None, false)
+                .unwrap();
+        assert!(same_dir(repo.workdir().unwrap(), &repo_path));
+
+        // Without them, the repository is found from a subdirectory
+        fs::create_dir(repo_path.join("src")).unwrap();
+        let repo =
+            Repository::open_with_git_env(&repo_path.join("src"), None, None, false).unwrap();
+        assert!(same_dir(repo.workdir().unwrap(), &repo_path));
+
+        assert!(Repository::open_with_git_env(elsewhere.path(), None, None, false).is_err());
+
+        drop(temp_dir);
+    }
+
     #[test]
     fn test_detached_head() {
         let (temp_dir, repo_path) = setup_test_repo();
@@ -409,7 +2524,7 @@ mod tests {
         let diff = repo.get_staged_diff(DiffAlg::Default);
 
         assert!(diff.is_ok());
-        assert_eq!(diff.unwrap(), "");
+        assert_eq!(diff.unwrap().text, "");
 
         drop(temp_dir);
     }
@@ -501,4 +2616,98 @@ mod tests {
 
         drop(temp_dir);
     }
+
+    #[test]
+    fn test_get_last_commit_messages_includes_bodies() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let repo = Repository::open(&repo_path, false).unwrap();
+
+        let file_path = repo_path.join("test.txt");
+        fs::write(&file_path, "second commit").unwrap();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+        let tree_id = index.write_tree().unwrap();
+        let tree = git_repo.find_tree(tree_id).unwrap();
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent_commit = git_repo.head().unwrap().peel_to_commit().unwrap();
+        git_repo
+            .commit(
+                Some("refs/heads/master"),
+                &sig,
+                &sig,
+                "feat(core): add second commit\n\nExplain why.\n- detail one\n- detail two\n",
+                &tree,
+                &[&parent_commit],
+            )
+            .unwrap();
+
+        let messages = repo.get_last_commit_messages(2).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            messages[0],
+            "feat(core): add second commit\n\nExplain why.\n- detail one\n- detail two"
+        );
+        assert_eq!(messages[1], "Initial commit");
+
+        // Titles stay lightweight
+        let titles = repo.get_last_commit_titles(1).unwrap();
+        assert_eq!(titles, vec!["feat(core): add second commit"]);
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_staged_diff_paths() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let repo = Repository::open(&repo_path, false).unwrap();
+        assert!(
+            repo.get_staged_diff(DiffAlg::Default)
+                .unwrap()
+                .paths
+                .is_empty()
+        );
+
+        fs::create_dir_all(repo_path.join("packages/web")).unwrap();
+        fs::write(repo_path.join("packages/web/index.js"), "export {}").unwrap();
+        fs::write(repo_path.join("test.txt"), "modified content").unwrap();
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("packages/web/index.js")).unwrap();
+        index.add_path(Path::new("test.txt")).unwrap();
+        index.write().unwrap();
+
+        let mut paths = repo.get_staged_diff(DiffAlg::Default).unwrap().paths;
+        paths.sort();
+        assert_eq!(paths, vec!["packages/web/index.js", "test.txt"]);
+
+        drop(temp_dir);
+    }
+
+    #[test]
+    fn test_get_last_commit_titles_keeps_emoji() {
+        let (temp_dir, repo_path) = setup_test_repo();
+        let repo = Repository::open(&repo_path, false).unwrap();
+
+        let git_repo = git2::Repository::open(&repo_path).unwrap();
+        let tree = git_repo.head().unwrap().peel_to_tree().unwrap();
+        let sig = git2::Signature::now("Test", "test@example.com").unwrap();
+        let parent_commit = git_repo.head().unwrap().peel_to_commit().unwrap();
+        git_repo
+            .commit(
+                Some("refs/heads/master"),
+                &sig,
+                &sig,
+                "✨ add gitmoji support",
+                &tree,
+                &[&parent_commit],
+            )
+            .unwrap();
+
+        let titles = repo.get_last_commit_titles(1).unwrap();
+        assert_eq!(titles, vec!["✨ add gitmoji support"]);
+
+        drop(temp_dir);
+    }
 }
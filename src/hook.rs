@@ -0,0 +1,174 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+use crate::commit;
+
+/// A commit message file as git hands it to a `prepare-commit-msg` hook:
+/// empty, the `#` comments git adds below the message, a template, or a
+/// message the user (or a merge) already wrote
+#[derive(Debug)]
+pub struct CommitMsgFile {
+    path: PathBuf,
+    content: String,
+    comments: Vec<String>,
+}
+
+impl CommitMsgFile {
+    /// Reads the file, treating a missing one as empty
+    pub fn read(path: &Path) -> Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+            }
+        };
+
+        let (comments, content): (Vec<&str>, Vec<&str>) =
+            text.lines().partition(|line| line.starts_with('#'));
+        Ok(Self {
+            path: path.to_path_buf(),
+            content: content.join("\n").trim().to_string(),
+            comments: comments.into_iter().map(String::from).collect(),
+        })
+    }
+
+    /// The non-comment text already in the file
+    pub fn content(&self) -> &str {
+        &self.content
+    }
+
+    /// Whether the file already holds a message that passes the Conventional
+    /// Commits check, so there is nothing to generate
+    pub fn has_conventional_message(&self, commit_types: &[String]) -> bool {
+        !self.content.is_empty()
+            && commit::conventional_problems(&self.content, commit_types).is_empty()
+    }
+
+    /// Adds the existing message to the user's instructions, so the model
+    /// can build on it
+    pub fn context_instructions(&self, instructions: Option<&str>) -> Option<String> {
+        if self.content.is_empty() {
+            return instructions.map(String::from);
+        }
+
+        let mut text = instructions
+            .map(|instructions| format!("{instructions}\n\n"))
+            .unwrap_or_default();
+        text.push_str(&format!(
+            "The commit message file already contains this text; use it as context:\n{}",
+            self.content
+        ));
+        Some(text)
+    }
+
+    /// Replaces the message in the file, keeping git's comment lines below it
+    pub fn write(&self, message: &str) -> Result<()> {
+        let mut text = format!("{}\n", message.trim_end());
+        if !self.comments.is_empty() {
+            text.push('\n');
+            for comment in &self.comments {
+                text.push_str(comment);
+                text.push('\n');
+            }
+        }
+        std::fs::write(&self.path, text)
+            .with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS_COMMENTS: &str = "\
+# Please enter the commit message for your changes. Lines starting
+# with '#' will be ignored, and an empty message aborts the commit.
+#
+# Changes to be committed:
+#\tmodified:   src/main.rs
+";
+
+    #[test]
+    fn test_empty_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("COMMIT_EDITMSG");
+        std::fs::write(&path, "").unwrap();
+
+        let file = CommitMsgFile::read(&path).unwrap();
+        assert_eq!(file.content(), "");
+        assert_eq!(file.context_instructions(None), None);
+        // Nothing written yet, so a message is still generated
+        assert!(!file.has_conventional_message(&[]));
+
+        file.write("feat: add login").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "feat: add login\n");
+
+        // A missing file reads as empty
+        let file = CommitMsgFile::read(&dir.path().join("missing")).unwrap();
+        assert_eq!(file.content(), "");
+    }
+
+    #[test]
+    fn test_template_only_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("COMMIT_EDITMSG");
+        std::fs::write(&path, format!("\n{STATUS_COMMENTS}")).unwrap();
+
+        let file = CommitMsgFile::read(&path).unwrap();
+        assert_eq!(file.content(), "");
+        assert_eq!(
+            file.context_instructions(Some("Mention the ticket"))
+                .as_deref(),
+            Some("Mention the ticket")
+        );
+
+        file.write("fix: close pool\n\nDetails.").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("fix: close pool\n\nDetails.\n\n{STATUS_COMMENTS}")
+        );
+    }
+
+    #[test]
+    fn test_conventional_message_already_written() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("COMMIT_EDITMSG");
+        std::fs::write(&path, format!("feat(auth): add login\n\n{STATUS_COMMENTS}")).unwrap();
+
+        let file = CommitMsgFile::read(&path).unwrap();
+        assert!(file.has_conventional_message(&[]));
+        assert!(!file.has_conventional_message(&["fix".to_string()]));
+
+        std::fs::write(&path, "added login\n").unwrap();
+        let file = CommitMsgFile::read(&path).unwrap();
+        assert!(!file.has_conventional_message(&[]));
+    }
+
+    #[test]
+    fn test_user_authored_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("COMMIT_EDITMSG");
+        std::fs::write(
+            &path,
+            format!("Merge branch 'feature/login'\n\n{STATUS_COMMENTS}"),
+        )
+        .unwrap();
+
+        let file = CommitMsgFile::read(&path).unwrap();
+        assert_eq!(file.content(), "Merge branch 'feature/login'");
+        assert!(file.has_conventional_message(&[]));
+        assert_eq!(
+            file.context_instructions(Some("Keep it short")).as_deref(),
+            Some(
+                "Keep it short\n\nThe commit message file already contains this text; use it as context:\nMerge branch 'feature/login'"
+            )
+        );
+
+        file.write("merge: bring in the login feature").unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("merge: bring in the login feature\n\n{STATUS_COMMENTS}")
+        );
+    }
+}
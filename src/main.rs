@@ -1,12 +1,33 @@
-use anyhow::{Context, Result};
-use clap::{Parser, ValueEnum};
+use anyhow::{Context, Result, anyhow, bail};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use futures::future::join_all;
+use regex::Regex;
+use serde_json::json;
+use std::future::Future;
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
 
 mod ai;
 mod commit;
+mod config;
+mod convention;
+mod editor;
+mod error;
 mod git;
+mod hook;
+mod pull_request;
+mod scopes;
 
-use ai::provider_factory::create_provider;
+use ai::fallback::{FallbackEntry, FallbackProvider};
+use ai::provider_factory::{ProviderOptions, create_provider};
+use ai::{AiProvider, ProviderCapabilities};
+use commit::Candidate;
+use convention::Convention;
+use pull_request::PullRequest;
+use scopes::{ScopeHint, ScopeMap};
 
 #[derive(Copy, Clone, Debug, ValueEnum, PartialEq)]
 enum Provider {
@@ -14,14 +35,77 @@ enum Provider {
     #[value(name = "openai")]
     OpenAI,
     Gemini,
+    #[value(name = "llamacpp", alias = "llama-cpp")]
+    LlamaCpp,
+    /// Any command that reads the prompt on stdin (see --provider-command)
+    Command,
 }
 
+/// Environment variables used when the corresponding flag is not given
+const ENV_PROVIDER: &str = "RS_GIT_MSG_PROVIDER";
+const ENV_MODEL: &str = "RS_GIT_MSG_MODEL";
+const ENV_API_KEY: &str = "RS_GIT_MSG_API_KEY";
+const ENV_API_URL: &str = "RS_GIT_MSG_API_URL";
+const ENV_TYPES: &str = "RS_GIT_MSG_TYPES";
+const ENV_CONFIG: &str = "RS_GIT_MSG_CONFIG";
+
 impl Provider {
+    /// Parses a provider name the same way `--provider` does (case-insensitive)
+    fn parse(value: &str) -> Result<Self> {
+        <Self as ValueEnum>::from_str(value.trim(), true).map_err(|_| {
+            let names: Vec<String> = Self::value_variants()
+                .iter()
+                .filter_map(|provider| provider.to_possible_value())
+                .map(|value| value.get_name().to_string())
+                .collect();
+            anyhow!(
+                "Unknown provider '{value}' in {ENV_PROVIDER} (possible values: auto, {})",
+                names.join(", ")
+            )
+        })
+    }
+
+    /// The name `--provider` takes, e.g. `openai`
+    fn name(&self) -> String {
+        self.to_possible_value()
+            .map(|value| value.get_name().to_string())
+            .unwrap_or_else(|| format!("{self:?}"))
+    }
+
+    /// The provider's own API key variable, used when no key is given
+    fn api_key_var(&self) -> Option<&'static str> {
+        match self {
+            Provider::OpenAI => Some("OPENAI_API_KEY"),
+            Provider::Gemini => Some("GEMINI_API_KEY"),
+            _ => None,
+        }
+    }
+
+    /// Whether the provider sends diffs off this machine by default
+    fn is_cloud(&self) -> bool {
+        matches!(self, Provider::OpenAI | Provider::Gemini)
+    }
+
+    /// Request features rs-git-msg makes use of with the provider
+    fn capabilities(&self) -> ProviderCapabilities {
+        match self {
+            Provider::Ollama => ai::ollama::OllamaProvider::CAPABILITIES,
+            Provider::OpenAI => ai::openai::OpenAIProvider::CAPABILITIES,
+            Provider::Gemini => ai::gemini::GeminiProvider::CAPABILITIES,
+            Provider::LlamaCpp => ai::llamacpp::LlamaCppProvider::CAPABILITIES,
+            Provider::Command => ai::command::CommandProvider::CAPABILITIES,
+        }
+    }
+
     fn default_model(&self) -> &'static str {
         match self {
             Provider::Ollama => "qwen2.5-coder",
             Provider::OpenAI => "gpt-4o-mini",
             Provider::Gemini => "gemini-2.0-flash-lite",
+            // llama-server answers with whatever model it was started with
+            Provider::LlamaCpp => "default",
+            // The command picks its own model
+            Provider::Command => "default",
         }
     }
 }
@@ -31,27 +115,112 @@ enum DiffAlgArg {
     Default,
     Patience,
     Minimal,
+    Histogram,
+    Word,
     Difftastic,
 }
 
+/// How messages and errors are printed
+#[derive(Copy, Clone, Debug, ValueEnum, PartialEq)]
+enum OutputFormat {
+    /// One message per line, errors as plain text
+    Text,
+    /// `{"provider", "model", "messages": [...]}` on stdout, errors as
+    /// `{"error", "kind"}` on stderr
+    Json,
+}
+
+/// What to print when stdout is not a terminal and several messages were generated
+#[derive(Copy, Clone, Debug, ValueEnum, PartialEq)]
+enum PipeBehavior {
+    /// Print only the first message, so it can be used as the commit message
+    First,
+    /// Refuse, since several lines would end up in one commit message
+    Error,
+    /// Print every message, one per line (e.g. for a picker menu)
+    All,
+}
+
 impl From<DiffAlgArg> for git::DiffAlg {
     fn from(arg: DiffAlgArg) -> Self {
         match arg {
             DiffAlgArg::Default => git::DiffAlg::Default,
             DiffAlgArg::Patience => git::DiffAlg::Patience,
             DiffAlgArg::Minimal => git::DiffAlg::Minimal,
+            DiffAlgArg::Histogram => git::DiffAlg::Histogram,
+            DiffAlgArg::Word => git::DiffAlg::Word,
             DiffAlgArg::Difftastic => git::DiffAlg::Difftastic,
         }
     }
 }
 
+#[derive(Copy, Clone, Debug, ValueEnum, PartialEq)]
+enum SubjectCaseArg {
+    Lower,
+    Sentence,
+    Asis,
+}
+
+impl From<SubjectCaseArg> for commit::SubjectCase {
+    fn from(arg: SubjectCaseArg) -> Self {
+        match arg {
+            SubjectCaseArg::Lower => commit::SubjectCase::Lower,
+            SubjectCaseArg::Sentence => commit::SubjectCase::Sentence,
+            SubjectCaseArg::Asis => commit::SubjectCase::AsIs,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum, PartialEq)]
+enum DiffWrapperArg {
+    Markdown,
+    Xml,
+}
+
+impl From<DiffWrapperArg> for commit::DiffWrapper {
+    fn from(arg: DiffWrapperArg) -> Self {
+        match arg {
+            DiffWrapperArg::Markdown => commit::DiffWrapper::Markdown,
+            DiffWrapperArg::Xml => commit::DiffWrapper::Xml,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, ValueEnum, PartialEq)]
+enum ScopeCaseArg {
+    Kebab,
+    Snake,
+    Asis,
+}
+
+impl From<ScopeCaseArg> for commit::ScopeCase {
+    fn from(arg: ScopeCaseArg) -> Self {
+        match arg {
+            ScopeCaseArg::Kebab => commit::ScopeCase::Kebab,
+            ScopeCaseArg::Snake => commit::ScopeCase::Snake,
+            ScopeCaseArg::Asis => commit::ScopeCase::AsIs,
+        }
+    }
+}
+
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// Number of commit messages to generate (1-5)
+    /// Number of commit messages to generate (1-5, or 0 to pick by the size
+    /// of the change)
     #[arg(short = 'n', long = "number", default_value_t = 1)]
     numbers: u8,
 
+    /// Change sizes at or below which -n 0 generates one message, and at or
+    /// above which it generates three. Size is changed lines plus 25 per file.
+    #[arg(long, value_name = "SMALL,LARGE", value_parser = parse_auto_thresholds, default_value = "60,500")]
+    auto_thresholds: (usize, usize),
+
+    /// Show at most N of the generated messages, after invalid and duplicate
+    /// ones are dropped [default: all of them]
+    #[arg(long, value_name = "N", alias = "max-candidates-shown", value_parser = clap::value_parser!(u8).range(1..))]
+    show: Option<u8>,
+
     /// Additional context or instructions for the AI
     #[arg(short = 'i', long)]
     instructions: Option<String>,
@@ -60,11 +229,12 @@ struct Args {
     #[arg(short, long)]
     verbose: bool,
 
-    /// AI provider to use
-    #[arg(short = 'p', long, value_enum, default_value_t = Provider::Ollama)]
-    provider: Provider,
+    /// AI provider to use [default: a running Ollama, else OpenAI or Gemini,
+    /// whichever has OPENAI_API_KEY or GEMINI_API_KEY set]
+    #[arg(short = 'p', long, value_enum)]
+    provider: Option<Provider>,
 
-    /// Model name to use
+    /// Model name to use, or an alias such as `fast` or `smart`
     #[arg(short = 'm', long)]
     model: Option<String>,
 
@@ -72,178 +242,4218 @@ struct Args {
     #[arg(short = 'k', long)]
     api_key: Option<String>,
 
+    /// Read the API key from a file (keeps it out of shell history and `ps`)
+    #[arg(long, value_name = "PATH")]
+    api_key_file: Option<PathBuf>,
+
     /// API base URL (defaults to provider's standard URL)
     #[arg(short = 'u', long)]
     api_url: Option<String>,
 
-    /// Diff algorithm to use (default, patience, minimal, difftastic)
-    #[arg(long, value_enum, default_value_t = DiffAlgArg::Default)]
+    /// Run against the git repository at this path instead of the current directory
+    #[arg(long, value_name = "PATH")]
+    repo_path: Option<PathBuf>,
+
+    /// Stage all changes to tracked files first, like `git add -u`
+    #[arg(short = 'a', long, conflicts_with = "from_commit_msg_file")]
+    add_all: bool,
+
+    /// Also stage untracked (but not ignored) files first, like `git add -A`
+    #[arg(long, conflicts_with = "from_commit_msg_file")]
+    add_all_untracked: bool,
+
+    /// Shell command for the command provider; it gets the prompt on stdin
+    #[arg(long, value_name = "CMD")]
+    provider_command: Option<String>,
+
+    /// Diff algorithm to use (default, patience, minimal, histogram, word, difftastic)
+    #[arg(long = "diff-algorithm", alias = "diff-alg", value_enum, default_value_t = DiffAlgArg::Default)]
     diff_alg: DiffAlgArg,
+
+    /// Seconds difftastic may spend on one file before the built-in diff is
+    /// used for it instead
+    #[arg(long, value_name = "SECONDS", default_value_t = git::DEFAULT_DIFFT_TIMEOUT.as_secs())]
+    difft_timeout: u64,
+
+    /// Only include these kinds of change, as letters from ACDMRT (added,
+    /// copied, deleted, modified, renamed, type changed); lower case letters
+    /// exclude instead, like `git diff --diff-filter`
+    #[arg(long, value_name = "ACDMRT", value_parser = git::DiffFilter::parse)]
+    diff_filter: Option<git::DiffFilter>,
+
+    /// Include the full messages of the last N commits as style examples
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    style_examples: usize,
+
+    /// Leave the titles of the last few commits out of the prompt, for when
+    /// they shouldn't be sent to the provider
+    #[arg(long)]
+    no_recent_commits: bool,
+
+    /// Tell the model how often each type appears in the last N commit
+    /// titles (0 to leave it out)
+    #[arg(long, value_name = "N", default_value_t = 50)]
+    type_history: usize,
+
+    /// Retry once at a higher temperature if the candidates are too similar
+    #[arg(long)]
+    diversify: bool,
+
+    /// Don't ask again (up to twice, by default) for the messages missing
+    /// when the model gives fewer than -n asked for
+    #[arg(long)]
+    no_top_up: bool,
+
+    /// Maximum number of AI requests in flight at once
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = commit::DEFAULT_CONCURRENCY as u16,
+        value_parser = clap::value_parser!(u16).range(1..)
+    )]
+    concurrency: u16,
+
+    /// Send at most N AI requests a minute within this run, across every
+    /// provider in use; separate runs (e.g. parallel hooks) each get N
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u32).range(1..))]
+    rpm: Option<u32>,
+
+    /// Total number of retries allowed across all AI requests
+    #[arg(long, value_name = "N", default_value_t = commit::DEFAULT_RETRY_BUDGET)]
+    retry_budget: usize,
+
+    /// Truncate the diff so the whole prompt fits in N tokens; the AI is
+    /// told when the diff was cut short
+    #[arg(long, value_name = "N")]
+    max_prompt_tokens: Option<usize>,
+
+    /// Comma-separated commit types to allow (defaults to the config file's
+    /// `types`, or else all Conventional Commits types)
+    #[arg(long, value_name = "TYPES", value_delimiter = ',')]
+    types: Vec<String>,
+
+    /// Longest subject line to ask for and accept, counting each displayed
+    /// character (emoji, accented letters) once; also checked by --check-only
+    #[arg(long, value_name = "N")]
+    max_subject_len: Option<usize>,
+
+    /// commitlint JSON config whose allowed types and header length the
+    /// prompt should follow [default: .commitlintrc or .commitlintrc.json at
+    /// the repository root]
+    #[arg(long, value_name = "PATH")]
+    convention_file: Option<PathBuf>,
+
+    /// Regex for an extra list marker to strip from the start of each
+    /// response line, for models that number their answers unusually
+    /// (e.g. '\[\d+\]'); repeat for several
+    #[arg(long, value_name = "REGEX", value_parser = commit::list_marker)]
+    list_marker: Vec<Regex>,
+
+    /// Scope to suggest to the AI (defaults to the scope from .rsgitmsg-scopes
+    /// or the monorepo package, if any)
+    #[arg(long, value_name = "SCOPE")]
+    template_scope: Option<String>,
+
+    /// Directory whose subdirectories are monorepo packages
+    #[arg(long, value_name = "DIR", default_value = commit::DEFAULT_PACKAGE_ROOT)]
+    package_root: String,
+
+    /// Number of directory levels under --package-root that name a package
+    #[arg(long, value_name = "N", default_value_t = commit::DEFAULT_PACKAGE_DEPTH)]
+    package_depth: usize,
+
+    /// Ask OpenAI for structured JSON output (falls back to text parsing)
+    #[arg(long)]
+    json_mode: bool,
+
+    /// Ask OpenAI for the whole response at once instead of streaming it,
+    /// for compatible servers that reject streaming
+    #[arg(long)]
+    no_stream: bool,
+
+    /// Seed for reproducible output, for OpenAI and Ollama (other providers ignore it)
+    #[arg(long, value_name = "SEED")]
+    seed: Option<u64>,
+
+    /// When no message can be parsed from the AI's response, print the
+    /// prompt and the raw response to stderr (always on with --verbose)
+    #[arg(long, alias = "print-prompt-only-on-error")]
+    debug_on_error: bool,
+
+    /// Print token usage and estimated cost to stderr (OpenAI only)
+    #[arg(long)]
+    print_usage: bool,
+
+    /// Include the branch's description (git branch --edit-description) as context
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_value_t = true, default_missing_value = "true", action = clap::ArgAction::Set)]
+    use_branch_description: bool,
+
+    /// Include the title and description of the pull request a CI job is
+    /// building (GitHub Actions, GitLab CI, or PR_TITLE and PR_BODY) as context
+    #[arg(long)]
+    context_commits_from_pr: bool,
+
+    /// Redact likely secrets from the diff [default: true for OpenAI and Gemini]
+    #[arg(long, value_name = "BOOL", num_args = 0..=1, default_missing_value = "true")]
+    redact_secrets: Option<bool>,
+
+    /// Collapse diff metadata (`diff --git`, `index`, `---`/`+++` and hunk
+    /// header lines) to save tokens
+    #[arg(long)]
+    trim_diff_headers: bool,
+
+    /// Replace the diff of any file longer than N lines with a one-line
+    /// summary ("large change: +X/-Y lines in <path>")
+    #[arg(long, value_name = "N")]
+    max_file_lines: Option<usize>,
+
+    /// How to set the diff off in the prompt: a Markdown code fence or
+    /// `<diff>...</diff>` tags, which some models follow better
+    #[arg(long, value_enum, default_value_t = DiffWrapperArg::Markdown)]
+    diff_wrapper: DiffWrapperArg,
+
+    /// Generate a message even when the staged changes only touch whitespace
+    #[arg(long)]
+    allow_whitespace: bool,
+
+    /// Describe files added with `git add -N` by their contents in the
+    /// working tree; otherwise they're listed as new files whose contents
+    /// aren't staged
+    #[arg(long)]
+    include_intent_to_add: bool,
+
+    /// File names that count as lockfiles; when only these are staged, the
+    /// message is "chore(deps): update lockfile" and the AI isn't asked
+    /// [default: Cargo.lock, package-lock.json, yarn.lock, pnpm-lock.yaml,
+    /// ...]
+    #[arg(long, value_delimiter = ',', value_name = "NAMES")]
+    lockfiles: Vec<String>,
+
+    /// Ask the AI even when only lockfiles are staged
+    #[arg(long)]
+    force_ai: bool,
+
+    /// Summarize each file separately, then combine the summaries into one message
+    #[arg(long)]
+    chunked: bool,
+
+    /// Suggest a separate message for each changed file, printed as
+    /// `path: message`, to split a large change into several commits
+    #[arg(long, alias = "split", conflicts_with_all = ["chunked", "compare", "edit", "from_commit_msg_file", "no_diff"])]
+    per_file: bool,
+
+    /// How long Ollama keeps the model loaded after the request (e.g. "10m")
+    #[arg(long, value_name = "DURATION")]
+    ollama_keep_alive: Option<String>,
+
+    /// Load the Ollama model before sending the prompt
+    #[arg(long)]
+    ollama_preload: bool,
+
+    /// Ask before generating when many files or risky paths (migrations,
+    /// .env files, CI workflows) are staged
+    #[arg(long)]
+    confirm_large: bool,
+
+    /// Number of staged files above which --confirm-large asks
+    #[arg(long, value_name = "N", default_value_t = 20)]
+    large_threshold: usize,
+
+    /// With --confirm-large, fail instead of continuing when there is no
+    /// terminal to ask on
+    #[arg(long, requires = "confirm_large")]
+    strict: bool,
+
+    /// Pipe each message to this command (e.g. commitlint); messages it
+    /// exits non-zero for are reported and dropped
+    #[arg(long, value_name = "CMD")]
+    post_command: Option<String>,
+
+    /// Replace each message with the --post-command output
+    #[arg(long, requires = "post_command")]
+    post_rewrites: bool,
+
+    /// Regenerate up to N times when --post-command rejects every message
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "post_command")]
+    post_retries: u8,
+
+    /// Providers to try in order, with their default models, when the
+    /// provider can't be reached or rejects its API key. Their keys come from
+    /// OPENAI_API_KEY and GEMINI_API_KEY.
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        value_name = "PROVIDERS",
+        conflicts_with = "compare"
+    )]
+    fallback: Vec<Provider>,
+
+    /// Generate with each of these providers (using their default models) at
+    /// once and print the results side by side with timings
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "PROVIDERS")]
+    compare: Vec<Provider>,
+
+    /// Retry once with this model (or alias) when the first response has no
+    /// valid commit message
+    #[arg(long, value_name = "NAME")]
+    escalate_model: Option<String>,
+
+    /// Use this message (e.g. "chore: update") instead of failing when no
+    /// message can be generated, after every retry and fallback provider.
+    /// Not allowed with --commit or `regen --apply`, which shouldn't record it.
+    #[arg(long, value_name = "TEXT", conflicts_with = "commit")]
+    fallback_message: Option<String>,
+
+    /// Follow each message with the model's one-line rationale
+    #[arg(long)]
+    explain: bool,
+
+    /// Write the final prompt to this file before sending it
+    #[arg(long, value_name = "PATH")]
+    dump_prompt: Option<PathBuf>,
+
+    /// Output format for messages and errors
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    format: OutputFormat,
+
+    /// Indent --format json output for reading instead of printing it on
+    /// one line
+    #[arg(long)]
+    json_pretty: bool,
+
+    /// Send only the names of the changed files, never their contents
+    #[arg(long, conflicts_with = "chunked")]
+    no_diff: bool,
+
+    /// Print the diff sent to the AI to stderr before the messages, as the
+    /// prompt carries it: cut to --max-prompt-tokens, or as the per-file
+    /// summaries with --chunked
+    #[arg(long, alias = "include-diff-in-output")]
+    show_diff: bool,
+
+    /// Rewrite scopes in this case, e.g. feat(UserAuth) to feat(user-auth)
+    #[arg(long, value_enum, default_value_t = ScopeCaseArg::Asis)]
+    scope_case: ScopeCaseArg,
+
+    /// Recase the subject after the type(scope) prefix
+    #[arg(long, value_enum, default_value_t = SubjectCaseArg::Asis)]
+    subject_case: SubjectCaseArg,
+
+    /// Keep a period at the end of the subject instead of dropping it, and
+    /// don't ask the model to leave it out
+    #[arg(long)]
+    allow_trailing_period: bool,
+
+    /// Remove a leading emoji (and the space after it) from each message,
+    /// e.g. when moving a gitmoji repository to Conventional Commits
+    #[arg(long)]
+    strip_emoji: bool,
+
+    /// Rewrap message bodies to this many columns, e.g. git's customary 72
+    /// (0 to leave them as they are)
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    wrap: usize,
+
+    /// Move ticket keys in the subject (e.g. "feat: JIRA-123 add login") to a
+    /// `Refs:` footer
+    #[arg(long, alias = "strip-ticket-from-subject")]
+    ticket_to_footer: bool,
+
+    /// Add a `Refs:` footer for the ticket the last commit's footer
+    /// references, if that commit is on this branch and not the base branch,
+    /// or else the one in the branch name (e.g. feature/JIRA-123-add-login)
+    #[arg(long, conflicts_with = "only_subject")]
+    add_ticket: bool,
+
+    /// Print only the subject, without the type(scope) prefix or leading gitmoji
+    #[arg(long)]
+    only_subject: bool,
+
+    /// Text to add before each generated message (e.g. "[WIP]")
+    #[arg(long)]
+    prepend: Option<String>,
+
+    /// Text to add after each generated message (e.g. a ticket reference)
+    #[arg(long)]
+    append: Option<String>,
+
+    /// Credit a pairing partner with a `Co-authored-by` trailer, given as
+    /// "Name <email>"; repeat for several
+    #[arg(long, value_name = "NAME <EMAIL>", value_parser = parse_co_author, conflicts_with = "only_subject")]
+    co_author: Vec<String>,
+
+    /// Open the first message in $EDITOR and use the edited text instead
+    #[arg(long, conflicts_with = "compare")]
+    edit: bool,
+
+    /// Choose a message interactively: a number picks it, `e N` edits it,
+    /// `r` generates a fresh batch and `q` quits
+    #[arg(long, conflicts_with_all = ["compare", "edit", "from_commit_msg_file", "per_file"])]
+    pick: bool,
+
+    /// What to print when stdout is piped and more than one message was generated
+    #[arg(long, value_enum, default_value_t = PipeBehavior::First)]
+    pipe_behavior: PipeBehavior,
+
+    /// How to print each message in text output, with the placeholders
+    /// {n}, {message}, {type}, {scope} and {subject} (e.g. "{n}. {message}")
+    #[arg(long, value_name = "TEMPLATE")]
+    output_template: Option<String>,
+
+    /// Run as a prepare-commit-msg hook: use the message already in this file
+    /// as context and write the generated message back to it
+    #[arg(long, value_name = "PATH", conflicts_with = "compare")]
+    from_commit_msg_file: Option<PathBuf>,
+
+    /// Replace a message already written in --from-commit-msg-file instead of
+    /// leaving it alone
+    #[arg(long, requires = "from_commit_msg_file")]
+    overwrite: bool,
+
+    /// Leave a --from-commit-msg-file that already holds a valid
+    /// Conventional Commit alone, even with --overwrite
+    #[arg(long, requires = "from_commit_msg_file")]
+    skip_if_valid: bool,
+
+    /// Validate the message in --from-commit-msg-file (or on stdin) as a
+    /// Conventional Commit instead of generating one; exits non-zero if invalid
+    #[arg(long, conflicts_with_all = ["compare", "overwrite", "edit"])]
+    check_only: bool,
+
+    /// Print which request features (streaming, n, seed, system prompt,
+    /// temperature, usage) each provider supports, then exit
+    #[arg(long)]
+    capabilities: bool,
+
+    /// Commit the staged changes with the first (or edited) message through
+    /// `git commit`, so hooks run, a merge in progress is concluded and
+    /// `commit.gpgsign` is honored
+    #[arg(long, conflicts_with_all = ["check_only", "compare", "from_commit_msg_file", "per_file"])]
+    commit: bool,
+
+    /// Also write the generated messages to this file, one per line
+    #[arg(long, value_name = "PATH")]
+    output_file: Option<PathBuf>,
+
+    /// Append to --output-file instead of overwriting it
+    #[arg(long, requires = "output_file")]
+    append_to_file: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let args = Args::parse();
+#[derive(Subcommand, Debug, Clone, Copy, PartialEq)]
+enum Command {
+    /// Regenerate the message of the last commit from the changes it made,
+    /// instead of describing the staged changes
+    Regen {
+        /// Rewrite the last commit with the first (or edited) message, like
+        /// `git commit --amend`
+        #[arg(long)]
+        apply: bool,
 
-    let api_key = args
-        .api_key
-        .or_else(|| std::env::var("RS_GIT_MSG_API_KEY").ok());
+        /// Rewrite the last commit without asking even when it is already on
+        /// a remote branch
+        #[arg(long, requires = "apply")]
+        force: bool,
+    },
+}
 
-    if args.numbers < 1 || args.numbers > 5 {
-        eprintln!("Error: Number of messages must be between 1 and 5");
-        process::exit(1);
+/// Checks the flags that can't be combined with `regen`, which clap doesn't
+/// check across a subcommand
+fn check_regen_conflicts(args: &Args) -> Result<(), clap::Error> {
+    let Some(Command::Regen { apply, .. }) = args.command else {
+        return Ok(());
+    };
+    let conflicts = [
+        (args.add_all, "--add-all"),
+        (args.add_all_untracked, "--add-all-untracked"),
+        (args.check_only, "--check-only"),
+        (args.commit, "--commit"),
+        (
+            args.from_commit_msg_file.is_some(),
+            "--from-commit-msg-file",
+        ),
+        (args.per_file, "--per-file"),
+        (
+            apply && args.fallback_message.is_some(),
+            "--fallback-message",
+        ),
+    ];
+    match conflicts.iter().find(|(given, _)| *given) {
+        Some((_, flag)) => Err(Args::command().error(
+            clap::error::ErrorKind::ArgumentConflict,
+            format!("{flag} cannot be used with `regen`"),
+        )),
+        None => Ok(()),
     }
+}
 
-    // Use the model provided by the user or fall back to the provider's default
-    let model = args
-        .model
-        .unwrap_or_else(|| args.provider.default_model().to_string());
+fn env_var(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
 
-    if args.verbose {
-        println!("Opening git repository...");
-    }
+/// Returns the flag's value, or the environment value when the flag is absent
+fn cli_or_env(cli: Option<String>, env_value: Option<String>) -> Option<String> {
+    cli.or_else(|| env_value.filter(|value| !value.trim().is_empty()))
+}
 
-    let repo =
-        git::Repository::open_current_dir(args.verbose).context("Failed to open git repository")?;
+/// Titles of the last few commits, shown to the model as examples, unless
+/// `--no-recent-commits` keeps them private
+fn recent_commit_titles(repo: &git::Repository, args: &Args) -> Vec<String> {
+    if args.no_recent_commits {
+        return Vec::new();
+    }
+    repo.get_last_commit_titles(3).unwrap_or_default()
+}
 
-    let branch_name = repo
-        .get_branch_name()
-        .context("Failed to get branch name")?;
+/// What `--no-diff` sends instead of the diff: one changed file per line,
+/// renames as `old -> new`
+fn changed_file_list(paths: &[String], renames: &[(String, String)]) -> String {
+    paths
+        .iter()
+        .map(|path| match renames.iter().find(|(_, new)| new == path) {
+            Some((old, new)) => format!("{old} -> {new}"),
+            None => path.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
 
-    if args.verbose {
-        println!("Current branch: {branch_name}");
-        println!("Reading staged changes...");
+/// Opens the repository at `--repo-path`, or in the current directory
+fn open_repository(path: Option<&Path>, verbose: bool) -> Result<git::Repository> {
+    let Some(path) = path else {
+        return git::Repository::open_current_dir(verbose).context("Failed to open git repository");
+    };
+    if !path.is_dir() {
+        bail!("--repo-path {} is not a directory", path.display());
     }
+    git::Repository::open(path, verbose)
+        .with_context(|| format!("--repo-path {} is not a git repository", path.display()))
+}
 
-    let diff_alg = args.diff_alg.into();
-    let diff = repo
-        .get_staged_diff(diff_alg)
-        .context("Failed to get staged diff")?;
+/// A git identity: a name, then an email address in angle brackets
+static CO_AUTHOR: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"^([^<>]*[^<>\s])\s*<([^<>@\s]+@[^<>@\s]+)>$").unwrap());
 
-    if diff.is_empty() {
-        println!("No staged changes found. Stage some changes first with 'git add'");
-        println!(
-            "Make sure you have staged changes using 'git add <file>' before running this command"
-        );
-        process::exit(1);
+/// Checks a --co-author value is "Name <email>", normalizing the spacing
+fn parse_co_author(value: &str) -> Result<String, String> {
+    let caps = CO_AUTHOR
+        .captures(value.trim())
+        .ok_or_else(|| format!("expected \"Name <email>\", got \"{value}\""))?;
+    Ok(format!("{} <{}>", caps[1].trim(), &caps[2]))
+}
+
+/// Reads an API key from a file, ignoring surrounding whitespace
+fn read_api_key_file(path: &Path) -> Result<String> {
+    let contents = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read API key file {}", path.display()))?;
+    let api_key = contents.trim();
+    if api_key.is_empty() {
+        return Err(anyhow!("API key file {} is empty", path.display()));
     }
+    Ok(api_key.to_string())
+}
 
-    if args.verbose {
-        println!("Found staged changes, generating commit message...");
-        println!("Using provider: {:?} with model: {}", args.provider, model);
+/// `--format json` output as text, indented with --json-pretty
+fn render_json(value: &serde_json::Value, pretty: bool) -> String {
+    if pretty {
+        serde_json::to_string_pretty(value).expect("a JSON value always serializes")
+    } else {
+        value.to_string()
     }
+}
 
-    let ai_provider = create_provider(
-        args.provider,
-        &model,
-        api_key.as_deref(),
-        args.api_url.as_deref(),
-        args.verbose,
-    )?;
+/// The `--format json` output: the messages, and which provider and model
+/// wrote them
+fn messages_json(provider: &str, model: &str, candidates: &[Candidate]) -> serde_json::Value {
+    json!({
+        "provider": provider,
+        "model": model,
+        "messages": candidates,
+    })
+}
 
-    let last_commit_titles = repo.get_last_commit_titles(3).unwrap_or_default();
+/// Applies `--show` to the messages that survived parsing and validation
+fn limit_shown<T>(mut messages: Vec<T>, show: Option<u8>) -> Vec<T> {
+    if let Some(show) = show {
+        messages.truncate(usize::from(show));
+    }
+    messages
+}
 
-    let generator = commit::CommitMessageGenerator::new(ai_provider);
+/// Applies `--pipe-behavior` to the messages printed on a non-terminal stdout
+fn select_piped_messages<T>(
+    mut messages: Vec<T>,
+    stdout_is_terminal: bool,
+    behavior: PipeBehavior,
+) -> Result<Vec<T>> {
+    if stdout_is_terminal || messages.len() < 2 {
+        return Ok(messages);
+    }
 
-    if args.verbose {
-        println!("Generating commit message(s)...");
+    match behavior {
+        PipeBehavior::First => {
+            messages.truncate(1);
+            Ok(messages)
+        }
+        PipeBehavior::Error => Err(anyhow!(
+            "{} messages were generated but stdout is not a terminal, so they would end up in one commit message. Use -n 1, or --pipe-behavior first|all",
+            messages.len()
+        )),
+        PipeBehavior::All => Ok(messages),
     }
+}
 
-    let messages = generator
-        .generate(
-            &diff,
-            &branch_name,
-            args.numbers,
-            args.instructions.as_deref(),
-            &last_commit_titles,
-            args.verbose,
-        )
-        .await
-        .context("Failed to generate commit message")?;
+/// Pipes each message to `--post-command`. Returns the messages it accepted
+/// (replaced by its output when `rewrite` is set) and, for the ones it
+/// rejected, the message and what the command printed.
+async fn apply_post_command(
+    command: &str,
+    candidates: Vec<Candidate>,
+    rewrite: bool,
+) -> Result<(Vec<Candidate>, Vec<(String, String)>)> {
+    let mut passed = Vec::new();
+    let mut rejections = Vec::new();
+    for candidate in candidates {
+        let output = ai::command::pipe_to_command(command, &candidate.message)
+            .await
+            .with_context(|| format!("Failed to run --post-command `{command}`"))?;
+        let stdout = String::from_utf8_lossy(&output.stdout)
+            .trim_end()
+            .to_string();
 
-    for message in &messages {
-        println!("{message}");
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let report: Vec<&str> = [stdout.trim(), stderr.trim()]
+                .into_iter()
+                .filter(|text| !text.is_empty())
+                .collect();
+            rejections.push((candidate.message, report.join("\n")));
+        } else if rewrite && !stdout.trim().is_empty() {
+            passed.push(Candidate {
+                message: stdout,
+                ..candidate
+            });
+        } else {
+            passed.push(candidate);
+        }
     }
+    Ok((passed, rejections))
+}
 
-    Ok(())
+/// The generated messages, or `fallback` in their place with a warning when
+/// generation failed and a fallback message was given
+fn or_fallback_message(
+    generated: Result<Vec<Candidate>>,
+    fallback: Option<&str>,
+) -> Result<Vec<Candidate>> {
+    match (generated, fallback) {
+        (Err(e), Some(fallback)) => {
+            eprintln!(
+                "Warning: failed to generate a commit message ({e:#}); using --fallback-message"
+            );
+            Ok(vec![Candidate {
+                message: fallback.to_string(),
+                rationale: None,
+            }])
+        }
+        (generated, _) => generated,
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use clap::CommandFactory;
+/// Extra instructions for regenerating after `--post-command` rejected the
+/// previous messages
+fn rejection_instructions(instructions: Option<&str>, rejections: &[(String, String)]) -> String {
+    let mut text = instructions
+        .map(|instructions| format!("{instructions}\n\n"))
+        .unwrap_or_default();
+    text.push_str(
+        "These messages were rejected by the commit message linter; avoid the same problems:\n",
+    );
+    for (message, output) in rejections {
+        text.push_str(&format!("- \"{message}\": {output}\n"));
+    }
+    text
+}
 
-    #[test]
-    fn test_provider_default_model() {
-        assert_eq!(Provider::Ollama.default_model(), "qwen2.5-coder");
-        assert_eq!(Provider::OpenAI.default_model(), "gpt-4o-mini");
-        assert_eq!(Provider::Gemini.default_model(), "gemini-2.0-flash-lite");
+/// The API key and URL a provider in `--compare` is created with: those given
+/// for the main provider when it is the same one, otherwise only the key in
+/// its own environment variable, so one provider's key never goes to another
+fn compared_credentials(
+    compared: Provider,
+    provider: Provider,
+    api_key: Option<&str>,
+    api_url: Option<&str>,
+    var: impl Fn(&str) -> Option<String>,
+) -> (Option<String>, Option<String>) {
+    if compared == provider {
+        return (api_key.map(str::to_string), api_url.map(str::to_string));
     }
+    (compared.api_key_var().and_then(var), None)
+}
 
-    #[test]
-    fn test_provider_parsing() {
-        let args = Args::parse_from(["program", "--provider", "ollama"]);
-        assert_eq!(args.provider, Provider::Ollama);
+/// One provider's result in `--compare` mode
+struct Comparison {
+    provider: Provider,
+    model: String,
+    elapsed: Duration,
+    result: Result<Vec<Candidate>>,
+}
 
-        let args = Args::parse_from(["program", "--provider", "openai"]);
-        assert_eq!(args.provider, Provider::OpenAI);
+/// Runs every generator on the same diff at once, timing each
+async fn compare_generators<T: AiProvider>(
+    generators: &[(Provider, String, commit::CommitMessageGenerator<T>)],
+    diff: &str,
+    branch_name: &str,
+    count: u8,
+    additional_instructions: Option<&str>,
+    last_commit_titles: &[String],
+) -> Vec<Comparison> {
+    join_all(
+        generators
+            .iter()
+            .map(|(provider, model, generator)| async move {
+                let start = Instant::now();
+                let result = generator
+                    .generate(
+                        diff,
+                        branch_name,
+                        count,
+                        additional_instructions,
+                        last_commit_titles,
+                        false,
+                    )
+                    .await;
+                Comparison {
+                    provider: *provider,
+                    model: model.clone(),
+                    elapsed: start.elapsed(),
+                    result,
+                }
+            }),
+    )
+    .await
+}
 
-        let args = Args::parse_from(["program", "--provider", "gemini"]);
-        assert_eq!(args.provider, Provider::Gemini);
+/// Prints a labeled section per provider, in the order they were listed
+fn format_comparison(results: &[Comparison]) -> String {
+    let mut output = String::new();
+    for comparison in results {
+        let seconds = comparison.elapsed.as_secs_f64();
+        output.push_str(&format!(
+            "=== {:?} ({}) [{seconds:.2}s] ===\n",
+            comparison.provider, comparison.model
+        ));
+        match &comparison.result {
+            Ok(candidates) => {
+                for candidate in candidates {
+                    output.push_str(&format!("{}\n", candidate.message));
+                }
+            }
+            Err(e) => output.push_str(&format!("Error: {e}\n")),
+        }
+        output.push('\n');
     }
+    output
+}
 
-    #[test]
-    fn test_numbers_validation() {
-        // Valid number
-        let args = Args::parse_from(["program", "--number", "3"]);
-        assert_eq!(args.numbers, 3);
+/// The `--format json` output of --compare: each provider's messages, or its
+/// error as `error::to_json` describes it
+fn comparison_json(results: &[Comparison]) -> serde_json::Value {
+    let results: Vec<serde_json::Value> = results
+        .iter()
+        .map(|comparison| {
+            let mut result = match &comparison.result {
+                Ok(candidates) => json!({ "messages": candidates }),
+                Err(e) => error::to_json(e),
+            };
+            result["provider"] = json!(comparison.provider.name());
+            result["model"] = json!(comparison.model);
+            result["elapsed_ms"] = json!(comparison.elapsed.as_millis());
+            result
+        })
+        .collect();
+    json!({ "results": results })
+}
 
-        // Default value
-        let args = Args::parse_from(["program"]);
-        assert_eq!(args.numbers, 1);
+/// Formats a message for stdout, with its rationale on the next line as a
+/// `# ` comment (dimmed on a terminal, and dropped by `git commit -F -`)
+fn format_candidate(candidate: &Candidate, dim: bool) -> String {
+    match &candidate.rationale {
+        Some(rationale) if dim => format!("{}\n\x1b[2m# {rationale}\x1b[0m", candidate.message),
+        Some(rationale) => format!("{}\n# {rationale}", candidate.message),
+        None => candidate.message.clone(),
     }
+}
 
-    #[test]
-    fn test_model_selection() {
-        // Default model
-        let args = Args::parse_from(["program", "--provider", "ollama"]);
-        let model = args
-            .model
-            .unwrap_or_else(|| args.provider.default_model().to_string());
-        assert_eq!(model, "qwen2.5-coder");
+/// A placeholder in --output-template
+static OUTPUT_PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{(n|message|type|scope|subject)\}").unwrap());
 
-        // Custom model
-        let args = Args::parse_from(["program", "--provider", "ollama", "--model", "llama3"]);
-        let model = args
-            .model
-            .unwrap_or_else(|| args.provider.default_model().to_string());
-        assert_eq!(model, "llama3");
-    }
+/// Fills in --output-template for the `n`th message. `{type}` and `{scope}`
+/// are empty for messages without a Conventional Commits header, whose
+/// `{subject}` is then their first line.
+fn render_output_template(template: &str, n: usize, message: &str) -> String {
+    let header = commit::parse_conventional_header(message);
+    OUTPUT_PLACEHOLDER
+        .replace_all(template, |caps: &regex::Captures| match &caps[1] {
+            "n" => n.to_string(),
+            "message" => message.to_string(),
+            "type" => header
+                .as_ref()
+                .map_or("", |header| header.commit_type)
+                .to_string(),
+            "scope" => header
+                .as_ref()
+                .and_then(|header| header.scope)
+                .unwrap_or_default()
+                .to_string(),
+            _ => match &header {
+                Some(header) => header.subject.to_string(),
+                None => message.lines().next().unwrap_or_default().to_string(),
+            },
+        })
+        .into_owned()
+}
 
-    #[test]
-    fn test_api_key_handling() {
-        // No API key
-        let args = Args::parse_from(["program"]);
-        assert_eq!(args.api_key, None);
+/// The options that shape the prompt, for the `--dump-prompt` header
+fn prompt_options(provider: Provider, args: &Args) -> String {
+    let options = [
+        match args.numbers {
+            0 => "number=auto".to_string(),
+            numbers => format!("number={numbers}"),
+        },
+        format!("diff-algorithm={:?}", args.diff_alg),
+        format!(
+            "redact-secrets={}",
+            args.redact_secrets
+                .unwrap_or(redacts_by_default(provider, &args.fallback))
+        ),
+        format!("chunked={}", args.chunked),
+        format!("explain={}", args.explain),
+        format!(
+            "max-prompt-tokens={}",
+            args.max_prompt_tokens
+                .map_or("none".to_string(), |tokens| tokens.to_string())
+        ),
+    ];
+    options.join(" ")
+}
 
-        // With API key
-        let args = Args::parse_from(["program", "--api-key", "test-key"]);
-        assert_eq!(args.api_key, Some("test-key".to_string()));
-    }
+/// Writes each message followed by a newline, appending or replacing the file
+fn write_messages(path: &Path, messages: &[String], append: bool) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .with_context(|| format!("Failed to open output file {}", path.display()))?;
 
-    #[test]
-    fn test_verbose_flag() {
-        // Not verbose
-        let args = Args::parse_from(["program"]);
-        assert!(!args.verbose);
+    for message in messages {
+        writeln!(file, "{message}")
+            .with_context(|| format!("Failed to write output file {}", path.display()))?;
+    }
+    Ok(())
+}
 
-        // Verbose short flag
-        let args = Args::parse_from(["program", "-v"]);
-        assert!(args.verbose);
+/// Picks the API key from `--api-key`, then `--api-key-file`, then the environment
+fn resolve_api_key(
+    cli: Option<String>,
+    file: Option<&Path>,
+    env_value: Option<String>,
+) -> Result<Option<String>> {
+    if cli.is_some() {
+        return Ok(cli);
+    }
+    if let Some(path) = file {
+        return read_api_key_file(path).map(Some);
+    }
+    Ok(cli_or_env(None, env_value))
+}
 
-        // Verbose long flag
-        let args = Args::parse_from(["program", "--verbose"]);
-        assert!(args.verbose);
+/// Parses `--auto-thresholds` as two comma-separated sizes, small first
+fn parse_auto_thresholds(value: &str) -> std::result::Result<(usize, usize), String> {
+    let parse = |size: &str| {
+        size.trim()
+            .parse::<usize>()
+            .map_err(|e| format!("invalid size '{size}': {e}"))
+    };
+    match value.split_once(',') {
+        Some((small, large)) => {
+            let (small, large) = (parse(small)?, parse(large)?);
+            if small >= large {
+                return Err(format!("SMALL ({small}) must be less than LARGE ({large})"));
+            }
+            Ok((small, large))
+        }
+        None => Err("expected two sizes, e.g. 60,500".to_string()),
+    }
+}
+
+/// How much a touched file adds to the size of a change, on top of its lines
+const AUTO_SIZE_PER_FILE: usize = 25;
+
+/// Picks how many messages `-n 0` generates. The size of a change is its
+/// changed lines plus `AUTO_SIZE_PER_FILE` per file: up to `small` gets one
+/// message, `large` or more gets three, and anything between gets two.
+fn auto_number(files_changed: usize, changed_lines: usize, (small, large): (usize, usize)) -> u8 {
+    let size = changed_lines + files_changed * AUTO_SIZE_PER_FILE;
+    if size <= small {
+        1
+    } else if size >= large {
+        3
+    } else {
+        2
+    }
+}
+
+/// File names of the lockfiles package managers write, used unless
+/// `--lockfiles` is given
+const DEFAULT_LOCKFILES: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "npm-shrinkwrap.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "bun.lockb",
+    "poetry.lock",
+    "Pipfile.lock",
+    "uv.lock",
+    "Gemfile.lock",
+    "composer.lock",
+    "go.sum",
+    "flake.lock",
+];
+
+/// The message for a change that only touches lockfiles
+const LOCKFILE_MESSAGE: &str = "chore(deps): update lockfile";
+
+/// Whether every staged path is a lockfile, in any directory. `lockfiles`
+/// holds file names; empty means `DEFAULT_LOCKFILES`.
+fn is_lockfile_only(staged_paths: &[String], lockfiles: &[String]) -> bool {
+    let is_lockfile = |path: &String| {
+        let name = path.rsplit('/').next().unwrap_or(path);
+        if lockfiles.is_empty() {
+            DEFAULT_LOCKFILES.contains(&name)
+        } else {
+            lockfiles.iter().any(|lockfile| lockfile == name)
+        }
+    };
+    !staged_paths.is_empty() && staged_paths.iter().all(is_lockfile)
+}
+
+/// Staged paths that usually deserve a second look before committing
+static RISKY_PATHS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(^|/)(migrations?|migrate)/|(^|/)\.env(\.[^/]*)?$|^\.github/workflows/").unwrap()
+});
+
+/// Why the staged change needs confirmation under `--confirm-large`, if it does
+fn large_change_reasons(staged_paths: &[String], max_files: usize) -> Vec<String> {
+    let mut reasons = Vec::new();
+    if staged_paths.len() > max_files {
+        reasons.push(format!(
+            "{} files are staged (more than {max_files})",
+            staged_paths.len()
+        ));
+    }
+    for path in staged_paths {
+        if RISKY_PATHS.is_match(path) {
+            reasons.push(format!("{path} is staged"));
+        }
+    }
+    reasons
+}
+
+/// Asks whether to go on despite the reasons. Without a terminal to ask on,
+/// goes on unless `strict` is set.
+fn confirm_large_change(
+    reasons: &[String],
+    stdin_is_terminal: bool,
+    strict: bool,
+    mut input: impl BufRead,
+) -> Result<bool> {
+    if !stdin_is_terminal {
+        if strict {
+            bail!(
+                "Refusing to continue without confirmation: {}",
+                reasons.join(", ")
+            );
+        }
+        return Ok(true);
+    }
+
+    for reason in reasons {
+        eprintln!("Warning: {reason}");
+    }
+    eprint!("Generate a commit message anyway? [y/N] ");
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Asks whether to rewrite a last commit that is already on these remote
+/// branches. Without a terminal to ask on, it refuses unless `--force`
+/// was given, which skips asking altogether.
+fn confirm_rewrite_pushed(
+    remotes: &[String],
+    stdin_is_terminal: bool,
+    mut input: impl BufRead,
+) -> Result<bool> {
+    let remotes = remotes.join(", ");
+    if !stdin_is_terminal {
+        bail!(
+            "The last commit is already on {remotes}; pass `regen --apply --force` to rewrite it anyway"
+        );
+    }
+
+    eprint!(
+        "The last commit is already on {remotes}; pushing the rewritten commit will need a force push. Rewrite it anyway? [y/N] "
+    );
+    std::io::stderr().flush()?;
+
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// What to do with an answer to the `--pick` prompt
+#[derive(Debug, PartialEq)]
+enum PickAction {
+    Choose(usize),
+    Edit(usize),
+    Regenerate,
+    Quit,
+}
+
+/// Reads one answer to the `--pick` prompt: a message number, `e` with an
+/// optional number to edit that message (the first by default), `r` or `q`.
+/// Returns None for anything else, including numbers out of range.
+fn parse_pick(answer: &str, count: usize) -> Option<PickAction> {
+    let answer = answer.trim().to_lowercase();
+    let number = |text: &str| {
+        text.trim()
+            .parse::<usize>()
+            .ok()
+            .filter(|n| (1..=count).contains(n))
+            .map(|n| n - 1)
+    };
+    match answer.as_str() {
+        "r" => Some(PickAction::Regenerate),
+        "q" => Some(PickAction::Quit),
+        "e" => (count > 0).then_some(PickAction::Edit(0)),
+        _ => match answer.strip_prefix('e') {
+            Some(rest) => number(rest).map(PickAction::Edit),
+            None => number(&answer).map(PickAction::Choose),
+        },
+    }
+}
+
+/// Lists the messages on stderr and asks until a valid answer is given.
+/// Running out of input counts as quitting.
+fn prompt_pick(candidates: &[Candidate], mut input: impl BufRead) -> Result<PickAction> {
+    for (n, candidate) in candidates.iter().enumerate() {
+        eprintln!("{}. {}", n + 1, candidate.message);
+    }
+    loop {
+        eprint!(
+            "Pick 1-{}, e [N] to edit, r to regenerate or q to quit: ",
+            candidates.len()
+        );
+        std::io::stderr().flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 {
+            return Ok(PickAction::Quit);
+        }
+        match parse_pick(&answer, candidates.len()) {
+            Some(action) => return Ok(action),
+            None => eprintln!("Invalid choice \"{}\"", answer.trim()),
+        }
+    }
+}
+
+/// The `--capabilities` matrix: a row per provider, "yes" or "no" per feature
+fn capabilities_table() -> String {
+    let header = [
+        "provider",
+        "streaming",
+        "n",
+        "seed",
+        "system prompt",
+        "temperature",
+        "usage",
+    ];
+    let mut rows = vec![header.map(str::to_string).to_vec()];
+    for provider in Provider::value_variants() {
+        let capabilities = provider.capabilities();
+        let mut row = vec![provider.name()];
+        row.extend(
+            [
+                capabilities.streaming,
+                capabilities.n,
+                capabilities.seed,
+                capabilities.system_prompt,
+                capabilities.temperature,
+                capabilities.usage,
+            ]
+            .map(|supported| if supported { "yes" } else { "no" }.to_string()),
+        );
+        rows.push(row);
+    }
+
+    let widths: Vec<usize> = (0..header.len())
+        .map(|column| rows.iter().map(|row| row[column].len()).max().unwrap_or(0))
+        .collect();
+    rows.iter()
+        .map(|row| {
+            let cells: Vec<String> = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:<width$}"))
+                .collect();
+            format!("{}\n", cells.join("  ").trim_end())
+        })
+        .collect()
+}
+
+/// Picks the allowed commit types from `--types`, then the environment's
+/// comma-separated list, then the config file. Empty entries are ignored.
+fn resolve_commit_types(
+    cli: Vec<String>,
+    env_value: Option<String>,
+    config_types: &[String],
+) -> Vec<String> {
+    let types = if !cli.is_empty() {
+        cli
+    } else if let Some(value) = env_value {
+        value.split(',').map(str::to_string).collect()
+    } else {
+        config_types.to_vec()
+    };
+
+    types
+        .iter()
+        .map(|commit_type| commit_type.trim().to_string())
+        .filter(|commit_type| !commit_type.is_empty())
+        .collect()
+}
+
+/// Picks the provider from the flag, then the environment, then Ollama
+fn resolve_provider(cli: Option<Provider>, env_value: Option<String>) -> Result<Option<Provider>> {
+    match (cli, env_value.filter(|value| !value.trim().is_empty())) {
+        (Some(provider), _) => Ok(Some(provider)),
+        (None, Some(value)) if value.trim().eq_ignore_ascii_case("auto") => Ok(None),
+        (None, Some(value)) => Provider::parse(&value).map(Some),
+        (None, None) => Ok(None),
+    }
+}
+
+/// Whether secrets are redacted without --redact-secrets: when the diff may
+/// leave this machine, through the provider or any of its fallbacks
+fn redacts_by_default(provider: Provider, fallback: &[Provider]) -> bool {
+    provider.is_cloud() || fallback.iter().any(Provider::is_cloud)
+}
+
+/// Picks a provider when none was given: a running Ollama first, then
+/// whichever cloud provider has its API key variable set. Falls back to
+/// Ollama, so the error says where it looked.
+fn detect_provider(ollama_running: bool, key_is_set: impl Fn(Provider) -> bool) -> Provider {
+    if ollama_running {
+        return Provider::Ollama;
+    }
+    [Provider::OpenAI, Provider::Gemini]
+        .into_iter()
+        .find(|&provider| key_is_set(provider))
+        .unwrap_or(Provider::Ollama)
+}
+
+/// Applies the message rewrites the flags ask for: ticket relocation, scope
+/// and subject case, then subject-only or body wrapping, and the user's
+/// prefix/suffix
+fn finish_message(message: &str, args: &Args, tickets: &[String]) -> String {
+    let message = if args.strip_emoji {
+        commit::strip_leading_emoji(message)
+    } else {
+        message.to_string()
+    };
+    let message = if args.ticket_to_footer {
+        commit::ticket_to_footer(&message)
+    } else {
+        message
+    };
+    let message = commit::apply_scope_case(&message, args.scope_case.into());
+    let message = commit::apply_subject_case(&message, args.subject_case.into());
+    let message = if args.allow_trailing_period {
+        message
+    } else {
+        commit::strip_subject_period(&message)
+    };
+    let message = if args.only_subject {
+        commit::subject_only(&message)
+    } else {
+        commit::wrap_body(&message, args.wrap)
+    };
+    let message = decorate_message(&message, args.prepend.as_deref(), args.append.as_deref());
+    let message = commit::add_ticket_footer(&message, tickets);
+    commit::add_co_authors(&message, &args.co_author)
+}
+
+/// `path: message` lines for --per-file, with any body indented below its
+/// subject
+fn format_per_file(suggestions: &[(String, String)]) -> Vec<String> {
+    suggestions
+        .iter()
+        .map(|(path, message)| {
+            let mut lines = message.lines();
+            let mut text = format!("{path}: {}", lines.next().unwrap_or_default());
+            for line in lines {
+                text.push('\n');
+                if !line.is_empty() {
+                    text.push_str("    ");
+                    text.push_str(line);
+                }
+            }
+            text
+        })
+        .collect()
+}
+
+/// The `--format json` output of --per-file
+fn per_file_json(
+    provider: &str,
+    model: &str,
+    suggestions: &[(String, String)],
+) -> serde_json::Value {
+    let files: Vec<serde_json::Value> = suggestions
+        .iter()
+        .map(|(path, message)| json!({ "path": path, "message": message }))
+        .collect();
+    json!({
+        "provider": provider,
+        "model": model,
+        "files": files,
+    })
+}
+
+/// Adds the user's prefix/suffix to a message, separated by a single space
+/// unless the text already provides its own whitespace
+fn decorate_message(message: &str, prepend: Option<&str>, append: Option<&str>) -> String {
+    let mut decorated = String::new();
+
+    if let Some(prefix) = prepend.filter(|p| !p.is_empty()) {
+        decorated.push_str(prefix);
+        if !prefix.ends_with(char::is_whitespace) {
+            decorated.push(' ');
+        }
+    }
+
+    decorated.push_str(message);
+
+    if let Some(suffix) = append.filter(|a| !a.is_empty()) {
+        if !suffix.starts_with(char::is_whitespace) {
+            decorated.push(' ');
+        }
+        decorated.push_str(suffix);
+    }
+
+    decorated
+}
+
+/// Reported when the staged diff changes nothing but whitespace
+const WHITESPACE_ONLY: &str = "Staged changes are whitespace-only";
+
+/// Exit code for a run interrupted by Ctrl-C (128 + SIGINT)
+const EXIT_CANCELLED: i32 = 130;
+
+/// Exit code for a message rejected by --check-only
+const EXIT_INVALID_MESSAGE: i32 = 1;
+
+/// Settles a run as a prepare-commit-msg hook: failing would abort the
+/// commit, so an error only warns on `out` and the file is left as it was
+fn leave_hook_file(result: Result<()>, path: &Path, out: &mut impl Write) {
+    if let Err(e) = result {
+        let _ = writeln!(out, "Warning: {e:#}; leaving {} as it is", path.display());
+    }
+}
+
+/// Loads the commitlint config given with --convention-file, or else the
+/// one found at `repo_root`, if any
+fn load_convention(repo_root: Option<&Path>, path: Option<&Path>) -> Result<Convention> {
+    match Convention::load(repo_root, path) {
+        Ok(convention) => Ok(convention.unwrap_or_default()),
+        // A found .commitlintrc may well be YAML, which shouldn't stop generation
+        Err(e) if path.is_none() => {
+            eprintln!("Warning: {e:#}");
+            Ok(Convention::default())
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// The types given with --types or the environment, or else the ones the
+/// commitlint config allows
+fn allowed_types(commit_types: Vec<String>, convention: &Convention) -> Vec<String> {
+    if commit_types.is_empty() {
+        convention.types.clone()
+    } else {
+        commit_types
+    }
+}
+
+/// Validates a message for --check-only, writing a diagnostic per problem to
+/// `out`, and returns the exit code
+fn check_message(
+    message: &str,
+    commit_types: &[String],
+    max_subject_len: Option<usize>,
+    out: &mut impl Write,
+) -> Result<i32> {
+    let mut problems = commit::conventional_problems(message, commit_types);
+    problems.extend(max_subject_len.and_then(|max| commit::subject_length_problem(message, max)));
+    if problems.is_empty() {
+        return Ok(0);
+    }
+
+    writeln!(out, "Invalid commit message:")?;
+    for problem in &problems {
+        writeln!(out, "  - {problem}")?;
+    }
+    Ok(EXIT_INVALID_MESSAGE)
+}
+
+/// Runs `work` unless `cancel` completes first, in which case the work is
+/// dropped and None is returned
+async fn until_cancelled<F: Future>(
+    work: F,
+    cancel: impl Future<Output = ()>,
+) -> Option<F::Output> {
+    tokio::select! {
+        output = work => Some(output),
+        _ = cancel => None,
+    }
+}
+
+/// Resolves on Ctrl-C. Never resolves if the signal handler can't be installed.
+async fn ctrl_c() {
+    if tokio::signal::ctrl_c().await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    if let Err(e) = check_regen_conflicts(&args) {
+        e.exit();
+    }
+    let format = args.format;
+    let json_pretty = args.json_pretty;
+    let hook_file = args
+        .from_commit_msg_file
+        .clone()
+        .filter(|_| !args.check_only);
+
+    let result = run(args).await;
+    if let Some(path) = &hook_file {
+        leave_hook_file(result, path, &mut std::io::stderr());
+        return Ok(());
+    }
+    match result {
+        Err(e) if format == OutputFormat::Json => {
+            eprintln!("{}", render_json(&error::to_json(&e), json_pretty));
+            process::exit(1);
+        }
+        result => result,
+    }
+}
+
+/// The editor `--edit` and the picker's edit action open
+fn resolve_editor(repo: &git::Repository) -> Result<String> {
+    editor::resolve_editor(
+        &[env_var("EDITOR"), env_var("VISUAL"), repo.core_editor()],
+        std::env::var_os("PATH").as_deref(),
+    )
+}
+
+/// Hands over the finished messages: through `--edit`, then to the hook's
+/// file or stdout, `--output-file`, and `regen --apply` or `--commit`.
+/// `answered_by` is the provider and model `--format json` reports.
+async fn deliver_messages(
+    repo: &git::Repository,
+    args: &Args,
+    commit_msg_file: Option<&hook::CommitMsgFile>,
+    candidates: Vec<Candidate>,
+    answered_by: (String, String),
+    apply: bool,
+) -> Result<()> {
+    let candidates = if args.edit {
+        let editor = resolve_editor(repo)?;
+        let (first, others) = candidates
+            .split_first()
+            .context("No message was generated to edit")?;
+        let others: Vec<String> = others.iter().map(|other| other.message.clone()).collect();
+        let message = editor::edit_message(&editor, &first.message, &others).await?;
+        vec![Candidate {
+            message,
+            rationale: None,
+        }]
+    } else {
+        candidates
+    };
+
+    let messages: Vec<String> = candidates
+        .iter()
+        .map(|candidate| candidate.message.clone())
+        .collect();
+
+    match (commit_msg_file, args.format) {
+        // The hook's file takes the one message git will use
+        (Some(file), _) => {
+            if let Some(message) = messages.first() {
+                file.write(message)?;
+            }
+        }
+        (None, OutputFormat::Json) => {
+            let (provider, model) = answered_by;
+            println!(
+                "{}",
+                render_json(
+                    &messages_json(&provider, &model, &candidates),
+                    args.json_pretty
+                )
+            );
+        }
+        (None, OutputFormat::Text) => {
+            let stdout_is_terminal = std::io::stdout().is_terminal();
+            let printed =
+                select_piped_messages(candidates, stdout_is_terminal, args.pipe_behavior)?;
+            for (n, candidate) in printed.into_iter().enumerate() {
+                let candidate = match &args.output_template {
+                    Some(template) => Candidate {
+                        message: render_output_template(template, n + 1, &candidate.message),
+                        ..candidate
+                    },
+                    None => candidate,
+                };
+                println!("{}", format_candidate(&candidate, stdout_is_terminal));
+            }
+        }
+    }
+
+    if let Some(path) = &args.output_file {
+        write_messages(path, &messages, args.append_to_file)?;
+    }
+
+    if apply && let Some(message) = messages.first() {
+        repo.amend_head_message(message)
+            .context("Failed to rewrite the last commit")?;
+        if args.verbose {
+            eprintln!("Rewrote the last commit's message");
+        }
+    }
+
+    if args.commit
+        && let Some(message) = messages.first()
+    {
+        let oid = repo
+            .commit(message)
+            .context("Failed to commit the staged changes")?;
+        if args.verbose {
+            eprintln!("Created commit {oid}");
+        }
+    }
+
+    Ok(())
+}
+
+async fn run(args: Args) -> Result<()> {
+    if args.capabilities {
+        print!("{}", capabilities_table());
+        return Ok(());
+    }
+    let (regen, apply, force) = match args.command {
+        Some(Command::Regen { apply, force }) => (true, apply, force),
+        None => (false, false, false),
+    };
+
+    let config = config::Config::load(env_var(ENV_CONFIG).as_deref().map(Path::new))?;
+    let commit_types = resolve_commit_types(args.types.clone(), env_var(ENV_TYPES), config.types());
+    if args.check_only {
+        let message = match &args.from_commit_msg_file {
+            Some(path) => hook::CommitMsgFile::read(path)?.content().to_string(),
+            None => {
+                let mut text = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut text)
+                    .context("Failed to read the message from stdin")?;
+                editor::strip_comments(&text)
+            }
+        };
+        // Outside a repository there is just no commitlint config to find
+        let repo = open_repository(args.repo_path.as_deref(), args.verbose).ok();
+        let convention = load_convention(
+            repo.as_ref().and_then(git::Repository::workdir),
+            args.convention_file.as_deref(),
+        )?;
+        process::exit(check_message(
+            &message,
+            &allowed_types(commit_types, &convention),
+            args.max_subject_len,
+            &mut std::io::stderr(),
+        )?);
+    }
+
+    if args.numbers > 5 {
+        bail!("Number of messages must be between 1 and 5 (or 0 for auto)");
+    }
+
+    if args.diff_alg == DiffAlgArg::Difftastic && !git::is_difftastic_available() {
+        bail!(
+            "--diff-algorithm difftastic requires difftastic (difft) in your PATH. Install it from https://difftastic.wilfred.me.uk/ or choose another algorithm, e.g. --diff-algorithm histogram"
+        );
+    }
+
+    if args.verbose {
+        eprintln!("Opening git repository...");
+    }
+
+    let repo = open_repository(args.repo_path.as_deref(), args.verbose)?
+        .with_difft_timeout(Duration::from_secs(args.difft_timeout))
+        .with_diff_filter(args.diff_filter.clone())
+        .with_include_intent_to_add(args.include_intent_to_add);
+    // Loaded first, since --skip-if-valid checks against its types too
+    let convention = load_convention(repo.workdir(), args.convention_file.as_deref())?;
+    let commit_types = allowed_types(commit_types, &convention);
+
+    let commit_msg_file = match &args.from_commit_msg_file {
+        Some(path) => {
+            let file = hook::CommitMsgFile::read(path)?;
+            if args.skip_if_valid && file.has_conventional_message(&commit_types) {
+                if args.verbose {
+                    eprintln!(
+                        "{} already has a conventional commit message, skipping generation",
+                        path.display()
+                    );
+                }
+                return Ok(());
+            }
+            if !file.content().is_empty() && !args.overwrite {
+                if args.verbose {
+                    eprintln!(
+                        "{} already has a message, leaving it alone (use --overwrite to replace it)",
+                        path.display()
+                    );
+                }
+                return Ok(());
+            }
+            Some(file)
+        }
+        None => None,
+    };
+
+    if args.add_all || args.add_all_untracked {
+        repo.stage_all(args.add_all_untracked)
+            .context("Failed to stage changes")?;
+    }
+
+    let branch_name = repo
+        .get_branch_name()
+        .context("Failed to get branch name")?;
+
+    let tickets = if args.add_ticket {
+        // The last commit only says which ticket the branch is for if it
+        // belongs to the branch, rather than to whatever it started from
+        let previous = if repo.head_is_ahead_of_base().unwrap_or(false) {
+            repo.get_last_commit_messages(1).unwrap_or_default()
+        } else {
+            Vec::new()
+        };
+        commit::preferred_tickets(previous.first().map(String::as_str), &branch_name)
+    } else {
+        Vec::new()
+    };
+
+    if args.verbose {
+        eprintln!("Current branch: {branch_name}");
+        if regen {
+            eprintln!("Reading the last commit's changes...");
+        } else {
+            eprintln!("Reading staged changes...");
+        }
+    }
+
+    // Chunked mode reads each file's diff from git as it is summarized,
+    // rather than splitting the whole text, which a staged patch file could
+    // throw off and a huge change would have to be held for
+    let mut streams_files = args.chunked
+        && !regen
+        && !args.no_diff
+        && args.compare.is_empty()
+        && matches!(
+            args.diff_alg,
+            DiffAlgArg::Default | DiffAlgArg::Patience | DiffAlgArg::Minimal
+        );
+
+    let diff_alg = args.diff_alg.into();
+    let mut staged = if regen {
+        let staged = repo
+            .get_head_diff(diff_alg)
+            .context("Failed to read the last commit")?;
+        if staged.text.is_empty() {
+            bail!("The last commit has no changes to describe");
+        }
+        // Asked before generating, so a refusal costs no request
+        let remotes = repo.remote_branches_containing_head().unwrap_or_default();
+        if apply
+            && !force
+            && !remotes.is_empty()
+            && !confirm_rewrite_pushed(
+                &remotes,
+                std::io::stdin().is_terminal(),
+                std::io::stdin().lock(),
+            )?
+        {
+            bail!("Aborted");
+        }
+        staged
+    } else if streams_files {
+        repo.get_staged_stats(diff_alg)
+            .context("Failed to get staged diff")?
+    } else {
+        repo.get_staged_diff(diff_alg)
+            .context("Failed to get staged diff")?
+    };
+    // A single file is sent whole anyway
+    if streams_files && staged.paths.len() < 2 {
+        streams_files = false;
+        staged = repo
+            .get_staged_diff(args.diff_alg.into())
+            .context("Failed to get staged diff")?;
+    }
+    let no_changes = staged.text.is_empty() && !streams_files;
+    if no_changes && commit_msg_file.is_some() {
+        eprintln!("Warning: no staged changes found, leaving the message alone");
+        return Ok(());
+    }
+    if no_changes && args.format == OutputFormat::Json {
+        return Err(error::Error::NoStagedChanges.into());
+    }
+    if no_changes {
+        println!("No staged changes found. Stage some changes first with 'git add'");
+        println!(
+            "Make sure you have staged changes using 'git add <file>' before running this command"
+        );
+        process::exit(1);
+    }
+
+    let whitespace_only = || -> Result<bool> {
+        if streams_files {
+            commit::files_are_whitespace_only(repo.staged_file_diffs(args.diff_alg.into())?)
+        } else {
+            Ok(commit::is_whitespace_only(&staged.text))
+        }
+    };
+    if !args.allow_whitespace && whitespace_only()? {
+        // Failing would abort the commit the hook runs for
+        if commit_msg_file.is_some() {
+            eprintln!("Warning: {WHITESPACE_ONLY}, leaving the message alone");
+            return Ok(());
+        }
+        bail!("{WHITESPACE_ONLY}; pass --allow-whitespace to generate a message anyway");
+    }
+
+    // Settled before a provider is even picked, so none has to be reachable
+    // or have an API key
+    if !args.force_ai && is_lockfile_only(&staged.paths, &args.lockfiles) {
+        if args.verbose {
+            eprintln!(
+                "Only lockfiles are staged; using \"{LOCKFILE_MESSAGE}\" without asking the AI"
+            );
+        }
+        let candidates = vec![Candidate {
+            message: finish_message(LOCKFILE_MESSAGE, &args, &tickets),
+            rationale: None,
+        }];
+        let answered_by = ("none".to_string(), "none".to_string());
+        return deliver_messages(
+            &repo,
+            &args,
+            commit_msg_file.as_ref(),
+            candidates,
+            answered_by,
+            apply,
+        )
+        .await;
+    }
+
+    let api_url = cli_or_env(args.api_url.clone(), env_var(ENV_API_URL));
+    let provider = match resolve_provider(args.provider, env_var(ENV_PROVIDER))? {
+        Some(provider) => provider,
+        None => {
+            let probe = ai::provider_factory::ollama_is_running(
+                api_url
+                    .as_deref()
+                    .unwrap_or(ai::provider_factory::OLLAMA_URL),
+            );
+            let Some(ollama_running) = until_cancelled(probe, ctrl_c()).await else {
+                eprintln!("Cancelled");
+                process::exit(EXIT_CANCELLED);
+            };
+            let provider = detect_provider(ollama_running, |provider| {
+                provider
+                    .api_key_var()
+                    .and_then(env_var)
+                    .is_some_and(|key| !key.trim().is_empty())
+            });
+            if args.verbose {
+                eprintln!("No provider given, detected {provider:?}");
+            }
+            provider
+        }
+    };
+    let prompt_options = prompt_options(provider, &args);
+    let api_key = resolve_api_key(
+        args.api_key.clone(),
+        args.api_key_file.as_deref(),
+        env_var(ENV_API_KEY).or_else(|| provider.api_key_var().and_then(env_var)),
+    )?;
+
+    // Use the model provided by the user or fall back to the provider's default
+    let model = cli_or_env(args.model.clone(), env_var(ENV_MODEL))
+        .unwrap_or_else(|| provider.default_model().to_string());
+    let model = config.resolve_model(provider, &model);
+
+    // The per-file summaries stand in for a streamed diff
+    let diff = if args.no_diff || streams_files {
+        changed_file_list(&staged.paths, &staged.renames)
+    } else {
+        staged.text
+    };
+
+    let count = match args.numbers {
+        0 => {
+            let count = auto_number(
+                staged.files_changed,
+                staged.insertions + staged.deletions,
+                args.auto_thresholds,
+            );
+            if args.verbose {
+                eprintln!("Generating {count} message(s) for the size of the change");
+            }
+            count
+        }
+        numbers => numbers,
+    };
+
+    if args.confirm_large {
+        let reasons = large_change_reasons(&staged.paths, args.large_threshold);
+        if !reasons.is_empty()
+            && !confirm_large_change(
+                &reasons,
+                std::io::stdin().is_terminal(),
+                args.strict,
+                std::io::stdin().lock(),
+            )?
+        {
+            bail!("Aborted");
+        }
+    }
+
+    if args.verbose {
+        eprintln!(
+            "Found staged changes in {} file(s) (+{} -{}), generating commit message...",
+            staged.files_changed, staged.insertions, staged.deletions
+        );
+        for (from, to) in &staged.renames {
+            eprintln!("Renamed: {from} -> {to}");
+        }
+        eprintln!("Using provider: {provider:?} with model: {model}");
+    }
+
+    let last_commit_titles = recent_commit_titles(&repo, &args);
+    let target_branch = repo.get_default_branch().unwrap_or_default();
+    let branch_description = if args.use_branch_description {
+        repo.get_branch_description().unwrap_or_default()
+    } else {
+        None
+    };
+    let pull_request = if args.context_commits_from_pr {
+        PullRequest::from_env()
+    } else {
+        None
+    };
+    if args.verbose
+        && let Some(pull_request) = &pull_request
+    {
+        eprintln!("Using pull request context: {}", pull_request.title);
+    }
+    let merge = repo.merge_info().unwrap_or_else(|e| {
+        eprintln!("Warning: couldn't read the merge in progress: {e}");
+        None
+    });
+    if args.verbose
+        && let Some(merge) = &merge
+    {
+        eprintln!(
+            "Merging {} (resolved by hand: {})",
+            merge.merged.join(", "),
+            merge.resolved_files.len()
+        );
+    }
+
+    let staged_paths = staged.paths;
+    let scope_hint = match repo.workdir() {
+        Some(root) => ScopeMap::load(root)?.and_then(|map| map.resolve(&staged_paths)),
+        None => None,
+    };
+    let (mapped_scope, scope_candidates) = match scope_hint {
+        Some(ScopeHint::Preferred(scope)) => (Some(scope), Vec::new()),
+        Some(ScopeHint::Candidates(scopes)) => (None, scopes),
+        None => (None, Vec::new()),
+    };
+    let default_scope =
+        args.template_scope.clone().or(mapped_scope).or_else(|| {
+            commit::package_scope(&staged_paths, &args.package_root, args.package_depth)
+        });
+
+    let style_examples = if args.style_examples > 0 {
+        repo.get_last_commit_messages(args.style_examples)
+            .unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+
+    let type_distribution = if args.type_history > 0 {
+        let titles = repo
+            .get_last_commit_titles(args.type_history)
+            .unwrap_or_default();
+        commit::type_distribution(&titles)
+    } else {
+        Vec::new()
+    };
+
+    // No bursts, so no minute ever goes over --rpm
+    let throttle = args
+        .rpm
+        .map(|rpm| Arc::new(ai::throttle::TokenBucket::new(1, rpm)));
+
+    // Settings shared by the generator of every provider in use
+    let configure = |provider: Provider, ai_provider: Box<dyn AiProvider>| {
+        commit::CommitMessageGenerator::new(ai_provider)
+            .with_style_examples(style_examples.clone())
+            .with_type_distribution(type_distribution.clone())
+            .with_default_scope(default_scope.clone())
+            .with_scope_candidates(scope_candidates.clone())
+            .with_target_branch(target_branch.clone())
+            .with_branch_description(branch_description.clone())
+            .with_pull_request(pull_request.clone())
+            .with_merge(merge.clone())
+            .with_chunked(args.chunked)
+            .with_explain(args.explain)
+            .with_allow_trailing_period(args.allow_trailing_period)
+            .with_redact_secrets(
+                args.redact_secrets
+                    .unwrap_or(redacts_by_default(provider, &args.fallback)),
+            )
+            .with_trim_diff_headers(args.trim_diff_headers)
+            .with_max_file_lines(args.max_file_lines)
+            .with_error_report(
+                (args.debug_on_error || args.verbose)
+                    .then(|| Arc::new(Mutex::new(std::io::stderr())) as commit::ReportOutput),
+            )
+            .with_diff_report(
+                args.show_diff
+                    .then(|| Arc::new(Mutex::new(std::io::stderr())) as commit::ReportOutput),
+            )
+            .with_diff_wrapper(args.diff_wrapper.into())
+            .with_no_diff(args.no_diff)
+            .with_commit_types(commit_types.clone())
+            .with_header_max_length(convention.header_max_length)
+            .with_max_subject_len(args.max_subject_len)
+            .with_list_markers(args.list_marker.clone())
+            .with_diversify(args.diversify)
+            .with_top_up(!args.no_top_up)
+            .with_concurrency(usize::from(args.concurrency))
+            .with_throttle(throttle.clone())
+            .with_retry_budget(args.retry_budget)
+            .with_max_prompt_tokens(args.max_prompt_tokens)
+    };
+
+    let provider_options = ProviderOptions {
+        json_mode: args.json_mode,
+        no_stream: args.no_stream,
+        ollama_keep_alive: args.ollama_keep_alive.clone(),
+        seed: args.seed,
+        command: args.provider_command.clone(),
+    };
+    if !args.compare.is_empty() {
+        let mut generators = Vec::new();
+        for &compared in &args.compare {
+            let model = config.resolve_model(compared, compared.default_model());
+            let (api_key, api_url) = compared_credentials(
+                compared,
+                provider,
+                api_key.as_deref(),
+                api_url.as_deref(),
+                env_var,
+            );
+            match create_provider(
+                compared,
+                &model,
+                api_key.as_deref(),
+                api_url.as_deref(),
+                args.verbose,
+                &provider_options,
+            ) {
+                Ok(ai_provider) => {
+                    generators.push((compared, model, configure(compared, ai_provider)))
+                }
+                Err(e) => eprintln!("Warning: skipping {compared:?}: {e}"),
+            }
+        }
+
+        let comparison = compare_generators(
+            &generators,
+            &diff,
+            &branch_name,
+            count,
+            args.instructions.as_deref(),
+            &last_commit_titles,
+        );
+        let Some(results) = until_cancelled(comparison, ctrl_c()).await else {
+            eprintln!("Cancelled");
+            process::exit(EXIT_CANCELLED);
+        };
+        match args.format {
+            OutputFormat::Json => {
+                println!(
+                    "{}",
+                    render_json(&comparison_json(&results), args.json_pretty)
+                );
+            }
+            OutputFormat::Text => print!("{}", format_comparison(&results)),
+        }
+        return Ok(());
+    }
+
+    let ai_provider = create_provider(
+        provider,
+        &model,
+        api_key.as_deref(),
+        api_url.as_deref(),
+        args.verbose,
+        &provider_options,
+    );
+    let ai_provider = if args.fallback.is_empty() {
+        ai_provider?
+    } else {
+        let fallbacks = args.fallback.iter().map(|&fallback| {
+            let model = config.resolve_model(fallback, fallback.default_model());
+            let api_key = fallback.api_key_var().and_then(env_var);
+            let created = create_provider(
+                fallback,
+                &model,
+                api_key.as_deref(),
+                None,
+                args.verbose,
+                &provider_options,
+            );
+            (fallback, model, created)
+        });
+
+        // Providers that can't even be created (no API key) are left out
+        let mut chain = Vec::new();
+        let mut first_error = None;
+        for (provider, model, created) in
+            std::iter::once((provider, model.clone(), ai_provider)).chain(fallbacks)
+        {
+            match created {
+                Ok(ai_provider) => chain.push(FallbackEntry {
+                    provider: provider.name(),
+                    model,
+                    ai_provider,
+                }),
+                Err(e) => {
+                    eprintln!("Warning: skipping {provider:?} ({model}): {e}");
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) if chain.is_empty() => return Err(e.into()),
+            _ => Box::new(FallbackProvider::new(chain)),
+        }
+    };
+    if args.seed.is_some() && !ai_provider.capabilities().seed {
+        eprintln!("Warning: {provider:?} ignores --seed (see --capabilities)");
+    }
+
+    let escalation = match &args.escalate_model {
+        Some(escalate_model) => {
+            let escalate_model = config.resolve_model(provider, escalate_model);
+            if args.verbose {
+                eprintln!("Escalating to model: {escalate_model}");
+            }
+            Some(create_provider(
+                provider,
+                &escalate_model,
+                api_key.as_deref(),
+                api_url.as_deref(),
+                args.verbose,
+                &provider_options,
+            )?)
+        }
+        None => None,
+    };
+
+    // A failed preload isn't fatal; the real request reports any problem
+    if args.ollama_preload
+        && let Err(e) = ai_provider.preload().await
+        && args.verbose
+    {
+        eprintln!("Preloading the model failed: {e}");
+    }
+
+    let generator = configure(provider, ai_provider)
+        .with_escalation(escalation)
+        .with_prompt_dump(
+            args.dump_prompt.clone(),
+            format!("# provider: {provider:?}\n# model: {model}\n# options: {prompt_options}\n\n"),
+        );
+    let generator = if streams_files {
+        let files = repo
+            .staged_file_diffs(args.diff_alg.into())
+            .context("Failed to get staged diff")?;
+        let summarizing = generator.summarize_files(files, args.verbose);
+        let Some(summaries) = until_cancelled(summarizing, ctrl_c()).await else {
+            eprintln!("Cancelled");
+            process::exit(EXIT_CANCELLED);
+        };
+        let summaries = summaries.context("Failed to generate commit message")?;
+        generator.with_file_summaries(Some(summaries))
+    } else {
+        generator
+    };
+
+    if args.verbose {
+        eprintln!("Generating commit message(s)...");
+    }
+
+    if args.per_file {
+        let generation = generator.generate_per_file(
+            &diff,
+            &branch_name,
+            args.instructions.as_deref(),
+            &last_commit_titles,
+            args.verbose,
+        );
+        let Some(suggestions) = until_cancelled(generation, ctrl_c()).await else {
+            eprintln!("Cancelled");
+            process::exit(EXIT_CANCELLED);
+        };
+        let suggestions: Vec<(String, String)> = suggestions
+            .context("Failed to generate commit message")?
+            .into_iter()
+            .map(|(path, candidate)| (path, finish_message(&candidate.message, &args, &tickets)))
+            .collect();
+
+        match args.format {
+            OutputFormat::Json => {
+                let (provider, model) = generator
+                    .answered_by()
+                    .unwrap_or_else(|| (provider.name(), model.clone()));
+                println!(
+                    "{}",
+                    render_json(
+                        &per_file_json(&provider, &model, &suggestions),
+                        args.json_pretty
+                    )
+                );
+            }
+            OutputFormat::Text => {
+                for suggestion in format_per_file(&suggestions) {
+                    println!("{suggestion}");
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    let base_instructions = match &commit_msg_file {
+        Some(file) => file.context_instructions(args.instructions.as_deref()),
+        None => args.instructions.clone(),
+    };
+    let candidates = loop {
+        let mut instructions = base_instructions.clone();
+        let mut retries_left = args.post_retries;
+        let candidates = loop {
+            let generation = generator.generate(
+                &diff,
+                &branch_name,
+                count,
+                instructions.as_deref(),
+                &last_commit_titles,
+                args.verbose,
+            );
+            let Some(messages) = until_cancelled(generation, ctrl_c()).await else {
+                eprintln!("Cancelled");
+                process::exit(EXIT_CANCELLED);
+            };
+            let messages = or_fallback_message(messages, args.fallback_message.as_deref())
+                .context("Failed to generate commit message")?;
+
+            let candidates: Vec<Candidate> = messages
+                .into_iter()
+                .map(|candidate| Candidate {
+                    message: finish_message(&candidate.message, &args, &tickets),
+                    ..candidate
+                })
+                .collect();
+
+            let Some(post_command) = &args.post_command else {
+                break candidates;
+            };
+            let (passed, rejections) =
+                apply_post_command(post_command, candidates, args.post_rewrites).await?;
+            for (message, output) in &rejections {
+                eprintln!("--post-command rejected \"{message}\":\n{output}");
+            }
+            if !passed.is_empty() {
+                break passed;
+            }
+            if retries_left == 0 {
+                bail!("Every message was rejected by --post-command");
+            }
+
+            retries_left -= 1;
+            if args.verbose {
+                eprintln!("Regenerating after --post-command rejected every message...");
+            }
+            instructions = Some(rejection_instructions(
+                base_instructions.as_deref(),
+                &rejections,
+            ));
+        };
+        let mut candidates = limit_shown(candidates, args.show);
+        if !args.pick || !std::io::stdin().is_terminal() {
+            break candidates;
+        }
+
+        match prompt_pick(&candidates, std::io::stdin().lock())? {
+            PickAction::Choose(n) => break vec![candidates.swap_remove(n)],
+            PickAction::Edit(n) => {
+                let editor = resolve_editor(&repo)?;
+                let chosen = candidates.swap_remove(n);
+                let others: Vec<String> =
+                    candidates.into_iter().map(|other| other.message).collect();
+                let message = editor::edit_message(&editor, &chosen.message, &others).await?;
+                break vec![Candidate {
+                    message,
+                    rationale: None,
+                }];
+            }
+            PickAction::Regenerate => {
+                if args.verbose {
+                    eprintln!("Regenerating commit message(s)...");
+                }
+            }
+            PickAction::Quit => {
+                eprintln!("Cancelled");
+                process::exit(EXIT_CANCELLED);
+            }
+        }
+    };
+
+    if args.print_usage {
+        match generator.usage() {
+            Some(usage) => eprintln!("{usage}"),
+            None => eprintln!("Token usage is not reported by {provider:?}"),
+        }
+    }
+
+    let answered_by = generator
+        .answered_by()
+        .unwrap_or_else(|| (provider.name(), model.clone()));
+    deliver_messages(
+        &repo,
+        &args,
+        commit_msg_file.as_ref(),
+        candidates,
+        answered_by,
+        apply,
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ai::mock::MockProvider;
+    use clap::CommandFactory;
+    use commit::CommitMessageGenerator;
+
+    #[test]
+    fn test_provider_default_model() {
+        assert_eq!(Provider::Ollama.default_model(), "qwen2.5-coder");
+        assert_eq!(Provider::OpenAI.default_model(), "gpt-4o-mini");
+        assert_eq!(Provider::Gemini.default_model(), "gemini-2.0-flash-lite");
+        assert_eq!(Provider::LlamaCpp.default_model(), "default");
+        assert_eq!(Provider::Command.default_model(), "default");
+    }
+
+    #[test]
+    fn test_capabilities_table() {
+        assert_eq!(
+            capabilities_table(),
+            "\
+provider  streaming  n   seed  system prompt  temperature  usage
+ollama    no         no  yes   no             yes          no
+openai    yes        no  yes   yes            yes          yes
+gemini    no         no  no    no             yes          no
+llamacpp  no         no  no    yes            yes          no
+command   no         no  no    no             no           no
+"
+        );
+        assert!(Args::parse_from(["program", "--capabilities"]).capabilities);
+    }
+
+    #[test]
+    fn test_provider_command_flag() {
+        let args = Args::parse_from([
+            "program",
+            "-p",
+            "command",
+            "--provider-command",
+            "my-llm --fast",
+        ]);
+        assert_eq!(args.provider, Some(Provider::Command));
+        assert_eq!(args.provider_command.as_deref(), Some("my-llm --fast"));
+    }
+
+    #[test]
+    fn test_provider_parsing() {
+        let args = Args::parse_from(["program", "--provider", "ollama"]);
+        assert_eq!(args.provider, Some(Provider::Ollama));
+
+        let args = Args::parse_from(["program", "--provider", "openai"]);
+        assert_eq!(args.provider, Some(Provider::OpenAI));
+
+        let args = Args::parse_from(["program", "--provider", "gemini"]);
+        assert_eq!(args.provider, Some(Provider::Gemini));
+
+        let args = Args::parse_from(["program", "--provider", "llamacpp"]);
+        assert_eq!(args.provider, Some(Provider::LlamaCpp));
+
+        let args = Args::parse_from(["program", "--provider", "llama-cpp"]);
+        assert_eq!(args.provider, Some(Provider::LlamaCpp));
+
+        // Without the flag the provider is resolved later
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.provider, None);
+    }
+
+    #[test]
+    fn test_numbers_validation() {
+        // Valid number
+        let args = Args::parse_from(["program", "--number", "3"]);
+        assert_eq!(args.numbers, 3);
+
+        // Default value
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.numbers, 1);
+    }
+
+    #[test]
+    fn test_auto_number() {
+        let defaults = (60, 500);
+
+        // A one-line fix in one file
+        assert_eq!(auto_number(1, 2, defaults), 1);
+        // A small change across two files
+        assert_eq!(auto_number(2, 10, defaults), 1);
+        // A moderate feature
+        assert_eq!(auto_number(4, 120, defaults), 2);
+        // Many files, even with few lines each
+        assert_eq!(auto_number(20, 40, defaults), 3);
+        // A large rewrite of one file
+        assert_eq!(auto_number(1, 800, defaults), 3);
+
+        // The thresholds are adjustable
+        assert_eq!(auto_number(4, 120, (300, 1000)), 1);
+        assert_eq!(auto_number(1, 2, (0, 100)), 2);
+    }
+
+    #[test]
+    fn test_auto_thresholds_flag() {
+        let args = Args::parse_from(["program", "-n", "0"]);
+        assert_eq!(args.numbers, 0);
+        assert_eq!(args.auto_thresholds, (60, 500));
+
+        let args = Args::parse_from(["program", "-n", "0", "--auto-thresholds", "100,800"]);
+        assert_eq!(args.auto_thresholds, (100, 800));
+
+        for invalid in ["100", "100,abc", "800,100"] {
+            assert!(Args::try_parse_from(["program", "--auto-thresholds", invalid]).is_err());
+        }
+    }
+
+    #[test]
+    fn test_model_selection() {
+        // Default model
+        let args = Args::parse_from(["program", "--provider", "ollama"]);
+        let provider = resolve_provider(args.provider, None).unwrap().unwrap();
+        let model =
+            cli_or_env(args.model, None).unwrap_or_else(|| provider.default_model().to_string());
+        assert_eq!(model, "qwen2.5-coder");
+
+        // Custom model
+        let args = Args::parse_from(["program", "--provider", "ollama", "--model", "llama3"]);
+        let provider = resolve_provider(args.provider, None).unwrap().unwrap();
+        let model =
+            cli_or_env(args.model, None).unwrap_or_else(|| provider.default_model().to_string());
+        assert_eq!(model, "llama3");
+    }
+
+    #[test]
+    fn test_provider_env_fallback() {
+        // Environment value is used when the flag is absent
+        let provider = resolve_provider(None, Some("openai".to_string())).unwrap();
+        assert_eq!(provider, Some(Provider::OpenAI));
+
+        // Case-insensitive, like the flag
+        let provider = resolve_provider(None, Some("Gemini".to_string())).unwrap();
+        assert_eq!(provider, Some(Provider::Gemini));
+
+        // The flag takes precedence
+        let provider =
+            resolve_provider(Some(Provider::Ollama), Some("openai".to_string())).unwrap();
+        assert_eq!(provider, Some(Provider::Ollama));
+
+        // Neither set (or blank), or "auto", leaves it to detection
+        assert_eq!(resolve_provider(None, None).unwrap(), None);
+        assert_eq!(resolve_provider(None, Some(" ".to_string())).unwrap(), None);
+        assert_eq!(
+            resolve_provider(None, Some("Auto".to_string())).unwrap(),
+            None
+        );
+    }
+
+    #[test]
+    fn test_detect_provider() {
+        let keys = |set: &'static [Provider]| move |provider| set.contains(&provider);
+
+        // A running Ollama wins, even with cloud keys around
+        assert_eq!(
+            detect_provider(true, keys(&[Provider::OpenAI, Provider::Gemini])),
+            Provider::Ollama
+        );
+        assert_eq!(detect_provider(true, keys(&[])), Provider::Ollama);
+
+        // Otherwise the first cloud provider with a key
+        assert_eq!(
+            detect_provider(false, keys(&[Provider::OpenAI, Provider::Gemini])),
+            Provider::OpenAI
+        );
+        assert_eq!(
+            detect_provider(false, keys(&[Provider::Gemini])),
+            Provider::Gemini
+        );
+
+        // Nothing available: Ollama, whose error says where it looked
+        assert_eq!(detect_provider(false, keys(&[])), Provider::Ollama);
+    }
+
+    #[test]
+    fn test_provider_api_key_var() {
+        assert_eq!(Provider::OpenAI.api_key_var(), Some("OPENAI_API_KEY"));
+        assert_eq!(Provider::Gemini.api_key_var(), Some("GEMINI_API_KEY"));
+        assert_eq!(Provider::Ollama.api_key_var(), None);
+    }
+
+    #[tokio::test]
+    async fn test_ollama_probe_unreachable() {
+        // Find a free port and release it, so nothing listens there
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+
+        let started = Instant::now();
+        assert!(!ai::provider_factory::ollama_is_running(&url).await);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_provider_env_invalid_value() {
+        let result = resolve_provider(None, Some("anthropic".to_string()));
+        assert!(result.is_err());
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "Unknown provider 'anthropic' in RS_GIT_MSG_PROVIDER (possible values: auto, ollama, openai, gemini, llamacpp, command)"
+        );
+    }
+
+    #[test]
+    fn test_model_env_fallback() {
+        let model = cli_or_env(None, Some("llama3".to_string()));
+        assert_eq!(model, Some("llama3".to_string()));
+
+        let model = cli_or_env(Some("mistral".to_string()), Some("llama3".to_string()));
+        assert_eq!(model, Some("mistral".to_string()));
+
+        // Blank environment values are ignored
+        assert_eq!(cli_or_env(None, Some(String::new())), None);
+    }
+
+    #[test]
+    fn test_api_url_env_fallback() {
+        let args = Args::parse_from(["program"]);
+        let api_url = cli_or_env(args.api_url, Some("http://gpu-box:11434".to_string()));
+        assert_eq!(api_url, Some("http://gpu-box:11434".to_string()));
+
+        let args = Args::parse_from(["program", "--api-url", "http://localhost:8080"]);
+        let api_url = cli_or_env(args.api_url, Some("http://gpu-box:11434".to_string()));
+        assert_eq!(api_url, Some("http://localhost:8080".to_string()));
+    }
+
+    #[test]
+    fn test_api_key_env_fallback() {
+        let args = Args::parse_from(["program"]);
+        let api_key = cli_or_env(args.api_key, Some("env-key".to_string()));
+        assert_eq!(api_key, Some("env-key".to_string()));
+
+        let args = Args::parse_from(["program", "--api-key", "cli-key"]);
+        let api_key = cli_or_env(args.api_key, Some("env-key".to_string()));
+        assert_eq!(api_key, Some("cli-key".to_string()));
+    }
+
+    #[test]
+    fn test_repo_path_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.repo_path, None);
+
+        let args = Args::parse_from(["program", "--repo-path", "../other"]);
+        assert_eq!(args.repo_path, Some(PathBuf::from("../other")));
+    }
+
+    #[test]
+    fn test_add_all_flags() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.add_all && !args.add_all_untracked);
+
+        let args = Args::parse_from(["program", "-a"]);
+        assert!(args.add_all && !args.add_all_untracked);
+
+        let args = Args::parse_from(["program", "--add-all-untracked"]);
+        assert!(args.add_all_untracked);
+
+        assert!(Args::try_parse_from(["program", "-a", "--from-commit-msg-file", "MSG"]).is_err());
+    }
+
+    #[test]
+    fn test_open_repository_at_path() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "hello\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("notes.txt")).unwrap();
+        index.write().unwrap();
+
+        let repo = open_repository(Some(dir.path()), false).unwrap();
+        let staged = repo.get_staged_diff(git::DiffAlg::Default).unwrap();
+        assert_eq!(staged.paths, vec!["notes.txt"]);
+        assert!(staged.text.contains("+hello"));
+    }
+
+    #[tokio::test]
+    async fn test_lockfile_only_needs_no_provider() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        std::fs::write(dir.path().join("Cargo.lock"), "version = 4\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("Cargo.lock")).unwrap();
+        index.write().unwrap();
+        let output = dir.path().join("message.txt");
+
+        // OpenAI can't be created without an API key, and a key file that
+        // can't be read fails as soon as it is looked at
+        let missing_key = dir.path().join("missing-key");
+        let args = Args::parse_from([
+            "program",
+            "-p",
+            "openai",
+            "--api-key-file",
+            missing_key.to_str().unwrap(),
+            "--repo-path",
+            dir.path().to_str().unwrap(),
+            "--output-file",
+            output.to_str().unwrap(),
+        ]);
+        run(args).await.unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&output).unwrap(),
+            format!("{LOCKFILE_MESSAGE}\n")
+        );
+    }
+
+    #[test]
+    fn test_whitespace_only_staged_change() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.allow_whitespace);
+        let args = Args::parse_from(["program", "--allow-whitespace"]);
+        assert!(args.allow_whitespace);
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let git_repo = git2::Repository::init(dir.path()).unwrap();
+        std::fs::write(dir.path().join("main.rs"), "fn main() {\n  run();\n}\n").unwrap();
+        let mut index = git_repo.index().unwrap();
+        index.add_path(Path::new("main.rs")).unwrap();
+        let tree = git_repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        git_repo
+            .commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+
+        // Reindenting and adding a blank line is whitespace-only
+        std::fs::write(dir.path().join("main.rs"), "fn main() {\n    run();\n\n}\n").unwrap();
+        index.add_path(Path::new("main.rs")).unwrap();
+        index.write().unwrap();
+        let repo = open_repository(Some(dir.path()), false).unwrap();
+        let staged = repo.get_staged_diff(git::DiffAlg::Default).unwrap();
+        assert!(commit::is_whitespace_only(&staged.text));
+
+        std::fs::write(dir.path().join("main.rs"), "fn main() {\n    start();\n}\n").unwrap();
+        index.add_path(Path::new("main.rs")).unwrap();
+        index.write().unwrap();
+        let staged = repo.get_staged_diff(git::DiffAlg::Default).unwrap();
+        assert!(!commit::is_whitespace_only(&staged.text));
+    }
+
+    #[test]
+    fn test_regen_command() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.command, None);
+
+        let args = Args::parse_from(["program", "-n", "3", "regen", "--apply", "--force"]);
+        assert_eq!(
+            args.command,
+            Some(Command::Regen {
+                apply: true,
+                force: true
+            })
+        );
+        assert!(check_regen_conflicts(&args).is_ok());
+
+        assert!(Args::try_parse_from(["program", "--apply"]).is_err());
+        assert!(Args::try_parse_from(["program", "regen", "--force"]).is_err());
+        for flags in [
+            &["-a", "regen"][..],
+            &["--from-commit-msg-file", "MSG", "regen"],
+            &["--commit", "regen"],
+            &["--fallback-message", "chore: update", "regen", "--apply"],
+        ] {
+            let args = Args::parse_from(std::iter::once(&"program").chain(flags));
+            assert!(check_regen_conflicts(&args).is_err(), "{flags:?}");
+        }
+        // Regenerating only prints the message, so a placeholder is fine
+        let args = Args::parse_from(["program", "--fallback-message", "chore: update", "regen"]);
+        assert!(check_regen_conflicts(&args).is_ok());
+    }
+
+    #[test]
+    fn test_confirm_rewrite_pushed() {
+        let remotes = vec!["origin/main".to_string()];
+        assert!(confirm_rewrite_pushed(&remotes, true, "y\n".as_bytes()).unwrap());
+        assert!(!confirm_rewrite_pushed(&remotes, true, "\n".as_bytes()).unwrap());
+
+        // Nobody to ask: refuse, naming the way around it
+        let err = confirm_rewrite_pushed(&remotes, false, "y\n".as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("origin/main"));
+        assert!(err.to_string().contains("--force"));
+    }
+
+    #[test]
+    fn test_commit_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.commit);
+
+        let args = Args::parse_from(["program", "--commit", "--edit"]);
+        assert!(args.commit);
+
+        assert!(Args::try_parse_from(["program", "--commit", "--check-only"]).is_err());
+    }
+
+    #[test]
+    fn test_open_repository_errors() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let err = open_repository(Some(dir.path()), false).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "--repo-path {} is not a git repository",
+                dir.path().display()
+            )
+        );
+
+        let missing = dir.path().join("missing");
+        let err = open_repository(Some(&missing), false).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            format!("--repo-path {} is not a directory", missing.display())
+        );
+    }
+
+    #[test]
+    fn test_api_key_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"  file-key\n").unwrap();
+
+        let args = Args::parse_from(["program", "--api-key-file", file.path().to_str().unwrap()]);
+        let api_key = resolve_api_key(args.api_key, args.api_key_file.as_deref(), None).unwrap();
+        assert_eq!(api_key, Some("file-key".to_string()));
+    }
+
+    #[test]
+    fn test_pipe_behavior_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.pipe_behavior, PipeBehavior::First);
+
+        let args = Args::parse_from(["program", "--pipe-behavior", "all"]);
+        assert_eq!(args.pipe_behavior, PipeBehavior::All);
+
+        assert!(Args::try_parse_from(["program", "--pipe-behavior", "last"]).is_err());
+    }
+
+    #[test]
+    fn test_show_flag() {
+        let args = Args::parse_from(["program", "-n", "5"]);
+        assert_eq!(args.show, None);
+
+        let args = Args::parse_from(["program", "-n", "5", "--show", "3"]);
+        assert_eq!(args.show, Some(3));
+
+        let args = Args::parse_from(["program", "--max-candidates-shown", "2"]);
+        assert_eq!(args.show, Some(2));
+
+        assert!(Args::try_parse_from(["program", "--show", "0"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_limit_shown_after_generation() {
+        let generator = CommitMessageGenerator::new(MockProvider::new(
+            "1. feat: add login\n2. feat: support login\n3. feat: allow signing in\n4. feat: introduce login\n5. feat: implement login flow",
+        ));
+        let candidates = generator
+            .generate("diff", "main", 5, None, &[], false)
+            .await
+            .unwrap();
+        assert_eq!(candidates.len(), 5);
+
+        let shown = limit_shown(candidates.clone(), Some(2));
+        assert_eq!(shown, vec!["feat: add login", "feat: support login"]);
+
+        // Without --show every message is kept, and the cap never adds any
+        assert_eq!(limit_shown(candidates.clone(), None).len(), 5);
+        assert_eq!(limit_shown(candidates, Some(5)).len(), 5);
+    }
+
+    #[test]
+    fn test_select_piped_messages() {
+        let messages = vec![
+            "feat: add login".to_string(),
+            "feat: support login".to_string(),
+        ];
+
+        // Piped output keeps only the first candidate by default
+        let printed = select_piped_messages(messages.clone(), false, PipeBehavior::First).unwrap();
+        assert_eq!(printed, vec!["feat: add login"]);
+
+        let printed = select_piped_messages(messages.clone(), false, PipeBehavior::All).unwrap();
+        assert_eq!(printed, messages);
+
+        let err = select_piped_messages(messages.clone(), false, PipeBehavior::Error).unwrap_err();
+        assert!(err.to_string().starts_with("2 messages were generated"));
+
+        // A terminal, or a single message, is never changed
+        let printed = select_piped_messages(messages.clone(), true, PipeBehavior::Error).unwrap();
+        assert_eq!(printed, messages);
+        let single = vec!["fix: typo".to_string()];
+        let printed = select_piped_messages(single.clone(), false, PipeBehavior::Error).unwrap();
+        assert_eq!(printed, single);
+    }
+
+    #[test]
+    fn test_confirm_large_flags() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.confirm_large);
+        assert_eq!(args.large_threshold, 20);
+        assert!(!args.strict);
+
+        let args = Args::parse_from([
+            "program",
+            "--confirm-large",
+            "--large-threshold",
+            "5",
+            "--strict",
+        ]);
+        assert!(args.confirm_large);
+        assert_eq!(args.large_threshold, 5);
+        assert!(args.strict);
+
+        assert!(Args::try_parse_from(["program", "--strict"]).is_err());
+    }
+
+    #[test]
+    fn test_large_change_reasons() {
+        let paths =
+            |paths: &[&str]| -> Vec<String> { paths.iter().map(|path| path.to_string()).collect() };
+
+        assert!(large_change_reasons(&paths(&["src/main.rs", "README.md"]), 2).is_empty());
+        assert_eq!(
+            large_change_reasons(&paths(&["a.rs", "b.rs", "c.rs"]), 2),
+            vec!["3 files are staged (more than 2)"]
+        );
+        assert_eq!(
+            large_change_reasons(
+                &paths(&[
+                    "db/migrations/0001_init.sql",
+                    ".env.production",
+                    ".github/workflows/ci.yml",
+                    "src/migrate/mod.rs",
+                ]),
+                20
+            ),
+            vec![
+                "db/migrations/0001_init.sql is staged",
+                ".env.production is staged",
+                ".github/workflows/ci.yml is staged",
+                "src/migrate/mod.rs is staged",
+            ]
+        );
+        // Only whole path components count
+        assert!(
+            large_change_reasons(&paths(&["src/environment.rs", "docs/migrations.md"]), 20)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_confirm_large_change() {
+        let reasons = vec!["db/migrations/0001_init.sql is staged".to_string()];
+
+        assert!(confirm_large_change(&reasons, true, false, "y\n".as_bytes()).unwrap());
+        assert!(confirm_large_change(&reasons, true, false, "Yes\n".as_bytes()).unwrap());
+        assert!(!confirm_large_change(&reasons, true, false, "\n".as_bytes()).unwrap());
+        assert!(!confirm_large_change(&reasons, true, true, "".as_bytes()).unwrap());
+
+        // Nobody to ask: go on, unless strict
+        assert!(confirm_large_change(&reasons, false, false, "".as_bytes()).unwrap());
+        let err = confirm_large_change(&reasons, false, true, "".as_bytes()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Refusing to continue without confirmation: db/migrations/0001_init.sql is staged"
+        );
+    }
+
+    #[test]
+    fn test_parse_pick() {
+        assert_eq!(parse_pick("2\n", 3), Some(PickAction::Choose(1)));
+        assert_eq!(parse_pick("r", 3), Some(PickAction::Regenerate));
+        assert_eq!(parse_pick("Q\n", 3), Some(PickAction::Quit));
+        assert_eq!(parse_pick("e", 3), Some(PickAction::Edit(0)));
+        assert_eq!(parse_pick("e 3", 3), Some(PickAction::Edit(2)));
+        assert_eq!(parse_pick("e3", 3), Some(PickAction::Edit(2)));
+
+        // Out of range or unknown answers are asked again
+        assert_eq!(parse_pick("0", 3), None);
+        assert_eq!(parse_pick("4", 3), None);
+        assert_eq!(parse_pick("e 4", 3), None);
+        assert_eq!(parse_pick("", 3), None);
+        assert_eq!(parse_pick("x", 3), None);
+        assert_eq!(parse_pick("e", 0), None);
+    }
+
+    #[test]
+    fn test_prompt_pick() {
+        let candidates: Vec<Candidate> = ["feat: add login", "fix: close pool"]
+            .into_iter()
+            .map(|message| Candidate {
+                message: message.to_string(),
+                rationale: None,
+            })
+            .collect();
+
+        // Asks again until the answer is valid
+        assert_eq!(
+            prompt_pick(&candidates, "9\nnope\n2\n".as_bytes()).unwrap(),
+            PickAction::Choose(1)
+        );
+        assert_eq!(
+            prompt_pick(&candidates, "r\n".as_bytes()).unwrap(),
+            PickAction::Regenerate
+        );
+        assert_eq!(
+            prompt_pick(&candidates, "".as_bytes()).unwrap(),
+            PickAction::Quit
+        );
+    }
+
+    #[test]
+    fn test_pick_flag() {
+        assert!(!Args::parse_from(["program"]).pick);
+        assert!(Args::parse_from(["program", "--pick"]).pick);
+        assert!(Args::try_parse_from(["program", "--pick", "--edit"]).is_err());
+    }
+
+    #[test]
+    fn test_post_command_flags() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.post_command, None);
+        assert!(!args.post_rewrites);
+        assert_eq!(args.post_retries, 0);
+
+        let args = Args::parse_from([
+            "program",
+            "--post-command",
+            "npx commitlint",
+            "--post-rewrites",
+            "--post-retries",
+            "2",
+        ]);
+        assert_eq!(args.post_command.as_deref(), Some("npx commitlint"));
+        assert!(args.post_rewrites);
+        assert_eq!(args.post_retries, 2);
+
+        assert!(Args::try_parse_from(["program", "--post-rewrites"]).is_err());
+    }
+
+    #[cfg(unix)]
+    fn candidates(messages: &[&str]) -> Vec<Candidate> {
+        messages
+            .iter()
+            .map(|message| Candidate {
+                message: message.to_string(),
+                rationale: None,
+            })
+            .collect()
+    }
+
+    /// Writes a shell script fixture and returns the command that runs it
+    #[cfg(unix)]
+    fn script(dir: &tempfile::TempDir, name: &str, body: &str) -> String {
+        let path = dir.path().join(name);
+        std::fs::write(&path, format!("#!/bin/sh\n{body}")).unwrap();
+        format!("sh {}", path.display())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_post_command_rewrites() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let uppercase = script(&dir, "upper.sh", "tr a-z A-Z\n");
+
+        let (passed, rejections) =
+            apply_post_command(&uppercase, candidates(&["feat: add login"]), true)
+                .await
+                .unwrap();
+        assert_eq!(passed, vec!["FEAT: ADD LOGIN"]);
+        assert!(rejections.is_empty());
+
+        // Without --post-rewrites the output is only a check
+        let (passed, _) = apply_post_command(&uppercase, candidates(&["feat: add login"]), false)
+            .await
+            .unwrap();
+        assert_eq!(passed, vec!["feat: add login"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_post_command_rejects() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let lint = script(
+            &dir,
+            "lint.sh",
+            "read subject\ncase \"$subject\" in\n  feat:*|fix:*) ;;\n  *) echo \"type must be feat or fix\" >&2; exit 1 ;;\nesac\n",
+        );
+
+        let (passed, rejections) = apply_post_command(
+            &lint,
+            candidates(&["chore: bump deps", "fix: close pool"]),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(passed, vec!["fix: close pool"]);
+        assert_eq!(
+            rejections,
+            vec![(
+                "chore: bump deps".to_string(),
+                "type must be feat or fix".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_rejection_instructions() {
+        let rejections = vec![(
+            "chore: bump deps".to_string(),
+            "type must be feat or fix".to_string(),
+        )];
+        assert_eq!(
+            rejection_instructions(Some("Mention the ticket"), &rejections),
+            "Mention the ticket\n\nThese messages were rejected by the commit message linter; avoid the same problems:\n- \"chore: bump deps\": type must be feat or fix\n"
+        );
+        assert!(rejection_instructions(None, &rejections).starts_with("These messages"));
+    }
+
+    #[test]
+    fn test_fallback_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(args.fallback.is_empty());
+
+        let args = Args::parse_from(["program", "-p", "openai", "--fallback", "gemini,ollama"]);
+        assert_eq!(args.fallback, vec![Provider::Gemini, Provider::Ollama]);
+
+        assert!(Args::try_parse_from(["program", "--fallback", "claude"]).is_err());
+        assert!(
+            Args::try_parse_from(["program", "--fallback", "gemini", "--compare", "ollama"])
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_redacts_by_default() {
+        assert!(redacts_by_default(Provider::OpenAI, &[]));
+        assert!(!redacts_by_default(Provider::Ollama, &[]));
+        // The diff may end up with a cloud fallback
+        assert!(redacts_by_default(Provider::Ollama, &[Provider::Gemini]));
+        assert!(!redacts_by_default(Provider::Ollama, &[Provider::LlamaCpp]));
+    }
+
+    #[tokio::test]
+    async fn test_generate_with_fallback() {
+        let provider = FallbackProvider::new(vec![
+            FallbackEntry {
+                provider: "openai".to_string(),
+                model: "gpt-4o-mini".to_string(),
+                ai_provider: Box::new(MockProvider::new_with_auth_error("Invalid API key")),
+            },
+            FallbackEntry {
+                provider: "gemini".to_string(),
+                model: "gemini-2.0-flash-lite".to_string(),
+                ai_provider: Box::new(MockProvider::new("feat: add login")),
+            },
+        ]);
+        let generator = CommitMessageGenerator::new(provider);
+        let candidates = generator
+            .generate("diff", "main", 1, None, &[], false)
+            .await
+            .unwrap();
+        assert_eq!(candidates, vec!["feat: add login"]);
+    }
+
+    #[test]
+    fn test_compare_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(args.compare.is_empty());
+
+        let args = Args::parse_from(["program", "--compare", "ollama,openai,gemini"]);
+        assert_eq!(
+            args.compare,
+            vec![Provider::Ollama, Provider::OpenAI, Provider::Gemini]
+        );
+
+        assert!(Args::try_parse_from(["program", "--compare", "ollama,claude"]).is_err());
+    }
+
+    #[test]
+    fn test_compared_credentials() {
+        let var = |name: &str| (name == "GEMINI_API_KEY").then(|| "gemini-key".to_string());
+
+        // The main provider keeps the key and URL it was given
+        assert_eq!(
+            compared_credentials(
+                Provider::OpenAI,
+                Provider::OpenAI,
+                Some("openai-key"),
+                Some("http://proxy"),
+                var
+            ),
+            (
+                Some("openai-key".to_string()),
+                Some("http://proxy".to_string())
+            )
+        );
+        // Others only get their own key, and no key at all if it isn't set
+        assert_eq!(
+            compared_credentials(
+                Provider::Gemini,
+                Provider::OpenAI,
+                Some("openai-key"),
+                Some("http://proxy"),
+                var
+            ),
+            (Some("gemini-key".to_string()), None)
+        );
+        assert_eq!(
+            compared_credentials(
+                Provider::OpenAI,
+                Provider::Gemini,
+                Some("gemini-key"),
+                None,
+                |_| None
+            ),
+            (None, None)
+        );
+
+        // Without a key a compared provider can't be created, so it's skipped
+        assert!(
+            create_provider(
+                Provider::Gemini,
+                "gemini-2.0-flash-lite",
+                None,
+                None,
+                false,
+                &ProviderOptions::default()
+            )
+            .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compare_generators() {
+        let generators = vec![
+            (
+                Provider::OpenAI,
+                "large".to_string(),
+                CommitMessageGenerator::new(
+                    MockProvider::new("feat(auth): add login with session cookies")
+                        .with_delay(Duration::from_millis(50)),
+                ),
+            ),
+            (
+                Provider::Ollama,
+                "small".to_string(),
+                CommitMessageGenerator::new(MockProvider::new("feat: add login")),
+            ),
+        ];
+
+        let results = compare_generators(&generators, "diff", "main", 1, None, &[]).await;
+        assert!(results[0].elapsed >= Duration::from_millis(50));
+
+        let output = format_comparison(&results);
+        let slow = output.find("=== OpenAI (large) [").unwrap();
+        let fast = output.find("=== Ollama (small) [").unwrap();
+        // Sections keep the listed order, whichever finishes first
+        assert!(slow < fast);
+        assert!(output[slow..fast].contains("\nfeat(auth): add login with session cookies\n"));
+        assert!(output[fast..].contains("\nfeat: add login\n"));
+    }
+
+    #[test]
+    fn test_format_comparison_error() {
+        let results = vec![Comparison {
+            provider: Provider::Gemini,
+            model: "gemini-2.0-flash-lite".to_string(),
+            elapsed: Duration::from_millis(1250),
+            result: Err(anyhow!("quota exceeded")),
+        }];
+        assert_eq!(
+            format_comparison(&results),
+            "=== Gemini (gemini-2.0-flash-lite) [1.25s] ===\nError: quota exceeded\n\n"
+        );
+    }
+
+    #[test]
+    fn test_comparison_json() {
+        let results = vec![
+            Comparison {
+                provider: Provider::Ollama,
+                model: "llama3".to_string(),
+                elapsed: Duration::from_millis(800),
+                result: Ok(vec![Candidate {
+                    message: "feat: add login".to_string(),
+                    rationale: None,
+                }]),
+            },
+            Comparison {
+                provider: Provider::Gemini,
+                model: "gemini-2.0-flash-lite".to_string(),
+                elapsed: Duration::from_millis(1250),
+                result: Err(error::Error::auth("Gemini", "API key not valid").into()),
+            },
+        ];
+        assert_eq!(
+            comparison_json(&results),
+            json!({"results": [
+                {
+                    "provider": "ollama",
+                    "model": "llama3",
+                    "elapsed_ms": 800,
+                    "messages": [{"message": "feat: add login"}],
+                },
+                {
+                    "provider": "gemini",
+                    "model": "gemini-2.0-flash-lite",
+                    "elapsed_ms": 1250,
+                    "error": "API key not valid",
+                    "kind": "provider_auth",
+                },
+            ]})
+        );
+    }
+
+    #[test]
+    fn test_escalate_model_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.escalate_model, None);
+
+        let args = Args::parse_from(["program", "--escalate-model", "smart"]);
+        assert_eq!(args.escalate_model.as_deref(), Some("smart"));
+    }
+
+    #[test]
+    fn test_dump_prompt_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.dump_prompt, None);
+
+        let args = Args::parse_from(["program", "--dump-prompt", "prompt.txt"]);
+        assert_eq!(args.dump_prompt, Some(PathBuf::from("prompt.txt")));
+    }
+
+    #[test]
+    fn test_prompt_options() {
+        let args = Args::parse_from([
+            "program",
+            "-n",
+            "3",
+            "--chunked",
+            "--max-prompt-tokens",
+            "4000",
+        ]);
+        assert_eq!(
+            prompt_options(Provider::OpenAI, &args),
+            "number=3 diff-algorithm=Default redact-secrets=true chunked=true explain=false max-prompt-tokens=4000"
+        );
+
+        let args = Args::parse_from(["program", "--redact-secrets", "false"]);
+        assert_eq!(
+            prompt_options(Provider::Ollama, &args),
+            "number=1 diff-algorithm=Default redact-secrets=false chunked=false explain=false max-prompt-tokens=none"
+        );
+    }
+
+    #[test]
+    fn test_format_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.format, OutputFormat::Text);
+
+        let args = Args::parse_from(["program", "--format", "json"]);
+        assert_eq!(args.format, OutputFormat::Json);
+    }
+
+    #[test]
+    fn test_json_pretty() {
+        let args = Args::parse_from(["program", "--format", "json"]);
+        assert!(!args.json_pretty);
+        let args = Args::parse_from(["program", "--format", "json", "--json-pretty"]);
+        assert!(args.json_pretty);
+
+        let candidates = vec![Candidate {
+            message: "feat: add login".to_string(),
+            rationale: None,
+        }];
+        let output = messages_json("openai", "gpt-4o-mini", &candidates);
+
+        let compact = render_json(&output, false);
+        assert!(!compact.contains('\n') && !compact.contains("  "));
+
+        let pretty = render_json(&output, true);
+        assert!(
+            pretty.contains("\n  \"messages\": [\n    {\n      \"message\": \"feat: add login\"")
+        );
+        let reparsed: serde_json::Value = serde_json::from_str(&pretty).unwrap();
+        assert_eq!(reparsed, output);
+    }
+
+    #[test]
+    fn test_auth_failure_json() {
+        let err = create_provider(
+            Provider::Gemini,
+            "gemini-2.0-flash-lite",
+            None,
+            None,
+            false,
+            &ProviderOptions::default(),
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error::to_json(&err.into()),
+            json!({
+                "error": "API key is required for Gemini",
+                "kind": "provider_auth",
+                "provider": "Gemini",
+            })
+        );
+    }
+
+    #[test]
+    fn test_messages_json() {
+        let candidates = vec![
+            Candidate {
+                message: "feat: add login".to_string(),
+                rationale: Some("Adds a user-facing capability".to_string()),
+            },
+            Candidate {
+                message: "feat(auth): add login".to_string(),
+                rationale: None,
+            },
+        ];
+        assert_eq!(
+            messages_json("openai", "gpt-4o-mini", &candidates).to_string(),
+            r#"{"messages":[{"message":"feat: add login","rationale":"Adds a user-facing capability"},{"message":"feat(auth): add login"}],"model":"gpt-4o-mini","provider":"openai"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_messages_json_names_fallback_provider() {
+        let provider = FallbackProvider::new(vec![
+            FallbackEntry {
+                provider: Provider::OpenAI.name(),
+                model: "gpt-4o-mini".to_string(),
+                ai_provider: Box::new(MockProvider::new_with_auth_error("Invalid API key")),
+            },
+            FallbackEntry {
+                provider: Provider::Gemini.name(),
+                model: "gemini-2.0-flash-lite".to_string(),
+                ai_provider: Box::new(MockProvider::new("feat: add login")),
+            },
+        ]);
+        let generator = CommitMessageGenerator::new(provider);
+        let candidates = generator
+            .generate("diff", "main", 1, None, &[], false)
+            .await
+            .unwrap();
+
+        let (provider, model) = generator.answered_by().unwrap();
+        let output = messages_json(&provider, &model, &candidates);
+        assert_eq!(output["provider"], "gemini");
+        assert_eq!(output["model"], "gemini-2.0-flash-lite");
+        assert_eq!(output["messages"][0]["message"], "feat: add login");
+
+        // Plain providers leave it to the configured names
+        let generator = CommitMessageGenerator::new(MockProvider::new("feat: add login"));
+        assert_eq!(generator.answered_by(), None);
+        assert_eq!(Provider::LlamaCpp.name(), "llamacpp");
+    }
+
+    #[test]
+    fn test_trim_diff_headers_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.trim_diff_headers);
+
+        let args = Args::parse_from(["program", "--trim-diff-headers"]);
+        assert!(args.trim_diff_headers);
+    }
+
+    #[test]
+    fn test_max_file_lines_flag() {
+        assert_eq!(Args::parse_from(["program"]).max_file_lines, None);
+        let args = Args::parse_from(["program", "--max-file-lines", "500"]);
+        assert_eq!(args.max_file_lines, Some(500));
+    }
+
+    #[test]
+    fn test_diff_wrapper_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.diff_wrapper, DiffWrapperArg::Markdown);
+
+        let args = Args::parse_from(["program", "--diff-wrapper", "xml"]);
+        assert_eq!(
+            commit::DiffWrapper::from(args.diff_wrapper),
+            commit::DiffWrapper::Xml
+        );
+
+        assert!(Args::try_parse_from(["program", "--diff-wrapper", "json"]).is_err());
+    }
+
+    #[test]
+    fn test_explain_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.explain);
+
+        let args = Args::parse_from(["program", "--explain"]);
+        assert!(args.explain);
+    }
+
+    #[test]
+    fn test_format_candidate() {
+        let candidate = Candidate {
+            message: "fix(db): close pool".to_string(),
+            rationale: Some("Fixes a leak in the db module".to_string()),
+        };
+        assert_eq!(
+            format_candidate(&candidate, false),
+            "fix(db): close pool\n# Fixes a leak in the db module"
+        );
+        assert_eq!(
+            format_candidate(&candidate, true),
+            "fix(db): close pool\n\x1b[2m# Fixes a leak in the db module\x1b[0m"
+        );
+
+        let candidate = Candidate {
+            rationale: None,
+            ..candidate
+        };
+        assert_eq!(format_candidate(&candidate, true), "fix(db): close pool");
+    }
+
+    #[test]
+    fn test_render_output_template() {
+        let message = "feat(auth)!: add login\n\nSupports SSO.";
+        assert_eq!(
+            render_output_template("{n}. {message}", 2, message),
+            "2. feat(auth)!: add login\n\nSupports SSO."
+        );
+        assert_eq!(
+            render_output_template("[{type}] {scope}: {subject}", 1, message),
+            "[feat] auth: add login"
+        );
+        assert_eq!(
+            render_output_template("git commit -m \"{subject}\" {unknown}", 1, message),
+            "git commit -m \"add login\" {unknown}"
+        );
+
+        // Placeholders in the message itself are left alone
+        assert_eq!(
+            render_output_template("{n}: {message}", 1, "fix: escape {n} in {message}"),
+            "1: fix: escape {n} in {message}"
+        );
+
+        // Messages without a conventional header
+        let message = "Update the readme\n\nMore detail.";
+        assert_eq!(
+            render_output_template("{type}|{scope}|{subject}", 1, message),
+            "||Update the readme"
+        );
+
+        let args = Args::parse_from(["program", "--output-template", "{n}. {message}"]);
+        assert_eq!(args.output_template.as_deref(), Some("{n}. {message}"));
+    }
+
+    #[test]
+    fn test_output_file_flags() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.output_file, None);
+        assert!(!args.append_to_file);
+
+        let args = Args::parse_from(["program", "--output-file", "draft.txt", "--append-to-file"]);
+        assert_eq!(args.output_file, Some(PathBuf::from("draft.txt")));
+        assert!(args.append_to_file);
+
+        // Appending needs a file to append to
+        assert!(Args::try_parse_from(["program", "--append-to-file"]).is_err());
+    }
+
+    #[test]
+    fn test_write_messages() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("draft.txt");
+        std::fs::write(&path, "old contents\n").unwrap();
+
+        let messages = vec![
+            "feat: add login".to_string(),
+            "fix: handle timeout".to_string(),
+        ];
+        write_messages(&path, &messages, false).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "feat: add login\nfix: handle timeout\n"
+        );
+
+        write_messages(&path, &["docs: update readme".to_string()], true).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "feat: add login\nfix: handle timeout\ndocs: update readme\n"
+        );
+    }
+
+    #[test]
+    fn test_write_messages_creates_file() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("new.txt");
+
+        write_messages(&path, &["chore: bump deps".to_string()], true).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "chore: bump deps\n"
+        );
+
+        let missing_dir = dir.path().join("missing").join("out.txt");
+        let err = write_messages(&missing_dir, &[], false).unwrap_err();
+        assert!(err.to_string().starts_with("Failed to open output file"));
+    }
+
+    #[test]
+    fn test_api_key_precedence() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut file, b"file-key").unwrap();
+        let env_key = Some("env-key".to_string());
+
+        // --api-key wins over the file and the environment
+        let api_key = resolve_api_key(
+            Some("cli-key".to_string()),
+            Some(file.path()),
+            env_key.clone(),
+        )
+        .unwrap();
+        assert_eq!(api_key, Some("cli-key".to_string()));
+
+        // The file wins over the environment
+        let api_key = resolve_api_key(None, Some(file.path()), env_key.clone()).unwrap();
+        assert_eq!(api_key, Some("file-key".to_string()));
+
+        // The environment is the last fallback
+        let api_key = resolve_api_key(None, None, env_key).unwrap();
+        assert_eq!(api_key, Some("env-key".to_string()));
+    }
+
+    #[test]
+    fn test_api_key_file_errors() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let missing = dir.path().join("missing.key");
+        let result = resolve_api_key(None, Some(&missing), None);
+        assert!(
+            result
+                .unwrap_err()
+                .to_string()
+                .starts_with("Failed to read API key file")
+        );
+
+        let empty = dir.path().join("empty.key");
+        std::fs::write(&empty, " \n").unwrap();
+        let result = resolve_api_key(None, Some(&empty), None);
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            format!("API key file {} is empty", empty.display())
+        );
+    }
+
+    #[test]
+    fn test_api_key_handling() {
+        // No API key
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.api_key, None);
+
+        // With API key
+        let args = Args::parse_from(["program", "--api-key", "test-key"]);
+        assert_eq!(args.api_key, Some("test-key".to_string()));
+    }
+
+    #[test]
+    fn test_verbose_flag() {
+        // Not verbose
+        let args = Args::parse_from(["program"]);
+        assert!(!args.verbose);
+
+        // Verbose short flag
+        let args = Args::parse_from(["program", "-v"]);
+        assert!(args.verbose);
+
+        // Verbose long flag
+        let args = Args::parse_from(["program", "--verbose"]);
+        assert!(args.verbose);
+    }
+
+    #[test]
+    fn test_diff_algorithm_parsing() {
+        // Default value
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.diff_alg, DiffAlgArg::Default);
+
+        let cases = [
+            ("default", DiffAlgArg::Default),
+            ("patience", DiffAlgArg::Patience),
+            ("minimal", DiffAlgArg::Minimal),
+            ("histogram", DiffAlgArg::Histogram),
+            ("word", DiffAlgArg::Word),
+            ("difftastic", DiffAlgArg::Difftastic),
+        ];
+        for (value, expected) in cases {
+            let args = Args::parse_from(["program", "--diff-algorithm", value]);
+            assert_eq!(args.diff_alg, expected);
+        }
+
+        // The old flag name is kept as an alias
+        let args = Args::parse_from(["program", "--diff-alg", "histogram"]);
+        assert_eq!(args.diff_alg, DiffAlgArg::Histogram);
+
+        // Unknown algorithms are rejected
+        assert!(Args::try_parse_from(["program", "--diff-algorithm", "myers"]).is_err());
+    }
+
+    #[test]
+    fn test_style_examples_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.style_examples, 0);
+
+        let args = Args::parse_from(["program", "--style-examples", "5"]);
+        assert_eq!(args.style_examples, 5);
+    }
+
+    #[test]
+    fn test_type_history_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.type_history, 50);
+
+        let args = Args::parse_from(["program", "--type-history", "0"]);
+        assert_eq!(args.type_history, 0);
+    }
+
+    #[test]
+    fn test_diversify_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.diversify);
+
+        let args = Args::parse_from(["program", "-n", "3", "--diversify"]);
+        assert!(args.diversify);
+    }
+
+    #[test]
+    fn test_top_up_flag() {
+        assert!(!Args::parse_from(["program"]).no_top_up);
+        assert!(Args::parse_from(["program", "-n", "3", "--no-top-up"]).no_top_up);
+    }
+
+    #[test]
+    fn test_concurrency_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.concurrency, 3);
+
+        let args = Args::parse_from(["program", "--concurrency", "5"]);
+        assert_eq!(args.concurrency, 5);
+
+        assert!(Args::try_parse_from(["program", "--concurrency", "0"]).is_err());
+
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.rpm, None);
+        let args = Args::parse_from(["program", "--rpm", "20"]);
+        assert_eq!(args.rpm, Some(20));
+        assert!(Args::try_parse_from(["program", "--rpm", "0"]).is_err());
+
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.retry_budget, 5);
+
+        let args = Args::parse_from(["program", "--retry-budget", "0"]);
+        assert_eq!(args.retry_budget, 0);
+    }
+
+    #[test]
+    fn test_types_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(args.types.is_empty());
+
+        let args = Args::parse_from(["program", "--types", "feat,fix,chore"]);
+        assert_eq!(args.types, vec!["feat", "fix", "chore"]);
+    }
+
+    #[test]
+    fn test_list_marker_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(args.list_marker.is_empty());
+
+        let args = Args::parse_from([
+            "program",
+            "--list-marker",
+            r"\[\d+\]",
+            "--list-marker",
+            "=>",
+        ]);
+        let patterns: Vec<&str> = args.list_marker.iter().map(Regex::as_str).collect();
+        assert_eq!(patterns, vec![r"^(?:\[\d+\])\s*", r"^(?:=>)\s*"]);
+
+        assert!(Args::try_parse_from(["program", "--list-marker", "("]).is_err());
+    }
+
+    #[test]
+    fn test_resolve_commit_types() {
+        assert_eq!(
+            resolve_commit_types(vec!["feat".into(), " fix".into()], Some("docs".into()), &[]),
+            vec!["feat", "fix"]
+        );
+        assert_eq!(
+            resolve_commit_types(Vec::new(), Some("feat, chore,".into()), &[]),
+            vec!["feat", "chore"]
+        );
+        assert!(resolve_commit_types(Vec::new(), None, &[]).is_empty());
+
+        // The config file's types, unless overridden
+        let config_types = vec!["feat".to_string(), "fix".to_string()];
+        assert_eq!(
+            resolve_commit_types(Vec::new(), None, &config_types),
+            vec!["feat", "fix"]
+        );
+        assert_eq!(
+            resolve_commit_types(Vec::new(), Some("chore".into()), &config_types),
+            vec!["chore"]
+        );
+        assert_eq!(
+            resolve_commit_types(vec!["docs".into()], None, &config_types),
+            vec!["docs"]
+        );
+    }
+
+    #[test]
+    fn test_template_scope_flags() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.template_scope, None);
+        assert_eq!(args.package_root, "packages");
+        assert_eq!(args.package_depth, 1);
+
+        let args = Args::parse_from([
+            "program",
+            "--template-scope",
+            "web",
+            "--package-root",
+            "libs",
+            "--package-depth",
+            "2",
+        ]);
+        assert_eq!(args.template_scope.as_deref(), Some("web"));
+        assert_eq!(args.package_root, "libs");
+        assert_eq!(args.package_depth, 2);
+    }
+
+    #[test]
+    fn test_print_usage_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.print_usage);
+
+        let args = Args::parse_from(["program", "--print-usage"]);
+        assert!(args.print_usage);
+    }
+
+    #[test]
+    fn test_redact_secrets_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.redact_secrets, None);
+
+        let args = Args::parse_from(["program", "--redact-secrets"]);
+        assert_eq!(args.redact_secrets, Some(true));
+
+        let args = Args::parse_from(["program", "--redact-secrets=false"]);
+        assert_eq!(args.redact_secrets, Some(false));
+    }
+
+    #[test]
+    fn test_context_commits_from_pr_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.context_commits_from_pr);
+
+        let args = Args::parse_from(["program", "--context-commits-from-pr"]);
+        assert!(args.context_commits_from_pr);
+    }
+
+    #[test]
+    fn test_use_branch_description_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(args.use_branch_description);
+
+        let args = Args::parse_from(["program", "--use-branch-description"]);
+        assert!(args.use_branch_description);
+
+        let args = Args::parse_from(["program", "--use-branch-description=false"]);
+        assert!(!args.use_branch_description);
+    }
+
+    #[test]
+    fn test_provider_is_cloud() {
+        assert!(Provider::OpenAI.is_cloud());
+        assert!(Provider::Gemini.is_cloud());
+        assert!(!Provider::Ollama.is_cloud());
+        assert!(!Provider::LlamaCpp.is_cloud());
+    }
+
+    #[test]
+    fn test_chunked_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.chunked);
+
+        let args = Args::parse_from(["program", "--chunked"]);
+        assert!(args.chunked);
+    }
+
+    #[test]
+    fn test_per_file_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.per_file);
+
+        let args = Args::parse_from(["program", "--split"]);
+        assert!(args.per_file);
+
+        assert!(Args::try_parse_from(["program", "--per-file", "--chunked"]).is_err());
+    }
+
+    #[test]
+    fn test_format_per_file() {
+        let suggestions = vec![
+            (
+                "src/auth.rs".to_string(),
+                "feat(auth): use session store".to_string(),
+            ),
+            (
+                "README.md".to_string(),
+                "docs: describe login\n\nCovers the new flow.".to_string(),
+            ),
+        ];
+        assert_eq!(
+            format_per_file(&suggestions),
+            vec![
+                "src/auth.rs: feat(auth): use session store",
+                "README.md: docs: describe login\n\n    Covers the new flow.",
+            ]
+        );
+        assert_eq!(
+            per_file_json("ollama", "qwen2.5-coder", &suggestions[..1]).to_string(),
+            r#"{"files":[{"message":"feat(auth): use session store","path":"src/auth.rs"}],"model":"qwen2.5-coder","provider":"ollama"}"#
+        );
+    }
+
+    #[test]
+    fn test_ollama_flags() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.ollama_keep_alive, None);
+        assert!(!args.ollama_preload);
+
+        let args = Args::parse_from(["program", "--ollama-keep-alive", "10m", "--ollama-preload"]);
+        assert_eq!(args.ollama_keep_alive.as_deref(), Some("10m"));
+        assert!(args.ollama_preload);
+    }
+
+    #[test]
+    fn test_json_mode_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.json_mode);
+
+        let args = Args::parse_from(["program", "--json-mode"]);
+        assert!(args.json_mode);
+    }
+
+    #[test]
+    fn test_no_stream_flag() {
+        assert!(!Args::parse_from(["program"]).no_stream);
+        assert!(Args::parse_from(["program", "--no-stream"]).no_stream);
+    }
+
+    #[test]
+    fn test_diff_filter_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.diff_filter, None);
+
+        let args = Args::parse_from(["program", "--diff-filter", "AM"]);
+        assert_eq!(
+            args.diff_filter,
+            Some(git::DiffFilter::parse("AM").unwrap())
+        );
+
+        assert!(Args::try_parse_from(["program", "--diff-filter", "Q"]).is_err());
+    }
+
+    #[test]
+    fn test_difft_timeout_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.difft_timeout, 10);
+
+        let args = Args::parse_from(["program", "--difft-timeout", "30"]);
+        assert_eq!(args.difft_timeout, 30);
+    }
+
+    #[test]
+    fn test_strip_emoji() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.strip_emoji);
+        assert_eq!(
+            finish_message("✨ feat: add X", &args, &[]),
+            "✨ feat: add X"
+        );
+
+        let args = Args::parse_from(["program", "--strip-emoji"]);
+        assert_eq!(finish_message("✨ feat: add X", &args, &[]), "feat: add X");
+        assert_eq!(
+            finish_message("🐛 fix: close pool", &args, &[]),
+            "fix: close pool"
+        );
+        assert_eq!(
+            finish_message("feat: add 🎉 confetti", &args, &[]),
+            "feat: add 🎉 confetti"
+        );
+    }
+
+    #[test]
+    fn test_allow_trailing_period() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.allow_trailing_period);
+        assert_eq!(
+            finish_message("fix: close the pool.\n\nIt leaked.", &args, &[]),
+            "fix: close the pool\n\nIt leaked."
+        );
+
+        let args = Args::parse_from(["program", "--allow-trailing-period"]);
+        assert!(args.allow_trailing_period);
+        assert_eq!(
+            finish_message("fix: close the pool.", &args, &[]),
+            "fix: close the pool."
+        );
+    }
+
+    #[test]
+    fn test_add_ticket() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.add_ticket);
+
+        let args = Args::parse_from(["program", "--add-ticket", "--co-author", "A <a@b.c>"]);
+        assert!(args.add_ticket);
+        assert_eq!(
+            finish_message("feat: add login", &args, &["JIRA-123".to_string()]),
+            "feat: add login\n\nRefs: JIRA-123\nCo-authored-by: A <a@b.c>"
+        );
+        assert!(Args::try_parse_from(["program", "--add-ticket", "--only-subject"]).is_err());
+    }
+
+    #[test]
+    fn test_seed_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.seed, None);
+
+        let args = Args::parse_from(["program", "--seed", "42"]);
+        assert_eq!(args.seed, Some(42));
+
+        assert!(Args::try_parse_from(["program", "--seed", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_max_prompt_tokens_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.max_prompt_tokens, None);
+
+        let args = Args::parse_from(["program", "--max-prompt-tokens", "4000"]);
+        assert_eq!(args.max_prompt_tokens, Some(4000));
+    }
+
+    #[test]
+    fn test_no_diff_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.no_diff);
+
+        let args = Args::parse_from(["program", "--no-diff"]);
+        assert!(args.no_diff);
+
+        assert!(Args::try_parse_from(["program", "--no-diff", "--chunked"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_no_recent_commits() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let git_repo = git2::Repository::init(dir.path()).unwrap();
+        let tree = git_repo
+            .find_tree(git_repo.index().unwrap().write_tree().unwrap())
+            .unwrap();
+        let signature = git2::Signature::now("Test", "test@example.com").unwrap();
+        git_repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "feat: start the secret project",
+                &tree,
+                &[],
+            )
+            .unwrap();
+        let repo = open_repository(Some(dir.path()), false).unwrap();
+
+        let prompt_path = dir.path().join("prompt.txt");
+        let prompt_for = |args: &[&str]| {
+            let titles = recent_commit_titles(&repo, &Args::parse_from(args));
+            let generator = CommitMessageGenerator::new(MockProvider::new("feat: add files"))
+                .with_prompt_dump(Some(prompt_path.clone()), String::new());
+            let prompt_path = prompt_path.clone();
+            async move {
+                generator
+                    .generate("diff", "main", 1, None, &titles, false)
+                    .await
+                    .unwrap();
+                std::fs::read_to_string(prompt_path).unwrap()
+            }
+        };
+
+        let prompt = prompt_for(&["program"]).await;
+        assert!(prompt.contains("last few commit messages"));
+        assert!(prompt.contains("- feat: start the secret project\n"));
+
+        let prompt = prompt_for(&["program", "--no-recent-commits"]).await;
+        assert!(!prompt.contains("last few commit messages"));
+        assert!(!prompt.contains("secret project"));
+    }
+
+    #[tokio::test]
+    async fn test_no_diff_prompt_has_no_contents() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let repo = git2::Repository::init(dir.path()).unwrap();
+        std::fs::write(dir.path().join("secret.rs"), "let token = compute();\n").unwrap();
+        std::fs::write(dir.path().join("notes.md"), "internal roadmap\n").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("secret.rs")).unwrap();
+        index.add_path(Path::new("notes.md")).unwrap();
+        index.write().unwrap();
+
+        let staged = open_repository(Some(dir.path()), false)
+            .unwrap()
+            .get_staged_diff(git::DiffAlg::Default)
+            .unwrap();
+        let file_list = changed_file_list(&staged.paths, &staged.renames);
+
+        let prompt_path = dir.path().join("prompt.txt");
+        let generator = CommitMessageGenerator::new(MockProvider::new("feat: add files"))
+            .with_no_diff(true)
+            .with_prompt_dump(Some(prompt_path.clone()), String::new());
+        generator
+            .generate(&file_list, "main", 1, Some("Adds the parser"), &[], false)
+            .await
+            .unwrap();
+
+        let prompt = std::fs::read_to_string(prompt_path).unwrap();
+        assert!(prompt.contains("notes.md\nsecret.rs"));
+        assert!(prompt.contains("Additional context: Adds the parser"));
+        assert!(!prompt.contains("compute()"));
+        assert!(!prompt.contains("roadmap"));
+        assert!(!prompt.contains("@@"));
+        assert!(
+            !prompt
+                .lines()
+                .any(|line| line.starts_with('+') || line.starts_with("-let"))
+        );
+    }
+
+    #[test]
+    fn test_changed_file_list() {
+        let paths = vec!["src/new_name.rs".to_string(), "README.md".to_string()];
+        let renames = vec![("src/old_name.rs".to_string(), "src/new_name.rs".to_string())];
+        assert_eq!(
+            changed_file_list(&paths, &renames),
+            "src/old_name.rs -> src/new_name.rs\nREADME.md"
+        );
+    }
+
+    #[test]
+    fn test_show_diff_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.show_diff);
+
+        let args = Args::parse_from(["program", "--show-diff"]);
+        assert!(args.show_diff);
+
+        let args = Args::parse_from(["program", "--include-diff-in-output"]);
+        assert!(args.show_diff);
+    }
+
+    #[test]
+    fn test_scope_case_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.scope_case, ScopeCaseArg::Asis);
+
+        let args = Args::parse_from(["program", "--scope-case", "kebab"]);
+        assert_eq!(
+            commit::ScopeCase::from(args.scope_case),
+            commit::ScopeCase::Kebab
+        );
+
+        let args = Args::parse_from(["program", "--scope-case", "snake"]);
+        assert_eq!(args.scope_case, ScopeCaseArg::Snake);
+
+        assert!(Args::try_parse_from(["program", "--scope-case", "camel"]).is_err());
+    }
+
+    #[test]
+    fn test_subject_case_flag() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.subject_case, SubjectCaseArg::Asis);
+
+        let args = Args::parse_from(["program", "--subject-case", "lower"]);
+        assert_eq!(
+            commit::SubjectCase::from(args.subject_case),
+            commit::SubjectCase::Lower
+        );
+
+        let args = Args::parse_from(["program", "--subject-case", "sentence"]);
+        assert_eq!(args.subject_case, SubjectCaseArg::Sentence);
+
+        assert!(Args::try_parse_from(["program", "--subject-case", "upper"]).is_err());
+    }
+
+    #[test]
+    fn test_edit_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.edit);
+
+        let args = Args::parse_from(["program", "--edit"]);
+        assert!(args.edit);
+
+        assert!(Args::try_parse_from(["program", "--edit", "--compare", "ollama,openai"]).is_err());
+    }
+
+    #[test]
+    fn test_check_only_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.check_only);
+
+        let args = Args::parse_from([
+            "program",
+            "--check-only",
+            "--from-commit-msg-file",
+            ".git/COMMIT_EDITMSG",
+        ]);
+        assert!(args.check_only);
+
+        assert!(Args::try_parse_from(["program", "--check-only", "--edit"]).is_err());
+    }
+
+    #[test]
+    fn test_leave_hook_file() {
+        let path = Path::new(".git/COMMIT_EDITMSG");
+        let mut out = Vec::new();
+        leave_hook_file(Ok(()), path, &mut out);
+        assert!(out.is_empty());
+
+        leave_hook_file(
+            Err(anyhow::anyhow!("Failed to generate commit message")),
+            path,
+            &mut out,
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Warning: Failed to generate commit message; leaving .git/COMMIT_EDITMSG as it is\n"
+        );
+    }
+
+    #[test]
+    fn test_allowed_types_from_convention() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(
+            dir.path().join(".commitlintrc.json"),
+            r#"{"rules": {"type-enum": [2, "always", ["feat", "fix"]]}}"#,
+        )
+        .unwrap();
+        let convention = load_convention(Some(dir.path()), None).unwrap();
+
+        // What --check-only and --skip-if-valid check against
+        let commit_types = allowed_types(Vec::new(), &convention);
+        assert_eq!(commit_types, vec!["feat", "fix"]);
+        let mut out = Vec::new();
+        assert_eq!(
+            check_message("docs: update readme", &commit_types, None, &mut out).unwrap(),
+            EXIT_INVALID_MESSAGE
+        );
+
+        // --types wins over the config
+        let commit_types = allowed_types(vec!["docs".to_string()], &convention);
+        assert_eq!(
+            check_message("docs: update readme", &commit_types, None, &mut out).unwrap(),
+            0
+        );
+
+        // A config that can't be read only counts when it was asked for
+        std::fs::write(dir.path().join(".commitlintrc.json"), "extends: x").unwrap();
+        assert!(load_convention(Some(dir.path()), None).is_ok());
+        let path = dir.path().join(".commitlintrc.json");
+        assert!(load_convention(None, Some(&path)).is_err());
+    }
+
+    #[test]
+    fn test_check_message_exit_codes() {
+        let mut out = Vec::new();
+        assert_eq!(
+            check_message("feat(auth): add login", &[], None, &mut out).unwrap(),
+            0
+        );
+        assert!(out.is_empty());
+
+        let commit_types = vec!["feat".to_string(), "fix".to_string()];
+        assert_eq!(
+            check_message("docs: update readme", &commit_types, None, &mut out).unwrap(),
+            EXIT_INVALID_MESSAGE
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Invalid commit message:\n  - type \"docs\" is not one of: feat, fix\n"
+        );
+
+        let mut out = Vec::new();
+        assert_eq!(
+            check_message("feat: add café login 🎉", &[], Some(22), &mut out).unwrap(),
+            0
+        );
+        assert_eq!(
+            check_message("feat: add café login 🎉", &[], Some(21), &mut out).unwrap(),
+            EXIT_INVALID_MESSAGE
+        );
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "Invalid commit message:\n  - the subject is 22 characters long, more than 21\n"
+        );
+    }
+
+    #[test]
+    fn test_commit_msg_file_flags() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.from_commit_msg_file, None);
+        assert!(!args.overwrite);
+
+        let args = Args::parse_from([
+            "program",
+            "--from-commit-msg-file",
+            ".git/COMMIT_EDITMSG",
+            "--overwrite",
+        ]);
+        assert_eq!(
+            args.from_commit_msg_file,
+            Some(PathBuf::from(".git/COMMIT_EDITMSG"))
+        );
+        assert!(args.overwrite);
+        assert!(!args.skip_if_valid);
+
+        assert!(Args::try_parse_from(["program", "--overwrite"]).is_err());
+
+        let args = Args::parse_from([
+            "program",
+            "--from-commit-msg-file",
+            ".git/COMMIT_EDITMSG",
+            "--skip-if-valid",
+        ]);
+        assert!(args.skip_if_valid);
+        assert!(Args::try_parse_from(["program", "--skip-if-valid"]).is_err());
+    }
+
+    #[test]
+    fn test_wrap_flag() {
+        // Bodies are left as they are unless asked otherwise
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.wrap, 0);
+
+        let args = Args::parse_from(["program", "--wrap", "72"]);
+        assert_eq!(args.wrap, 72);
+
+        assert!(Args::try_parse_from(["program", "--wrap", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_ticket_to_footer_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.ticket_to_footer);
+
+        let args = Args::parse_from(["program", "--ticket-to-footer"]);
+        assert!(args.ticket_to_footer);
+
+        let args = Args::parse_from(["program", "--strip-ticket-from-subject"]);
+        assert!(args.ticket_to_footer);
+    }
+
+    #[test]
+    fn test_co_author_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(args.co_author.is_empty());
+
+        let args = Args::parse_from([
+            "program",
+            "--co-author",
+            "Ada Lovelace <ada@example.com>",
+            "--co-author",
+            "  Alan Turing<alan@example.com> ",
+        ]);
+        assert_eq!(
+            args.co_author,
+            vec![
+                "Ada Lovelace <ada@example.com>",
+                "Alan Turing <alan@example.com>"
+            ]
+        );
+
+        for invalid in [
+            "ada@example.com",
+            "Ada Lovelace",
+            "<ada@example.com>",
+            "Ada <ada.example.com>",
+            "Ada <ada@example.com> extra",
+        ] {
+            assert!(
+                Args::try_parse_from(["program", "--co-author", invalid]).is_err(),
+                "{invalid}"
+            );
+        }
+        assert!(
+            Args::try_parse_from([
+                "program",
+                "--co-author",
+                "Ada <ada@example.com>",
+                "--only-subject"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_only_subject_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.only_subject);
+
+        let args = Args::parse_from(["program", "--only-subject"]);
+        assert!(args.only_subject);
+    }
+
+    #[test]
+    fn test_prepend_append_parsing() {
+        let args = Args::parse_from(["program"]);
+        assert_eq!(args.prepend, None);
+        assert_eq!(args.append, None);
+
+        let args = Args::parse_from(["program", "--prepend", "[WIP]", "--append", "(#42)"]);
+        assert_eq!(args.prepend, Some("[WIP]".to_string()));
+        assert_eq!(args.append, Some("(#42)".to_string()));
+    }
+
+    #[test]
+    fn test_decorate_message_prepend() {
+        let message = decorate_message("feat(core): add thing", Some("[WIP]"), None);
+        assert_eq!(message, "[WIP] feat(core): add thing");
+
+        // Existing trailing whitespace is not doubled
+        let message = decorate_message("feat(core): add thing", Some("[WIP] "), None);
+        assert_eq!(message, "[WIP] feat(core): add thing");
+    }
+
+    #[test]
+    fn test_decorate_message_append() {
+        let message = decorate_message("fix(api): handle timeout", None, Some("(PROJ-123)"));
+        assert_eq!(message, "fix(api): handle timeout (PROJ-123)");
+
+        let message = decorate_message("fix(api): handle timeout", None, Some(" (PROJ-123)"));
+        assert_eq!(message, "fix(api): handle timeout (PROJ-123)");
+    }
+
+    #[test]
+    fn test_decorate_message_prepend_and_append() {
+        let message = decorate_message("docs: update readme", Some("[WIP]"), Some("#7"));
+        assert_eq!(message, "[WIP] docs: update readme #7");
+
+        // Empty values leave the message untouched
+        let message = decorate_message("docs: update readme", Some(""), Some(""));
+        assert_eq!(message, "docs: update readme");
     }
 
     #[test]
@@ -251,4 +4461,117 @@ mod tests {
         let cmd = Args::command();
         cmd.debug_assert();
     }
+
+    #[tokio::test]
+    async fn test_until_cancelled_stops_slow_generation() {
+        let mock_provider = MockProvider::new("feat: slow").with_delay(Duration::from_secs(30));
+        let started = mock_provider.max_in_flight.clone();
+        let generator = CommitMessageGenerator::new(mock_provider);
+
+        let generation = generator.generate("+change", "main", 1, None, &[], false);
+        let cancel = tokio::time::sleep(Duration::from_millis(20));
+        let result =
+            tokio::time::timeout(Duration::from_secs(5), until_cancelled(generation, cancel))
+                .await
+                .expect("cancellation should not wait for the provider");
+
+        assert!(result.is_none());
+        // The request was in flight when it was cancelled
+        assert_eq!(started.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_is_lockfile_only() {
+        let paths = |paths: &[&str]| paths.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+
+        assert!(is_lockfile_only(&paths(&["Cargo.lock"]), &[]));
+        assert!(is_lockfile_only(
+            &paths(&["web/package-lock.json", "yarn.lock"]),
+            &[]
+        ));
+
+        // Mixed with a manifest or code, the change needs describing
+        assert!(!is_lockfile_only(
+            &paths(&["Cargo.lock", "Cargo.toml"]),
+            &[]
+        ));
+        assert!(!is_lockfile_only(&paths(&["src/main.rs"]), &[]));
+        assert!(!is_lockfile_only(&paths(&["Cargo.lock.orig"]), &[]));
+        assert!(!is_lockfile_only(&[], &[]));
+
+        // A configured set replaces the default one
+        let lockfiles = vec!["deps.lock".to_string()];
+        assert!(is_lockfile_only(&paths(&["deps.lock"]), &lockfiles));
+        assert!(!is_lockfile_only(&paths(&["Cargo.lock"]), &lockfiles));
+    }
+
+    #[test]
+    fn test_include_intent_to_add_flag() {
+        let args = Args::parse_from(["program"]);
+        assert!(!args.include_intent_to_add);
+
+        let args = Args::parse_from(["program", "--include-intent-to-add"]);
+        assert!(args.include_intent_to_add);
+    }
+
+    #[test]
+    fn test_lockfile_flags() {
+        let args = Args::parse_from(["program"]);
+        assert!(args.lockfiles.is_empty() && !args.force_ai);
+
+        let args = Args::parse_from(["program", "--lockfiles", "deps.lock,go.sum", "--force-ai"]);
+        assert_eq!(args.lockfiles, vec!["deps.lock", "go.sum"]);
+        assert!(args.force_ai);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_message() {
+        let generator = CommitMessageGenerator::new(MockProvider::new_with_error("Test error"));
+
+        let generated = generator
+            .generate("+change", "main", 1, None, &[], false)
+            .await;
+        let messages = or_fallback_message(generated, Some("chore: update")).unwrap();
+        assert_eq!(messages, vec!["chore: update"]);
+
+        // Without one, the error stands
+        let generated = generator
+            .generate("+change", "main", 1, None, &[], false)
+            .await;
+        assert!(or_fallback_message(generated, None).is_err());
+
+        // A generated message is kept
+        let generator = CommitMessageGenerator::new(MockProvider::new("feat: fast"));
+        let generated = generator
+            .generate("+change", "main", 1, None, &[], false)
+            .await;
+        let messages = or_fallback_message(generated, Some("chore: update")).unwrap();
+        assert_eq!(messages, vec!["feat: fast"]);
+
+        let args = Args::parse_from(["program", "--fallback-message", "chore: update"]);
+        assert_eq!(args.fallback_message.as_deref(), Some("chore: update"));
+        // A placeholder must never end up in the history
+        assert!(
+            Args::try_parse_from(["program", "--fallback-message", "chore: update", "--commit"])
+                .is_err()
+        );
+        let args = Args::parse_from([
+            "program",
+            "--fallback-message",
+            "chore: update",
+            "regen",
+            "--apply",
+        ]);
+        assert!(check_regen_conflicts(&args).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_until_cancelled_returns_finished_generation() {
+        let generator = CommitMessageGenerator::new(MockProvider::new("feat: fast"));
+
+        let generation = generator.generate("+change", "main", 1, None, &[], false);
+        let messages = until_cancelled(generation, std::future::pending()).await;
+
+        assert_eq!(messages.unwrap().unwrap(), vec!["feat: fast"]);
+    }
 }
@@ -0,0 +1,194 @@
+use serde_json::Value;
+
+/// Longest pull request description put in the prompt, in characters.
+/// Descriptions following a long template would otherwise crowd out the diff.
+const MAX_BODY_CHARS: usize = 2000;
+
+/// The pull (or merge) request a CI job is building, used as context for the
+/// commit message
+#[derive(Debug, Clone, PartialEq)]
+pub struct PullRequest {
+    pub number: Option<String>,
+    pub title: String,
+    pub body: Option<String>,
+}
+
+impl PullRequest {
+    /// Reads the pull request from the environment of the CI job, if any:
+    ///
+    /// - GitHub Actions: the event payload at `GITHUB_EVENT_PATH`
+    /// - GitLab CI: `CI_MERGE_REQUEST_IID`, `CI_MERGE_REQUEST_TITLE` and
+    ///   `CI_MERGE_REQUEST_DESCRIPTION`
+    /// - Anything else: `PR_TITLE` and `PR_BODY`, numbered by
+    ///   `GITHUB_PR_NUMBER` or `PR_NUMBER`
+    ///
+    /// Returns None, without complaint, when none of them hold a title.
+    pub fn from_env() -> Option<Self> {
+        Self::from_vars(|name| std::env::var(name).ok())
+    }
+
+    fn from_vars(var: impl Fn(&str) -> Option<String>) -> Option<Self> {
+        let var = |name: &str| var(name).filter(|value| !value.trim().is_empty());
+
+        let from_github = || {
+            let event = std::fs::read_to_string(var("GITHUB_EVENT_PATH")?).ok()?;
+            let event: Value = serde_json::from_str(&event).ok()?;
+            let pull_request = event.get("pull_request")?;
+            Some(Self {
+                number: pull_request["number"]
+                    .as_u64()
+                    .map(|number| number.to_string())
+                    .or_else(|| var("GITHUB_PR_NUMBER")),
+                title: pull_request["title"].as_str()?.to_string(),
+                body: pull_request["body"].as_str().map(str::to_string),
+            })
+        };
+        let from_gitlab = || {
+            Some(Self {
+                number: var("CI_MERGE_REQUEST_IID"),
+                title: var("CI_MERGE_REQUEST_TITLE")?,
+                body: var("CI_MERGE_REQUEST_DESCRIPTION"),
+            })
+        };
+        let from_generic = || {
+            Some(Self {
+                number: var("GITHUB_PR_NUMBER").or_else(|| var("PR_NUMBER")),
+                title: var("PR_TITLE")?,
+                body: var("PR_BODY"),
+            })
+        };
+
+        let mut pull_request = from_github()
+            .or_else(from_gitlab)
+            .or_else(from_generic)
+            .filter(|pull_request| !pull_request.title.trim().is_empty())?;
+        pull_request.title = pull_request.title.trim().to_string();
+        pull_request.body = pull_request
+            .body
+            .map(|body| body.trim().to_string())
+            .filter(|body| !body.is_empty());
+        Some(pull_request)
+    }
+
+    /// The pull request as a prompt section, with a long description cut short
+    pub fn context(&self) -> String {
+        let mut context = match &self.number {
+            Some(number) => format!("Pull request #{number}: {}\n", self.title),
+            None => format!("Pull request: {}\n", self.title),
+        };
+        if let Some(body) = &self.body {
+            let mut shown: String = body.chars().take(MAX_BODY_CHARS).collect();
+            if shown.len() < body.len() {
+                shown.push('…');
+            }
+            context.push_str(&format!("Pull request description:\n{shown}\n"));
+        }
+        context
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn from_vars(vars: &[(&str, &str)]) -> Option<PullRequest> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        PullRequest::from_vars(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_from_github_event() {
+        let dir = tempfile::tempdir().unwrap();
+        let event = dir.path().join("event.json");
+        std::fs::write(
+            &event,
+            r#"{"action": "opened", "pull_request": {
+                "number": 42,
+                "title": "Rework session handling",
+                "body": "Sessions now expire after an hour.\r\n"
+            }}"#,
+        )
+        .unwrap();
+        let event = event.to_str().unwrap();
+
+        assert_eq!(
+            from_vars(&[("CI", "true"), ("GITHUB_EVENT_PATH", event)]),
+            Some(PullRequest {
+                number: Some("42".to_string()),
+                title: "Rework session handling".to_string(),
+                body: Some("Sessions now expire after an hour.".to_string()),
+            })
+        );
+
+        // A push event has no pull request
+        let push = dir.path().join("push.json");
+        std::fs::write(&push, r#"{"ref": "refs/heads/main"}"#).unwrap();
+        assert_eq!(
+            from_vars(&[("GITHUB_EVENT_PATH", push.to_str().unwrap())]),
+            None
+        );
+        // Nor does a payload that can't be read
+        assert_eq!(from_vars(&[("GITHUB_EVENT_PATH", "/missing.json")]), None);
+    }
+
+    #[test]
+    fn test_from_gitlab_and_generic_vars() {
+        assert_eq!(
+            from_vars(&[
+                ("CI", "true"),
+                ("CI_MERGE_REQUEST_IID", "7"),
+                ("CI_MERGE_REQUEST_TITLE", "Add login"),
+                ("CI_MERGE_REQUEST_DESCRIPTION", ""),
+            ]),
+            Some(PullRequest {
+                number: Some("7".to_string()),
+                title: "Add login".to_string(),
+                body: None,
+            })
+        );
+
+        assert_eq!(
+            from_vars(&[
+                ("GITHUB_PR_NUMBER", "12"),
+                ("PR_TITLE", "Add login"),
+                ("PR_BODY", "Closes #3"),
+            ]),
+            Some(PullRequest {
+                number: Some("12".to_string()),
+                title: "Add login".to_string(),
+                body: Some("Closes #3".to_string()),
+            })
+        );
+
+        // A number alone says nothing about the change
+        assert_eq!(from_vars(&[("GITHUB_PR_NUMBER", "12")]), None);
+        assert_eq!(from_vars(&[("CI", "true"), ("PR_TITLE", " ")]), None);
+        assert_eq!(from_vars(&[]), None);
+    }
+
+    #[test]
+    fn test_context() {
+        let pull_request = PullRequest {
+            number: Some("42".to_string()),
+            title: "Rework session handling".to_string(),
+            body: Some("Sessions now expire.".to_string()),
+        };
+        assert_eq!(
+            pull_request.context(),
+            "Pull request #42: Rework session handling\nPull request description:\nSessions now expire.\n"
+        );
+
+        let pull_request = PullRequest {
+            number: None,
+            body: Some("é".repeat(MAX_BODY_CHARS + 1)),
+            ..pull_request
+        };
+        let context = pull_request.context();
+        assert!(context.starts_with("Pull request: Rework session handling\n"));
+        assert!(context.ends_with(&format!("\n{}…\n", "é".repeat(MAX_BODY_CHARS))));
+    }
+}
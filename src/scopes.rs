@@ -0,0 +1,223 @@
+use anyhow::{Context, Result, bail};
+use globset::{Glob, GlobBuilder, GlobMatcher};
+use std::path::Path;
+
+/// File at the repository root mapping path patterns to commit scopes
+pub const SCOPES_FILE: &str = ".rsgitmsg-scopes";
+
+/// Path patterns and the scope each one maps to, read from a
+/// CODEOWNERS-like file:
+///
+/// ```text
+/// # pattern      scope
+/// /web/          web
+/// src/api/**     api
+/// *.md           docs
+/// ```
+///
+/// Patterns follow CODEOWNERS: a leading `/` anchors the pattern to the
+/// repository root, a pattern without any other `/` matches at any depth,
+/// and a pattern naming a directory also matches everything under it. When
+/// several lines match a path, the last one wins.
+#[derive(Debug)]
+pub struct ScopeMap {
+    rules: Vec<(Vec<GlobMatcher>, String)>,
+}
+
+/// What the scope map says about a set of staged paths
+#[derive(Debug, PartialEq)]
+pub enum ScopeHint {
+    /// Every path maps to this scope
+    Preferred(String),
+    /// The paths map to several scopes, or only some of them map to one
+    Candidates(Vec<String>),
+}
+
+impl ScopeMap {
+    /// Loads the scope file from the repository root, if there is one
+    pub fn load(repo_root: &Path) -> Result<Option<Self>> {
+        let path = repo_root.join(SCOPES_FILE);
+        match std::fs::read_to_string(&path) {
+            Ok(contents) => Self::parse(&contents)
+                .map(Some)
+                .with_context(|| format!("Invalid scope file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    fn parse(contents: &str) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut fields = line.split_whitespace();
+            let (Some(pattern), Some(scope), None) = (fields.next(), fields.next(), fields.next())
+            else {
+                bail!(
+                    "line {}: expected `<pattern> <scope>`, got `{line}`",
+                    number + 1
+                );
+            };
+            let matchers = pattern_matchers(pattern)
+                .with_context(|| format!("line {}: bad pattern `{pattern}`", number + 1))?;
+            rules.push((matchers, scope.to_string()));
+        }
+
+        Ok(Self { rules })
+    }
+
+    /// The scope of the last rule matching the path
+    pub fn scope_for(&self, path: &str) -> Option<&str> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(matchers, _)| matchers.iter().any(|matcher| matcher.is_match(path)))
+            .map(|(_, scope)| scope.as_str())
+    }
+
+    /// Resolves the scopes of the staged paths, in order of first appearance
+    pub fn resolve(&self, paths: &[String]) -> Option<ScopeHint> {
+        let mut scopes: Vec<String> = Vec::new();
+        let mut all_matched = true;
+        for path in paths {
+            match self.scope_for(path) {
+                Some(scope) if !scopes.iter().any(|existing| existing == scope) => {
+                    scopes.push(scope.to_string());
+                }
+                Some(_) => {}
+                None => all_matched = false,
+            }
+        }
+
+        match scopes.len() {
+            0 => None,
+            1 if all_matched => scopes.pop().map(ScopeHint::Preferred),
+            _ => Some(ScopeHint::Candidates(scopes)),
+        }
+    }
+}
+
+/// Matchers for a CODEOWNERS-style pattern: the path itself, and anything
+/// under it in case it names a directory
+fn pattern_matchers(pattern: &str) -> Result<Vec<GlobMatcher>> {
+    let anchored = pattern.starts_with('/') || pattern.trim_end_matches('/').contains('/');
+    let pattern = pattern.trim_matches('/');
+    if pattern.is_empty() {
+        bail!("pattern is empty");
+    }
+
+    let pattern = if anchored {
+        pattern.to_string()
+    } else {
+        format!("**/{pattern}")
+    };
+
+    [pattern.clone(), format!("{pattern}/**")]
+        .iter()
+        .map(|pattern| {
+            Ok(GlobBuilder::new(pattern)
+                .literal_separator(true)
+                .build()
+                .map(|glob: Glob| glob.compile_matcher())?)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCOPES: &str = "\
+# Paths owned by each area
+/web/          web
+src/api/**     api
+*.md           docs
+docs           docs
+src/api/*.md   api
+";
+
+    fn paths(paths: &[&str]) -> Vec<String> {
+        paths.iter().map(|path| path.to_string()).collect()
+    }
+
+    #[test]
+    fn test_scope_for_path() {
+        let map = ScopeMap::parse(SCOPES).unwrap();
+
+        assert_eq!(map.scope_for("web/index.html"), Some("web"));
+        assert_eq!(map.scope_for("web/src/app.ts"), Some("web"));
+        assert_eq!(map.scope_for("src/api/routes/users.rs"), Some("api"));
+        // Unanchored patterns match at any depth
+        assert_eq!(map.scope_for("README.md"), Some("docs"));
+        assert_eq!(map.scope_for("src/lib/NOTES.md"), Some("docs"));
+        assert_eq!(map.scope_for("guide/docs/intro.txt"), Some("docs"));
+        // Anchored patterns don't
+        assert_eq!(map.scope_for("packages/web/index.html"), None);
+        // The last matching line wins
+        assert_eq!(map.scope_for("src/api/README.md"), Some("api"));
+        assert_eq!(map.scope_for("src/main.rs"), None);
+    }
+
+    #[test]
+    fn test_resolve_single_scope() {
+        let map = ScopeMap::parse(SCOPES).unwrap();
+
+        assert_eq!(
+            map.resolve(&paths(&["src/api/users.rs", "src/api/README.md"])),
+            Some(ScopeHint::Preferred("api".to_string()))
+        );
+        assert_eq!(map.resolve(&paths(&["src/main.rs"])), None);
+        assert_eq!(map.resolve(&[]), None);
+    }
+
+    #[test]
+    fn test_resolve_multiple_scopes() {
+        let map = ScopeMap::parse(SCOPES).unwrap();
+
+        assert_eq!(
+            map.resolve(&paths(&[
+                "web/app.ts",
+                "src/api/users.rs",
+                "web/index.html",
+                "CHANGELOG.md",
+            ])),
+            Some(ScopeHint::Candidates(vec![
+                "web".to_string(),
+                "api".to_string(),
+                "docs".to_string(),
+            ]))
+        );
+
+        // A scope only some of the files map to is a candidate, not a given
+        assert_eq!(
+            map.resolve(&paths(&["web/app.ts", "src/main.rs"])),
+            Some(ScopeHint::Candidates(vec!["web".to_string()]))
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = ScopeMap::parse("/web/\n").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "line 1: expected `<pattern> <scope>`, got `/web/`"
+        );
+
+        let err = ScopeMap::parse("# header\nsrc/[api api\n").unwrap_err();
+        assert_eq!(err.to_string(), "line 2: bad pattern `src/[api`");
+    }
+
+    #[test]
+    fn test_load_from_repo_root() {
+        let dir = tempfile::TempDir::new().unwrap();
+        assert!(ScopeMap::load(dir.path()).unwrap().is_none());
+
+        std::fs::write(dir.path().join(SCOPES_FILE), "cli/ cli\n").unwrap();
+        let map = ScopeMap::load(dir.path()).unwrap().unwrap();
+        assert_eq!(map.scope_for("cli/main.rs"), Some("cli"));
+    }
+}